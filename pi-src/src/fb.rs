@@ -0,0 +1,357 @@
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::io::AsRawFd;
+
+use crate::frame::{self, Frame};
+
+const FBIOGET_FSCREENINFO: libc::c_ulong = 0x4602;
+const FBIO_WAITFORVSYNC: libc::c_ulong = 0x4004_4620;
+
+/// `struct fb_fix_screeninfo` from `linux/fb.h`.
+#[repr(C)]
+#[derive(Default)]
+struct FixScreenInfo {
+    id: [u8; 16],
+    smem_start: libc::c_ulong,
+    smem_len: u32,
+    kind: u32,
+    type_aux: u32,
+    visual: u32,
+    xpanstep: u16,
+    ypanstep: u16,
+    ywrapstep: u16,
+    line_length: u32,
+    mmio_start: libc::c_ulong,
+    mmio_len: u32,
+    accel: u32,
+    capabilities: u16,
+    reserved: [u16; 2],
+}
+
+/// Layout of a framebuffer pixel in memory.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PixelFormat {
+    Rgb565,
+    /// Three bytes, in the order blue, green, red.
+    Bgr888,
+    /// Four bytes, blue first, with the last unused.
+    Xrgb8888,
+}
+
+impl PixelFormat {
+    pub fn from_bits_per_pixel(bits: u32) -> Option<PixelFormat> {
+        match bits {
+            16 => Some(PixelFormat::Rgb565),
+            24 => Some(PixelFormat::Bgr888),
+            32 => Some(PixelFormat::Xrgb8888),
+            _ => None,
+        }
+    }
+
+    pub fn bytes_per_pixel(&self) -> usize {
+        match self {
+            PixelFormat::Rgb565 => 2,
+            PixelFormat::Bgr888 => 3,
+            PixelFormat::Xrgb8888 => 4,
+        }
+    }
+
+    /// Writes one pixel into `out`, which is `bytes_per_pixel` long.
+    fn encode(&self, rgb: [f32; 3], out: &mut [u8]) {
+        let (r, g, b) = (frame::to_u8(rgb[0]), frame::to_u8(rgb[1]), frame::to_u8(rgb[2]));
+        match self {
+            PixelFormat::Rgb565 => {
+                let value = ((r as u16 >> 3) << 11) | ((g as u16 >> 2) << 5) | (b as u16 >> 3);
+                out.copy_from_slice(&value.to_le_bytes());
+            }
+            PixelFormat::Bgr888 => out.copy_from_slice(&[b, g, r]),
+            PixelFormat::Xrgb8888 => out.copy_from_slice(&[b, g, r, 0]),
+        }
+    }
+}
+
+/// Shape of the framebuffer's memory.
+#[derive(Clone, Copy, Debug)]
+pub struct Geometry {
+    pub width: usize,
+    pub height: usize,
+    /// Bytes from the start of one line to the next.
+    pub stride: usize,
+    pub format: PixelFormat,
+    /// Screens of memory, stacked vertically. Two allows double buffering.
+    pub buffers: usize,
+}
+
+impl Geometry {
+    fn buffer_len(&self) -> usize {
+        self.stride * self.height
+    }
+}
+
+/**
+ * Double-buffered output to a Linux framebuffer. Pictures are drawn into the
+ * hidden buffer, which `present` pans to on the next vertical blank.
+ *
+ * A regular file can stand in for the device, e.g. one in `/dev/shm`. Its
+ * geometry comes from `FB_GEOMETRY` (`WxHxBPP`, default `640x480x32`) and
+ * `FB_STRIDE`, and it holds both buffers one after the other.
+ */
+pub struct Output {
+    pub geometry: Geometry,
+    /// None when writing to a file instead of a device.
+    device: Option<linuxfb::Framebuffer>,
+    file: File,
+    memory: *mut u8,
+    memory_len: usize,
+    /// Buffer being drawn into.
+    back: usize,
+    vsync: bool,
+}
+
+impl Output {
+    pub fn open(path: &str) -> io::Result<Output> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let (device, geometry) = if file.metadata()?.file_type().is_char_device() {
+            let (device, geometry) = open_device(path, &file)?;
+            (Some(device), geometry)
+        } else {
+            let geometry = fake_geometry();
+            file.set_len((geometry.buffer_len() * geometry.buffers) as u64)?;
+            (None, geometry)
+        };
+
+        let memory_len = geometry.buffer_len() * geometry.buffers;
+        let memory = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                memory_len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if memory == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut output = Output {
+            geometry,
+            device,
+            file,
+            memory: memory as *mut u8,
+            memory_len,
+            back: 0,
+            vsync: true,
+        };
+        // Show the first buffer and draw into the other.
+        output.pan(0)?;
+        output.back = if geometry.buffers > 1 { 1 } else { 0 };
+        Ok(output)
+    }
+
+    /// Draws a picture into the hidden buffer, cropped to the screen and
+    /// padded with black.
+    pub fn draw(&mut self, frame: &Frame) {
+        let geometry = self.geometry;
+        let bytes = geometry.format.bytes_per_pixel();
+        let buffer_len = geometry.buffer_len();
+        let memory = unsafe { std::slice::from_raw_parts_mut(self.memory, self.memory_len) };
+        let buffer = &mut memory[self.back * buffer_len..(self.back + 1) * buffer_len];
+
+        for (y, line) in buffer.chunks_exact_mut(geometry.stride).enumerate() {
+            for (x, pixel) in line[..geometry.width * bytes].chunks_exact_mut(bytes).enumerate() {
+                let rgb = if x < frame.width && y < frame.height { frame.get(x, y) } else { [0.; 3] };
+                geometry.format.encode(rgb, pixel);
+            }
+        }
+    }
+
+    /// Waits for the vertical blank and then shows the buffer that was just
+    /// drawn, so the switch doesn't tear and the next `draw` goes to the
+    /// buffer no longer displayed. With a single buffer this only waits for
+    /// the vertical blank.
+    pub fn present(&mut self) -> io::Result<()> {
+        self.wait_for_vsync();
+        if self.geometry.buffers > 1 {
+            let shown = self.back;
+            self.pan(shown)?;
+            self.back = 1 - shown;
+        }
+        Ok(())
+    }
+
+    fn pan(&mut self, buffer: usize) -> io::Result<()> {
+        let y = (buffer * self.geometry.height) as u32;
+        match &mut self.device {
+            Some(device) => device.set_offset(0, y)
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{:?}", e))),
+            None => Ok(()),
+        }
+    }
+
+    /// Blocks until the next vertical blank. Drivers without support for it,
+    /// and files, are only asked once.
+    fn wait_for_vsync(&mut self) {
+        if !self.vsync {
+            return;
+        }
+        let screen: u32 = 0;
+        let result = unsafe { libc::ioctl(self.file.as_raw_fd(), FBIO_WAITFORVSYNC, &screen) };
+        if result != 0 {
+            eprintln!("framebuffer can't wait for vsync: {}", io::Error::last_os_error());
+            self.vsync = false;
+        }
+    }
+}
+
+impl Drop for Output {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.memory as *mut libc::c_void, self.memory_len);
+        }
+    }
+}
+
+/// Opens a framebuffer device, asking for a virtual screen twice its height
+/// to pan between. Falls back to a single buffer if the driver refuses.
+fn open_device(path: &str, file: &File) -> io::Result<(linuxfb::Framebuffer, Geometry)> {
+    let to_io = |e: linuxfb::Error| io::Error::new(io::ErrorKind::Other, format!("{:?}", e));
+    let mut device = linuxfb::Framebuffer::new(path).map_err(to_io)?;
+
+    let bits = device.get_bytes_per_pixel() * 8;
+    let format = PixelFormat::from_bits_per_pixel(bits)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::Other, format!("unsupported {} bits per pixel", bits)))?;
+    let (width, height) = device.get_size();
+
+    let buffers = if device.set_virtual_size(width, height * 2).is_ok() && device.get_virtual_size().1 >= height * 2 {
+        2
+    } else {
+        eprintln!("framebuffer can't pan, drawing to the visible buffer");
+        1
+    };
+
+    // The line length can change with the virtual size, so read it after.
+    let mut info = FixScreenInfo::default();
+    if unsafe { libc::ioctl(file.as_raw_fd(), FBIOGET_FSCREENINFO, &mut info) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let geometry = Geometry {
+        width: width as usize,
+        height: height as usize,
+        stride: info.line_length as usize,
+        format,
+        buffers,
+    };
+    if geometry.buffer_len() * buffers > info.smem_len as usize {
+        return Err(io::Error::new(io::ErrorKind::Other, "framebuffer memory smaller than its screens"));
+    }
+    Ok((device, geometry))
+}
+
+/// Geometry of a file standing in for a framebuffer device.
+fn fake_geometry() -> Geometry {
+    let value = std::env::var("FB_GEOMETRY").unwrap_or("640x480x32".to_string());
+    let parts = value.split('x')
+        .map(|x| x.parse::<usize>().ok())
+        .collect::<Option<Vec<_>>>()
+        .filter(|x| x.len() == 3)
+        .unwrap_or_else(|| panic!("invalid FB_GEOMETRY {:?}", value));
+    let (width, height) = (parts[0], parts[1]);
+    let format = PixelFormat::from_bits_per_pixel(parts[2] as u32)
+        .unwrap_or_else(|| panic!("unsupported {} bits per pixel", parts[2]));
+
+    let stride = std::env::var("FB_STRIDE").ok()
+        .map(|x| x.parse::<usize>().unwrap())
+        .unwrap_or(width * format.bytes_per_pixel());
+    assert!(stride >= width * format.bytes_per_pixel(), "FB_STRIDE shorter than a line");
+    Geometry {
+        width,
+        height,
+        stride,
+        format,
+        buffers: 2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PADDING: usize = 3;
+    const FILL: u8 = 0xaa;
+
+    /// Red, half green and full blue, white and black.
+    fn frame() -> Frame {
+        let mut frame = Frame::new(2, 2);
+        frame.set(0, 0, [1., 0., 0.]);
+        frame.set(1, 0, [0., 0.5, 1.]);
+        frame.set(0, 1, [1., 1., 1.]);
+        frame.set(1, 1, [0., 0., 0.]);
+        frame
+    }
+
+    /// Encodings of the pixels in `frame`, the last of which is black.
+    type Pixels = [&'static [u8]; 4];
+
+    /// The bytes a 3x2 screen should hold after drawing `frame`, which is a
+    /// pixel narrower so the last column is black.
+    fn expected(pixels: Pixels) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for y in 0..2 {
+            bytes.extend_from_slice(pixels[y * 2]);
+            bytes.extend_from_slice(pixels[y * 2 + 1]);
+            bytes.extend_from_slice(pixels[3]);
+            bytes.extend_from_slice(&[FILL; PADDING]);
+        }
+        bytes
+    }
+
+    #[test]
+    fn draws_into_file() {
+        let formats: [(u32, Pixels); 3] = [
+            (16, [&[0x00, 0xf8], &[0xff, 0x03], &[0xff, 0xff], &[0, 0]]),
+            (24, [&[0, 0, 255], &[255, 127, 0], &[255, 255, 255], &[0, 0, 0]]),
+            (32, [&[0, 0, 255, 0], &[255, 127, 0, 0], &[255, 255, 255, 0], &[0, 0, 0, 0]]),
+        ];
+        // Environment variables are shared between threads, so every format
+        // is checked in this one test.
+        for &(bits, pixels) in formats.iter() {
+            let bytes = bits as usize / 8;
+            let stride = 3 * bytes + PADDING;
+            let buffer_len = stride * 2;
+            let path = std::env::temp_dir().join(format!("fb-test-{}-{}", std::process::id(), bits));
+            std::fs::write(&path, vec![FILL; buffer_len * 2]).unwrap();
+            std::env::set_var("FB_GEOMETRY", format!("3x2x{}", bits));
+            std::env::set_var("FB_STRIDE", stride.to_string());
+
+            let mut output = Output::open(path.to_str().unwrap()).unwrap();
+            assert_eq!(output.geometry.format, PixelFormat::from_bits_per_pixel(bits).unwrap());
+            assert_eq!(output.geometry.stride, stride);
+
+            // The second buffer is drawn first, leaving the shown one alone.
+            output.draw(&frame());
+            let contents = std::fs::read(&path).unwrap();
+            assert_eq!(contents.len(), buffer_len * 2);
+            assert_eq!(&contents[..buffer_len], &vec![FILL; buffer_len][..]);
+            assert_eq!(&contents[buffer_len..], &expected(pixels)[..]);
+
+            // A file can't pan or wait for vsync, which only turns vsync off.
+            output.present().unwrap();
+            assert!(!output.vsync);
+            assert_eq!(output.back, 0);
+            output.draw(&frame());
+            output.present().unwrap();
+            let contents = std::fs::read(&path).unwrap();
+            assert_eq!(&contents[..buffer_len], &expected(pixels)[..]);
+            assert_eq!(output.back, 1);
+
+            drop(output);
+            std::fs::remove_file(&path).unwrap();
+        }
+        std::env::remove_var("FB_GEOMETRY");
+        std::env::remove_var("FB_STRIDE");
+    }
+}
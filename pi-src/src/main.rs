@@ -1,5 +1,21 @@
+#[allow(dead_code)]
+#[path = "../../src/crt.rs"]
+mod crt;
+mod fb;
+#[path = "../../src/frame.rs"]
+mod frame;
+#[allow(dead_code)]
+#[path = "../../src/overscan.rs"]
+mod overscan;
+#[path = "../../src/resample.rs"]
+mod resample;
+#[path = "../../src/scale.rs"]
+mod scale;
+
 use crossbeam::channel::bounded;
 use itertools::Itertools;
+use frame::Frame;
+use scale::Scaler;
 
 extern "C" {
     fn fn_setup(nsamp: u32) -> u32;
@@ -9,34 +25,15 @@ extern "C" {
 const WIN_LENGTH: usize = 16;
 
 fn main() {
-    // Instead of hardcoding the path, you could also use `Framebuffer::list()`
-    // to find paths to available devices.
-    let fb = linuxfb::Framebuffer::new("/dev/fb0").unwrap();
-
-    println!("Size in pixels: {:?}", fb.get_size());
-
-    println!("Bytes per pixel: {:?}", fb.get_bytes_per_pixel());
-
-    println!("Physical size in mm: {:?}", fb.get_physical_size());
+    // A regular file can stand in for the device, see `fb::Output`.
+    let fb_path = std::env::var("FB").unwrap_or("/dev/fb0".to_string());
+    let mut output = fb::Output::open(&fb_path).unwrap();
 
-    // Map the framebuffer into memory, so we can write to it:
-    let mut data = fb.map().unwrap();
+    println!("Framebuffer: {:?}", output.geometry);
 
-    // Make everything black:
-    for i in 0..data.len() {
-        data[i] = 0;
-    }
-
-    // Make everything red:
-    for i in 0..data.len() {
-        if i % 4 == 0 {
-            data[i] = 0x00;
-        } else if i % 4 == 2 {
-            data[i] = 0x94;
-        } else {
-            data[i] = 0x00;
-        }
-    }
+    // Start from black.
+    output.draw(&Frame::new(0, 0));
+    output.present().unwrap();
 
     unsafe {
 
@@ -65,13 +62,11 @@ fn main() {
             }
         });
 
-        let size = fb.get_size();
-
-        let (prefix, pixels, suffix) = unsafe { data.align_to_mut::<u32>() };
-        assert_eq!(prefix.len(), 0);
-        assert_eq!(suffix.len(), 0);
+        let size = (output.geometry.width, output.geometry.height);
 
         let mut framec = 0;
+        // Picture area, picked from the first frame so the crop stays put.
+        let mut area = None;
 
         while let Ok(raw_frame) = rx.recv() {
             framec += 1;
@@ -85,31 +80,38 @@ fn main() {
             // let mut file = File::create("out.csv").unwrap();
 
             // Smooth it!
-            let mut x = 0;
-            let mut y = 0;
-            for value in raw_frame.iter()
+            let values = raw_frame.iter()
                 // Convert raw values into voltages
                 .map(|sample| (2080 - ((*sample as i32 >> 4))))
                 // Sample windows of WIN_LENGTH
                 .chunks(WIN_LENGTH)
                 .into_iter()
                 // Calculate sum, then average
-                .map(|value| value.sum::<i32>()) {
+                .map(|value| value.sum::<i32>())
+                .collect::<Vec<_>>();
 
+            // We roll over at pixel PIXEL_LEN
+            let width = PIXEL_LEN + 1;
+            let mut image = Frame::new(width, values.len() / width);
+            for (i, value) in values.iter().take(image.pixels.len()).enumerate() {
                 // Average this window into a color value.
-                let color: u32 = volt_to_color(value  as f32);
-
-                // Draw the pixel.
-                draw_pixel(pixels, x, y, color, size);
+                let lum = (volt_to_color(*value as f32) & 0xFF) as f32 / 255.;
+                image.pixels[i] = [lum, lum, lum];
+            }
 
-                // We roll over at pixel PIXEL_LEN
-                if x < PIXEL_LEN {
-                    x += 1;
-                } else {
-                    x = 0;
-                    y += 1;
-                }
+            // Scale to the whole screen.
+            let mut scaler = Scaler::new(size.0, size.1);
+            scaler.apply_env();
+            let area = *area.get_or_insert_with(|| overscan::area_from_env(&[image.clone()]).unwrap());
+            let image = area.apply(&image);
+            let display_aspect = area.display_aspect(4. / 3.);
+            let mut scaled = scaler.scale(&image, display_aspect);
+            if let Some(crt) = crt::Crt::from_env() {
+                let (_, top, _, height) = scaler.placement(image.height, display_aspect);
+                scaled = crt.apply(&scaled, top, height, image.height);
             }
+            output.draw(&scaled);
+            output.present().unwrap();
 
             std::fs::write("./frame-out", &std::slice::from_raw_parts(raw_frame.as_ptr() as *const u8, raw_frame.len() * 2));
 
@@ -130,13 +132,3 @@ fn volt_to_color(value: f32) -> u32 {
     }
     return (lum << 16) + (lum << 8) + lum;
 }
-
-fn draw_pixel(pixels: &mut [u32], x: usize, y: usize, color: u32, size: (u32, u32)) {
-    let XMUL = 6;
-    let YMUL = 3;
-    for nx in x*XMUL..(x+1)*XMUL {
-        for ny in y*YMUL..(y+1)*YMUL {
-            pixels[ ((12 + ny) * (size.0 as usize)) + (60 + nx)] = color;
-        }
-    }
-}
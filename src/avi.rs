@@ -0,0 +1,499 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use crate::frame::{Frame, rgb_to_ycbcr, to_u8};
+
+/// Uncompressed pixel layouts that editors accept without a codec.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PixelFormat {
+    /// Bottom-up BGR rows, padded to four bytes.
+    Rgb24,
+    /// Packed 4:2:2 Y'CbCr, `U Y0 V Y1`.
+    Uyvy,
+}
+
+impl PixelFormat {
+    fn bits(&self) -> u16 {
+        match self {
+            PixelFormat::Rgb24 => 24,
+            PixelFormat::Uyvy => 16,
+        }
+    }
+
+    fn fourcc(&self) -> [u8; 4] {
+        match self {
+            PixelFormat::Rgb24 => [0; 4],
+            PixelFormat::Uyvy => *b"UYVY",
+        }
+    }
+}
+
+/// 16-bit signed PCM audio.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AudioFormat {
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+impl AudioFormat {
+    fn block_align(&self) -> u32 {
+        self.channels as u32 * 2
+    }
+}
+
+/// Largest file written. Sizes in AVI 1.0 are 32 bits, and many readers
+/// take them to be signed or stop at 1GB, which is as far as they go
+/// without OpenDML.
+pub const MAX_FILE_BYTES: u64 = 1 << 30;
+
+/// Bytes each chunk adds to the `idx1` index.
+const INDEX_ENTRY_BYTES: u64 = 16;
+
+struct IndexEntry {
+    id: [u8; 4],
+    offset: u32,
+    size: u32,
+}
+
+/**
+ * Writes an uncompressed AVI with one video stream and an optional PCM audio
+ * stream. Sizes and counts are patched into the header by `finish`, so the
+ * output needs to be seekable. There's no OpenDML support, so files are
+ * limited to `MAX_FILE_BYTES`: writing a chunk that would go past it fails,
+ * leaving what was written before to be finished.
+ */
+pub struct AviWriter<W: Write + Seek> {
+    out: W,
+    width: usize,
+    height: usize,
+    rate: (u32, u32),
+    format: PixelFormat,
+    audio: Option<AudioFormat>,
+    /// SMPTE timecode of the first frame.
+    timecode: Option<String>,
+    frames: u32,
+    audio_blocks: u32,
+    movi_start: u64,
+    index: Vec<IndexEntry>,
+}
+
+impl<W: Write + Seek> AviWriter<W> {
+    /// `rate` is in frames per second. `timecode` is the first frame's,
+    /// stored in the INFO list as `ISMP`.
+    pub fn new(
+        out: W,
+        width: usize,
+        height: usize,
+        rate: (u32, u32),
+        format: PixelFormat,
+        audio: Option<AudioFormat>,
+        timecode: Option<String>,
+    ) -> io::Result<AviWriter<W>> {
+        if format == PixelFormat::Uyvy {
+            assert!(width % 2 == 0, "UYVY needs an even width");
+        }
+        let mut writer = AviWriter {
+            out,
+            width,
+            height,
+            rate,
+            format,
+            audio,
+            timecode,
+            frames: 0,
+            audio_blocks: 0,
+            movi_start: 0,
+            index: vec![],
+        };
+        let header = writer.header(0);
+        writer.out.write_all(&header)?;
+        writer.out.write_all(b"LIST")?;
+        writer.out.write_u32::<LittleEndian>(0)?;
+        writer.movi_start = writer.out.stream_position()?;
+        writer.out.write_all(b"movi")?;
+        Ok(writer)
+    }
+
+    fn stride(&self) -> usize {
+        match self.format {
+            PixelFormat::Rgb24 => (self.width * 3 + 3) & !3,
+            PixelFormat::Uyvy => self.width * 2,
+        }
+    }
+
+    fn frame_size(&self) -> u32 {
+        (self.stride() * self.height) as u32
+    }
+
+    /// Builds everything up to the `movi` list. Its length doesn't depend on
+    /// the counts, so it can be rewritten in place.
+    fn header(&self, riff_size: u32) -> Vec<u8> {
+        let mut hdrl = vec![];
+        let usec_per_frame = (1_000_000u64 * self.rate.1 as u64 / self.rate.0 as u64) as u32;
+        let streams = if self.audio.is_some() { 2 } else { 1 };
+
+        // Main AVI header.
+        let mut avih = vec![];
+        avih.write_u32::<LittleEndian>(usec_per_frame).unwrap();
+        let bytes_per_sec = self.frame_size() as u64 * self.rate.0 as u64 / self.rate.1 as u64;
+        avih.write_u32::<LittleEndian>(bytes_per_sec as u32).unwrap();
+        avih.write_u32::<LittleEndian>(0).unwrap(); // padding granularity
+        avih.write_u32::<LittleEndian>(0x10).unwrap(); // AVIF_HASINDEX
+        avih.write_u32::<LittleEndian>(self.frames).unwrap();
+        avih.write_u32::<LittleEndian>(0).unwrap(); // initial frames
+        avih.write_u32::<LittleEndian>(streams).unwrap();
+        avih.write_u32::<LittleEndian>(self.frame_size()).unwrap();
+        avih.write_u32::<LittleEndian>(self.width as u32).unwrap();
+        avih.write_u32::<LittleEndian>(self.height as u32).unwrap();
+        avih.extend(&[0; 16]);
+        write_chunk(&mut hdrl, b"avih", &avih).unwrap();
+
+        // Video stream.
+        let mut strh = vec![];
+        strh.extend(b"vids");
+        strh.extend(&self.format.fourcc());
+        strh.write_u32::<LittleEndian>(0).unwrap(); // flags
+        strh.write_u32::<LittleEndian>(0).unwrap(); // priority and language
+        strh.write_u32::<LittleEndian>(0).unwrap(); // initial frames
+        strh.write_u32::<LittleEndian>(self.rate.1).unwrap();
+        strh.write_u32::<LittleEndian>(self.rate.0).unwrap();
+        strh.write_u32::<LittleEndian>(0).unwrap(); // start
+        strh.write_u32::<LittleEndian>(self.frames).unwrap();
+        strh.write_u32::<LittleEndian>(self.frame_size()).unwrap();
+        strh.write_i32::<LittleEndian>(-1).unwrap(); // quality
+        strh.write_u32::<LittleEndian>(0).unwrap(); // sample size
+        strh.write_u16::<LittleEndian>(0).unwrap();
+        strh.write_u16::<LittleEndian>(0).unwrap();
+        strh.write_u16::<LittleEndian>(self.width as u16).unwrap();
+        strh.write_u16::<LittleEndian>(self.height as u16).unwrap();
+
+        let mut strf = vec![];
+        strf.write_u32::<LittleEndian>(40).unwrap();
+        strf.write_i32::<LittleEndian>(self.width as i32).unwrap();
+        strf.write_i32::<LittleEndian>(self.height as i32).unwrap();
+        strf.write_u16::<LittleEndian>(1).unwrap(); // planes
+        strf.write_u16::<LittleEndian>(self.format.bits()).unwrap();
+        strf.extend(&self.format.fourcc());
+        strf.write_u32::<LittleEndian>(self.frame_size()).unwrap();
+        strf.extend(&[0; 16]);
+
+        let mut strl = vec![];
+        write_chunk(&mut strl, b"strh", &strh).unwrap();
+        write_chunk(&mut strl, b"strf", &strf).unwrap();
+        write_list(&mut hdrl, b"strl", &strl);
+
+        // Audio stream.
+        if let Some(audio) = self.audio {
+            let mut strh = vec![];
+            strh.extend(b"auds");
+            strh.extend(&[0; 4]);
+            strh.write_u32::<LittleEndian>(0).unwrap(); // flags
+            strh.write_u32::<LittleEndian>(0).unwrap(); // priority and language
+            strh.write_u32::<LittleEndian>(0).unwrap(); // initial frames
+            strh.write_u32::<LittleEndian>(audio.block_align()).unwrap();
+            strh.write_u32::<LittleEndian>(audio.sample_rate * audio.block_align()).unwrap();
+            strh.write_u32::<LittleEndian>(0).unwrap(); // start
+            strh.write_u32::<LittleEndian>(self.audio_blocks).unwrap();
+            strh.write_u32::<LittleEndian>(audio.sample_rate * audio.block_align()).unwrap();
+            strh.write_i32::<LittleEndian>(-1).unwrap(); // quality
+            strh.write_u32::<LittleEndian>(audio.block_align()).unwrap();
+            strh.extend(&[0; 8]);
+
+            let mut strf = vec![];
+            strf.write_u16::<LittleEndian>(1).unwrap(); // WAVE_FORMAT_PCM
+            strf.write_u16::<LittleEndian>(audio.channels).unwrap();
+            strf.write_u32::<LittleEndian>(audio.sample_rate).unwrap();
+            strf.write_u32::<LittleEndian>(audio.sample_rate * audio.block_align()).unwrap();
+            strf.write_u16::<LittleEndian>(audio.block_align() as u16).unwrap();
+            strf.write_u16::<LittleEndian>(16).unwrap();
+
+            let mut strl = vec![];
+            write_chunk(&mut strl, b"strh", &strh).unwrap();
+            write_chunk(&mut strl, b"strf", &strf).unwrap();
+            write_list(&mut hdrl, b"strl", &strl);
+        }
+
+        let mut header = vec![];
+        header.extend(b"RIFF");
+        header.write_u32::<LittleEndian>(riff_size).unwrap();
+        header.extend(b"AVI ");
+        write_list(&mut header, b"hdrl", &hdrl);
+        if let Some(timecode) = &self.timecode {
+            let mut info = vec![];
+            let mut value = timecode.as_bytes().to_vec();
+            value.push(0);
+            write_chunk(&mut info, b"ISMP", &value).unwrap();
+            write_list(&mut header, b"INFO", &info);
+        }
+        header
+    }
+
+    fn write_data(&mut self, id: [u8; 4], data: &[u8]) -> io::Result<()> {
+        let pos = self.out.stream_position()?;
+        // The chunk, and the index with its entry added.
+        let end = pos + 8 + data.len() as u64 + data.len() as u64 % 2 + 8 + (self.index.len() as u64 + 1) * INDEX_ENTRY_BYTES;
+        if end > MAX_FILE_BYTES {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!(
+                "AVI would be larger than {}MB, the most without OpenDML", MAX_FILE_BYTES >> 20,
+            )));
+        }
+        self.index.push(IndexEntry {
+            id,
+            offset: (pos - self.movi_start) as u32,
+            size: data.len() as u32,
+        });
+        write_chunk(&mut self.out, &id, data)
+    }
+
+    pub fn write_frame(&mut self, frame: &Frame) -> io::Result<()> {
+        assert_eq!((frame.width, frame.height), (self.width, self.height),
+            "frame size changed mid-stream");
+
+        let stride = self.stride();
+        let mut data = vec![0; stride * self.height];
+        match self.format {
+            PixelFormat::Rgb24 => {
+                for y in 0..self.height {
+                    let row = &mut data[(self.height - 1 - y) * stride..];
+                    for (x, p) in frame.row(y).iter().enumerate() {
+                        row[x * 3] = to_u8(p[2]);
+                        row[x * 3 + 1] = to_u8(p[1]);
+                        row[x * 3 + 2] = to_u8(p[0]);
+                    }
+                }
+            }
+            PixelFormat::Uyvy => {
+                for y in 0..self.height {
+                    let row = &mut data[y * stride..];
+                    for (x, pair) in frame.row(y).chunks_exact(2).enumerate() {
+                        let a = rgb_to_ycbcr(pair[0]);
+                        let b = rgb_to_ycbcr(pair[1]);
+                        row[x * 4] = ((a[1] as u16 + b[1] as u16) / 2) as u8;
+                        row[x * 4 + 1] = a[0];
+                        row[x * 4 + 2] = ((a[2] as u16 + b[2] as u16) / 2) as u8;
+                        row[x * 4 + 3] = b[0];
+                    }
+                }
+            }
+        }
+
+        self.write_data(*b"00db", &data)?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Writes interleaved samples for all channels.
+    pub fn write_audio(&mut self, samples: &[i16]) -> io::Result<()> {
+        let audio = self.audio.expect("stream has no audio track");
+        let mut data = Vec::with_capacity(samples.len() * 2);
+        for sample in samples {
+            data.write_i16::<LittleEndian>(*sample)?;
+        }
+        self.write_data(*b"01wb", &data)?;
+        self.audio_blocks += samples.len() as u32 / audio.channels as u32;
+        Ok(())
+    }
+
+    /// Writes the index and patches the header.
+    pub fn finish(mut self) -> io::Result<W> {
+        let movi_end = self.out.stream_position()?;
+
+        let mut idx1 = vec![];
+        for entry in &self.index {
+            idx1.extend(&entry.id);
+            idx1.write_u32::<LittleEndian>(0x10)?; // AVIIF_KEYFRAME
+            idx1.write_u32::<LittleEndian>(entry.offset)?;
+            idx1.write_u32::<LittleEndian>(entry.size)?;
+        }
+        write_chunk(&mut self.out, b"idx1", &idx1)?;
+        let end = self.out.stream_position()?;
+
+        let header = self.header((end - 8) as u32);
+        self.out.seek(SeekFrom::Start(0))?;
+        self.out.write_all(&header)?;
+        self.out.seek(SeekFrom::Start(self.movi_start - 4))?;
+        self.out.write_u32::<LittleEndian>((movi_end - self.movi_start) as u32)?;
+        self.out.seek(SeekFrom::Start(end))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+fn write_chunk<W: Write>(out: &mut W, id: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(id)?;
+    out.write_u32::<LittleEndian>(data.len() as u32)?;
+    out.write_all(data)?;
+    if data.len() % 2 == 1 {
+        out.write_all(&[0])?;
+    }
+    Ok(())
+}
+
+fn write_list(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend(b"LIST");
+    out.write_u32::<LittleEndian>(data.len() as u32 + 4).unwrap();
+    out.extend(kind);
+    out.extend(data);
+}
+
+/// Reads a 16-bit PCM WAV file, returning its format and interleaved samples.
+pub fn read_wav<P: AsRef<Path>>(path: P) -> io::Result<(AudioFormat, Vec<i16>)> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+    let mut bytes = vec![];
+    File::open(path)?.read_to_end(&mut bytes)?;
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(invalid("not a WAV file"));
+    }
+
+    let mut format = None;
+    let mut pos = 12;
+    while pos + 8 <= bytes.len() {
+        let id = &bytes[pos..pos + 4];
+        let size = (&bytes[pos + 4..pos + 8]).read_u32::<LittleEndian>()? as usize;
+        let body = &bytes[pos + 8..std::cmp::min(bytes.len(), pos + 8 + size)];
+        if id == b"fmt " {
+            let mut body = body;
+            let tag = body.read_u16::<LittleEndian>()?;
+            let channels = body.read_u16::<LittleEndian>()?;
+            let sample_rate = body.read_u32::<LittleEndian>()?;
+            let _byte_rate = body.read_u32::<LittleEndian>()?;
+            let _block_align = body.read_u16::<LittleEndian>()?;
+            let bits = body.read_u16::<LittleEndian>()?;
+            if tag != 1 || bits != 16 {
+                return Err(invalid("only 16-bit PCM WAV is supported"));
+            }
+            format = Some(AudioFormat { sample_rate, channels });
+        } else if id == b"data" {
+            let format = format.ok_or_else(|| invalid("WAV data before fmt chunk"))?;
+            let mut samples = vec![0; body.len() / 2];
+            (&body[..samples.len() * 2]).read_i16_into::<LittleEndian>(&mut samples)?;
+            return Ok((format, samples));
+        }
+        pos += 8 + size + (size % 2);
+    }
+    Err(invalid("WAV file has no data chunk"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Splits RIFF data into `(id, body)` chunks, checking each one's size
+    /// and padding fits.
+    fn chunks(mut data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+        let mut chunks = vec![];
+        while !data.is_empty() {
+            let mut id = [0; 4];
+            id.copy_from_slice(&data[..4]);
+            let size = (&data[4..8]).read_u32::<LittleEndian>().unwrap() as usize;
+            assert!(8 + size <= data.len(), "{:?} overruns its parent", id);
+            chunks.push((id, &data[8..8 + size]));
+            data = &data[std::cmp::min(data.len(), 8 + size + size % 2)..];
+        }
+        chunks
+    }
+
+    fn read_u32(data: &[u8], at: usize) -> u32 {
+        (&data[at..at + 4]).read_u32::<LittleEndian>().unwrap()
+    }
+
+    #[test]
+    fn chunk_sizes() {
+        let audio = AudioFormat { sample_rate: 48000, channels: 2 };
+        let mut writer = AviWriter::new(Cursor::new(vec![]), 2, 2, (30000, 1001), PixelFormat::Rgb24, Some(audio), None).unwrap();
+        let mut frame = Frame::new(2, 2);
+        frame.set(0, 0, [1., 0., 0.]);
+        frame.set(1, 1, [0., 0., 1.]);
+        writer.write_frame(&frame).unwrap();
+        writer.write_audio(&[1, -1, 2, -2, 3]).unwrap();
+        writer.write_frame(&frame).unwrap();
+        let out = writer.finish().unwrap().into_inner();
+
+        let riff = chunks(&out);
+        assert_eq!(riff.len(), 1);
+        let (id, body) = riff[0];
+        assert_eq!(&id, b"RIFF");
+        assert_eq!(body.len(), out.len() - 8);
+        assert_eq!(&body[..4], b"AVI ");
+
+        let top = chunks(&body[4..]);
+        let ids = top.iter().map(|(id, _)| id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![b"LIST", b"LIST", b"idx1"]);
+        assert_eq!(&top[0].1[..4], b"hdrl");
+        assert_eq!(&top[1].1[..4], b"movi");
+
+        // Counts are patched in once the stream is finished.
+        let hdrl = chunks(&top[0].1[4..]);
+        assert_eq!(&hdrl[0].0, b"avih");
+        assert_eq!(read_u32(hdrl[0].1, 16), 2);
+        assert_eq!(read_u32(hdrl[0].1, 24), 2);
+        assert_eq!(hdrl.len(), 3);
+
+        // Rows are bottom-up BGR, padded to four bytes.
+        let movi = chunks(&top[1].1[4..]);
+        let ids = movi.iter().map(|(id, body)| (*id, body.len())).collect::<Vec<_>>();
+        assert_eq!(ids, vec![(*b"00db", 16), (*b"01wb", 10), (*b"00db", 16)]);
+        assert_eq!(movi[0].1, &[0, 0, 0, 255, 0, 0, 0, 0, 0, 0, 255, 0, 0, 0, 0, 0][..]);
+
+        // Index offsets are from the `movi` tag, and point at each chunk.
+        let idx1 = top[2].1;
+        assert_eq!(idx1.len(), 3 * 16);
+        let movi_data = top[1].1;
+        for (entry, (id, body)) in idx1.chunks(16).zip(&movi) {
+            assert_eq!(&entry[..4], id);
+            let offset = read_u32(entry, 8) as usize;
+            assert_eq!(&movi_data[offset..offset + 4], id);
+            assert_eq!(read_u32(entry, 12) as usize, body.len());
+        }
+    }
+
+    /// Keeps track of how much has been written, and nothing else.
+    #[derive(Default)]
+    struct Sink {
+        pos: u64,
+        len: u64,
+    }
+
+    impl Write for Sink {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.pos += buf.len() as u64;
+            self.len = self.len.max(self.pos);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for Sink {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.pos = match pos {
+                SeekFrom::Start(x) => x,
+                SeekFrom::Current(x) => (self.pos as i64 + x) as u64,
+                SeekFrom::End(x) => (self.len as i64 + x) as u64,
+            };
+            Ok(self.pos)
+        }
+    }
+
+    #[test]
+    fn stops_short_of_limit() {
+        let mut writer = AviWriter::new(Sink::default(), 2, 2, (30000, 1001), PixelFormat::Rgb24, None, None).unwrap();
+        let data = vec![0; 64 << 20];
+        let mut written = 0;
+        let err = loop {
+            match writer.write_data(*b"00db", &data) {
+                Ok(()) => written += 1,
+                Err(err) => break err,
+            }
+        };
+        assert_eq!(written, 15);
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let sink = writer.finish().unwrap();
+        assert!(sink.len <= MAX_FILE_BYTES && sink.len > MAX_FILE_BYTES - (64 << 20), "{}", sink.len);
+    }
+}
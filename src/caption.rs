@@ -0,0 +1,647 @@
+use std::f32::consts::PI;
+use std::io::{self, Write};
+use std::ops::{Range, RangeInclusive};
+use crate::capture::to_ire;
+use crate::decode::{self, SAMPLE_RATE_MHZ};
+use crate::font::{self, GLYPH_HEIGHT, GLYPH_WIDTH};
+use crate::frame::Frame;
+use crate::vbi;
+use crate::waveform;
+
+/// Line of each field carrying EIA-608 data.
+pub const LINE: usize = 21;
+
+/// Lines searched for it. Lines are only numbered from vertical sync to
+/// within a line, depending on how the decoder's line chunks fall, so the
+/// lines either side are tried when line 21 has no data.
+pub const SEARCH_LINES: RangeInclusive<usize> = LINE - 1..=LINE + 1;
+
+/// Bits per line after the start bits: two bytes of seven bits and odd
+/// parity, least significant bit first.
+pub const DATA_BITS: usize = 16;
+
+/// Bits per line period; data and clock run-in are both at 32 times the
+/// line rate.
+pub const BITS_PER_LINE: f32 = 32.;
+
+/// Part of the clock run-in locked to for the slicing level, in µs from the
+/// sync leading edge. The run-in starts at 10.5µs and lasts about 7 cycles.
+pub const RUN_IN_US: Range<f32> = 11.0..22.5;
+
+/// Where the start bit's leading edge may be, in µs from the sync leading
+/// edge. It's nominally at 27.4µs.
+pub const START_BIT_US: Range<f32> = 24.5..31.;
+
+/// Smallest clock run-in, in IRE peak to peak, taken to be caption data
+/// rather than a blank line or picture. It's nominally 50.
+pub const MIN_RUN_IN_IRE: f32 = 20.;
+
+/// Rows and columns of the caption grid.
+pub const ROWS: usize = 15;
+pub const COLUMNS: usize = 32;
+
+/// Pair of bytes sent when there's nothing to send, parity included.
+pub const NULL_PAIR: [u8; 2] = [0x80, 0x80];
+
+/// Seconds per NTSC frame, which carries one pair of bytes per field.
+pub const FRAME_SECONDS: f64 = 1001. / 30000.;
+
+/// Whether a byte has odd parity, as every byte on line 21 should.
+pub fn parity_ok(byte: u8) -> bool {
+    byte.count_ones() % 2 == 1
+}
+
+/**
+ * Slices line 21 starting at `start` (the end of its sync) into its two
+ * bytes, parity bits included. The slicing level is the middle of the clock
+ * run-in, and bits are timed from the start bit's leading edge. Returns None
+ * if there's no run-in and start bit.
+ */
+pub fn slice(signal: &[f32], start: usize, line_samples: usize) -> Option<[u8; 2]> {
+    let fall = decode::find_sync_fall(signal, start);
+    let line = waveform::luma(&decode::line_at(signal, fall, line_samples));
+    let bit = line_samples as f32 / BITS_PER_LINE;
+    let at = |us: f32| (us * SAMPLE_RATE_MHZ) as usize;
+
+    // The run-in is a sine one bit long, centered on the slicing level.
+    let run_in = &line[at(RUN_IN_US.start)..at(RUN_IN_US.end)];
+    let level = run_in.iter().sum::<f32>() / run_in.len() as f32;
+    let (i, q) = run_in.iter().enumerate().fold((0., 0.), |(i, q), (n, x)| {
+        let phase = 2. * PI * n as f32 / bit;
+        (i + (x - level) * phase.sin(), q + (x - level) * phase.cos())
+    });
+    let peak_to_peak = 4. * (i * i + q * q).sqrt() / run_in.len() as f32;
+    if to_ire(peak_to_peak) < MIN_RUN_IN_IRE {
+        return None;
+    }
+
+    // Two zero bits, then the start bit.
+    let low = (bit * 1.5) as usize;
+    let edge = (at(START_BIT_US.start)..at(START_BIT_US.end))
+        .find(|i| line[*i] >= level && line[i - low..*i].iter().all(|x| *x < level))?;
+    let edge = (edge - 1) as f32 + (level - line[edge - 1]) / (line[edge] - line[edge - 1]);
+
+    let mut bytes = [0; 2];
+    for k in 0..DATA_BITS {
+        let center = edge + (1.5 + k as f32) * bit;
+        let from = (center - bit / 4.) as usize;
+        let to = ((center + bit / 4.) as usize).min(line.len());
+        if from >= to {
+            return None;
+        }
+        if line[from..to].iter().sum::<f32>() / (to - from) as f32 > level {
+            bytes[k / 8] |= 1 << (k % 8);
+        }
+    }
+    Some(bytes)
+}
+
+/// The bytes of one field's line 21, parity bits included.
+#[derive(Clone, Copy, Debug)]
+pub struct Packet {
+    pub line: vbi::Line,
+    pub bytes: [u8; 2],
+}
+
+/// Slices each field's line 21, or failing that the nearest of `lines`
+/// with data, see `SEARCH_LINES`. Fields without data are left out.
+/// `signal` is the sync channel's levels.
+pub fn packets(signal: &[f32], lines: &[vbi::Line], line_samples: usize) -> Vec<Packet> {
+    let mut packets: Vec<Packet> = vec![];
+    let mut lines = lines.to_vec();
+    lines.sort_by_key(|x| (x.field, (x.number as isize - LINE as isize).abs()));
+    for line in lines {
+        if packets.last().map_or(false, |x| x.line.field == line.field) {
+            continue;
+        }
+        if let Some(bytes) = slice(signal, line.start, line_samples) {
+            packets.push(Packet { line, bytes });
+        }
+    }
+    packets
+}
+
+/// Parses a caption channel, `1` to `4` or `cc1` to `cc4`.
+pub fn parse_channel(value: &str) -> Option<usize> {
+    let value = value.trim().to_lowercase();
+    match value.trim_start_matches("cc").parse::<usize>() {
+        Ok(channel) if (1..=4).contains(&channel) => Some(channel),
+        _ => None,
+    }
+}
+
+/// Reads the `CAPTIONS` environment variable, the channel to overlay on the
+/// decoded picture, if any.
+pub fn channel_from_env() -> Option<usize> {
+    std::env::var("CAPTIONS").ok()
+        .map(|x| parse_channel(&x).unwrap_or_else(|| panic!("unknown CAPTIONS {:?}", x)))
+}
+
+/// Whether a line carries caption channel `channel`: CC1 and CC2 are sent
+/// in the first field of each frame, CC3 and CC4 in the second.
+pub fn carries(channel: usize, line: &vbi::Line) -> bool {
+    line.second.unwrap_or(false) == (channel > 2)
+}
+
+/// A character of the basic set, which is ASCII but for a few accented
+/// letters and symbols.
+pub fn basic(byte: u8) -> char {
+    match byte {
+        0x2a => 'á',
+        0x5c => 'é',
+        0x5e => 'í',
+        0x5f => 'ó',
+        0x60 => 'ú',
+        0x7b => 'ç',
+        0x7c => '÷',
+        0x7d => 'Ñ',
+        0x7e => 'ñ',
+        0x7f => '█',
+        _ => byte as char,
+    }
+}
+
+/// Special characters, sent as `0x11 0x30` to `0x11 0x3f`.
+const SPECIAL: [char; 16] = [
+    '®', '°', '½', '¿', '™', '¢', '£', '♪', 'à', '\u{a0}', 'è', 'â', 'ê', 'î', 'ô', 'û',
+];
+
+/// Extended characters, sent as `0x12 0x20` to `0x13 0x3f`, each replacing
+/// the basic character sent before it.
+const EXTENDED: [char; 64] = [
+    'Á', 'É', 'Ó', 'Ú', 'Ü', 'ü', '‘', '¡', '*', '’', '─', '©', '℠', '•', '“', '”',
+    'À', 'Â', 'Ç', 'È', 'Ê', 'Ë', 'ë', 'Î', 'Ï', 'ï', 'Ô', 'Ù', 'ù', 'Û', '«', '»',
+    'Ã', 'ã', 'Í', 'Ì', 'ì', 'Ò', 'ò', 'Õ', 'õ', '{', '}', '\\', '^', '_', '|', '~',
+    'Ä', 'ä', 'Ö', 'ö', 'ß', '¥', '¤', '│', 'Å', 'å', 'Ø', 'ø', '┌', '┐', '└', '┘',
+];
+
+/// How captions are put on screen.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Style {
+    /// Built off screen, then shown all at once.
+    PopOn,
+    /// Written on the bottom row of a window this many rows tall, which
+    /// scrolls up on each carriage return.
+    RollUp(usize),
+    /// Written straight on screen.
+    PaintOn,
+    /// Text service rather than captions, which is ignored.
+    Text,
+}
+
+type Memory = [[Option<char>; COLUMNS]; ROWS];
+
+const EMPTY: Memory = [[None; COLUMNS]; ROWS];
+
+/**
+ * Decodes one caption channel from its field's pairs of bytes into what's
+ * on screen, following CEA-608: preamble address codes place the cursor,
+ * and pop-on, roll-up and paint-on captions are kept in displayed and
+ * non-displayed memories. Colors and other attributes aren't kept.
+ */
+pub struct CaptionDecoder {
+    /// Data channel within the field, 1 or 2.
+    channel: usize,
+    /// Data channel the last control code was for.
+    current: usize,
+    style: Style,
+    displayed: Memory,
+    hidden: Memory,
+    row: usize,
+    column: usize,
+    /// Control codes are sent twice in a row so that one can be lost; the
+    /// first of a pair, so the second can be skipped.
+    last_control: Option<[u8; 2]>,
+    /// Whether extended data services bytes are being sent, which aren't
+    /// captions.
+    xds: bool,
+}
+
+impl CaptionDecoder {
+    /// A decoder for `channel`, 1 to 4 for CC1 to CC4. It should be fed the
+    /// bytes of the fields that carry it, see `carries`.
+    pub fn new(channel: usize) -> CaptionDecoder {
+        CaptionDecoder {
+            channel: (channel - 1) % 2 + 1,
+            current: 1,
+            style: Style::PopOn,
+            displayed: EMPTY,
+            hidden: EMPTY,
+            row: ROWS - 1,
+            column: 0,
+            last_control: None,
+            xds: false,
+        }
+    }
+
+    /// Takes the next pair of bytes, parity bits included.
+    pub fn feed(&mut self, bytes: [u8; 2]) {
+        let (a, b) = (bytes[0] & 0x7f, bytes[1] & 0x7f);
+        if (0x10..0x20).contains(&a) {
+            self.xds = false;
+            if !parity_ok(bytes[0]) || !parity_ok(bytes[1]) || self.last_control == Some(bytes) {
+                self.last_control = None;
+                return;
+            }
+            self.last_control = Some(bytes);
+            self.current = if a & 0x08 == 0 { 1 } else { 2 };
+            if self.current == self.channel {
+                self.control(a & !0x08, b);
+            }
+            return;
+        }
+        self.last_control = None;
+
+        // Extended data services packets run from a start code to their
+        // checksum, or until captions pick up again.
+        if (0x01..0x10).contains(&a) {
+            self.xds = a != 0x0f;
+            return;
+        }
+        if self.xds || self.current != self.channel || self.style == Style::Text {
+            return;
+        }
+        for (byte, raw) in [a, b].iter().zip(bytes.iter()) {
+            if *byte >= 0x20 {
+                self.write(if parity_ok(*raw) { basic(*byte) } else { '█' });
+            }
+        }
+    }
+
+    fn control(&mut self, a: u8, b: u8) {
+        if self.style == Style::Text && !(a == 0x14 || a == 0x15) {
+            return;
+        }
+        match (a, b) {
+            // Field 2 sends miscellaneous control codes as 0x15.
+            (0x14, 0x20..=0x2f) | (0x15, 0x20..=0x2f) => self.command(b),
+            (0x17, 0x21..=0x23) => self.column = (self.column + (b - 0x20) as usize).min(COLUMNS - 1),
+            (0x11, 0x30..=0x3f) => self.write(SPECIAL[(b - 0x30) as usize]),
+            (0x12, 0x20..=0x3f) | (0x13, 0x20..=0x3f) => {
+                self.column = self.column.saturating_sub(1);
+                self.write(EXTENDED[((a - 0x12) * 32 + b - 0x20) as usize]);
+            }
+            // Mid-row codes change style, and show as a space.
+            (0x11, 0x20..=0x2f) => self.write(' '),
+            (_, 0x40..=0x7f) => self.preamble(a, b),
+            _ => {}
+        }
+    }
+
+    /// Moves the cursor to the row and indent given by a preamble address
+    /// code. In roll-up, the window moves with it.
+    fn preamble(&mut self, a: u8, b: u8) {
+        let row = match a {
+            0x11 => 1,
+            0x12 => 3,
+            0x15 => 5,
+            0x16 => 7,
+            0x17 => 9,
+            0x10 => 11,
+            0x13 => 12,
+            0x14 => 14,
+            _ => return,
+        };
+        let mut row = row - 1 + if a != 0x10 && b & 0x20 != 0 { 1 } else { 0 };
+        if let Style::RollUp(rows) = self.style {
+            row = row.max(rows - 1);
+            if row != self.row {
+                let old = std::mem::replace(&mut self.displayed, EMPTY);
+                for k in 0..rows.min(self.row + 1) {
+                    self.displayed[row - k] = old[self.row - k];
+                }
+            }
+        }
+        self.row = row;
+        self.column = if b & 0x10 != 0 { ((b & 0x0e) >> 1) as usize * 4 } else { 0 };
+    }
+
+    fn command(&mut self, b: u8) {
+        match b {
+            // Resume caption loading
+            0x20 => self.style = Style::PopOn,
+            // Backspace, which does nothing at the first column
+            0x21 if self.column > 0 => {
+                self.column -= 1;
+                let (row, column) = (self.row, self.column);
+                self.memory()[row][column] = None;
+            }
+            // Delete to end of row
+            0x24 => {
+                let (row, column) = (self.row, self.column);
+                self.memory()[row][column..].iter_mut().for_each(|x| *x = None);
+            }
+            // Roll-up captions, 2 to 4 rows
+            0x25..=0x27 => {
+                let rows = (b - 0x23) as usize;
+                match self.style {
+                    Style::RollUp(_) => {}
+                    _ => {
+                        self.displayed = EMPTY;
+                        self.hidden = EMPTY;
+                        self.row = ROWS - 1;
+                    }
+                }
+                self.row = self.row.max(rows - 1);
+                let top = self.row + 1 - rows;
+                self.displayed[..top].iter_mut().for_each(|x| *x = [None; COLUMNS]);
+                self.style = Style::RollUp(rows);
+                self.column = 0;
+            }
+            // Resume direct captioning
+            0x29 => self.style = Style::PaintOn,
+            // Text restart, resume text display
+            0x2a | 0x2b => self.style = Style::Text,
+            // Erase displayed memory
+            0x2c => self.displayed = EMPTY,
+            // Carriage return
+            0x2d => {
+                if let Style::RollUp(rows) = self.style {
+                    let top = self.row + 1 - rows;
+                    for row in top..self.row {
+                        self.displayed[row] = self.displayed[row + 1];
+                    }
+                    self.displayed[self.row] = [None; COLUMNS];
+                    self.column = 0;
+                }
+            }
+            // Erase non-displayed memory
+            0x2e => self.hidden = EMPTY,
+            // End of caption
+            0x2f => {
+                std::mem::swap(&mut self.displayed, &mut self.hidden);
+                self.style = Style::PopOn;
+            }
+            // Alarms and flash
+            _ => {}
+        }
+    }
+
+    /// The memory being written to.
+    fn memory(&mut self) -> &mut Memory {
+        match self.style {
+            Style::PopOn => &mut self.hidden,
+            _ => &mut self.displayed,
+        }
+    }
+
+    fn write(&mut self, c: char) {
+        let (row, column) = (self.row, self.column);
+        self.memory()[row][column] = Some(c);
+        self.column = (column + 1).min(COLUMNS - 1);
+    }
+
+    /// What's on screen, as rows from the top: each row's number, the
+    /// column its text starts in, and the text, with gaps as spaces.
+    pub fn rows(&self) -> Vec<(usize, usize, String)> {
+        self.displayed.iter()
+            .enumerate()
+            .filter_map(|(n, row)| {
+                let first = row.iter().position(|x| x.is_some())?;
+                let last = row.iter().rposition(|x| x.is_some())?;
+                let text = row[first..=last].iter().map(|x| x.unwrap_or(' ')).collect();
+                Some((n, first, text))
+            })
+            .collect()
+    }
+
+    /// What's on screen as plain text, a line per row.
+    pub fn text(&self) -> String {
+        self.rows().iter().map(|(_, _, text)| text.trim()).collect::<Vec<_>>().join("\n")
+    }
+}
+
+/// Caption text on screen between two times, in seconds from the start of
+/// the capture.
+#[derive(Clone, Debug)]
+pub struct Cue {
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+}
+
+/// Decodes `channel` into a cue each time what's on screen changes. Roll-up
+/// and paint-on captions change as each pair of characters arrives.
+pub fn cues(packets: &[Packet], channel: usize) -> Vec<Cue> {
+    let mut decoder = CaptionDecoder::new(channel);
+    let mut cues = vec![];
+    let mut shown: Option<Cue> = None;
+    let mut end = 0.;
+    for packet in packets.iter().filter(|x| carries(channel, &x.line)) {
+        decoder.feed(packet.bytes);
+        let time = packet.line.time();
+        end = time + FRAME_SECONDS;
+        let text = decoder.text();
+        if shown.as_ref().map_or(text.is_empty(), |x| x.text == text) {
+            continue;
+        }
+        if let Some(mut cue) = shown.take() {
+            cue.end = time;
+            cues.push(cue);
+        }
+        if !text.is_empty() {
+            shown = Some(Cue { start: time, end: time, text });
+        }
+    }
+    if let Some(mut cue) = shown {
+        cue.end = end;
+        cues.push(cue);
+    }
+    cues
+}
+
+/// Formats seconds as an SRT timestamp, `HH:MM:SS,mmm`.
+fn srt_time(seconds: f64) -> String {
+    let ms = (seconds * 1000.).round() as u64;
+    format!("{:02}:{:02}:{:02},{:03}", ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60, ms % 1000)
+}
+
+/// Formats seconds as a 29.97 Hz non-drop-frame timecode, `HH:MM:SS:FF`.
+pub fn timecode(seconds: f64) -> String {
+    let frames = (seconds / FRAME_SECONDS).round() as u64;
+    format!("{:02}:{:02}:{:02}:{:02}", frames / 108_000, frames / 1800 % 60, frames / 30 % 60, frames % 30)
+}
+
+/// Writes cues as SubRip subtitles.
+pub fn write_srt<W: Write>(out: &mut W, cues: &[Cue]) -> io::Result<()> {
+    for (n, cue) in cues.iter().enumerate() {
+        writeln!(out, "{}", n + 1)?;
+        writeln!(out, "{} --> {}", srt_time(cue.start), srt_time(cue.end))?;
+        writeln!(out, "{}", cue.text)?;
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/**
+ * Writes the bytes of the fields carrying `channel` as Scenarist SCC: each
+ * run of pairs between null pairs on a line of its own, in hex with parity,
+ * after the timecode of its first pair. Lines that didn't slice are left
+ * out.
+ */
+pub fn write_scc<W: Write>(out: &mut W, packets: &[Packet], channel: usize) -> io::Result<()> {
+    writeln!(out, "Scenarist_SCC V1.0")?;
+    let mut run = vec![];
+    for packet in packets.iter().filter(|x| carries(channel, &x.line)) {
+        if packet.bytes == NULL_PAIR {
+            write_scc_line(out, &run)?;
+            run.clear();
+        } else {
+            run.push(packet);
+        }
+    }
+    write_scc_line(out, &run)
+}
+
+fn write_scc_line<W: Write>(out: &mut W, run: &[&Packet]) -> io::Result<()> {
+    if let Some(first) = run.first() {
+        let pairs = run.iter().map(|x| format!("{:02x}{:02x}", x.bytes[0], x.bytes[1])).collect::<Vec<_>>();
+        writeln!(out)?;
+        writeln!(out, "{}\t{}", timecode(first.line.time()), pairs.join(" "))?;
+    }
+    Ok(())
+}
+
+/**
+ * Draws what's on screen over a field's picture as white on black, on a
+ * grid of `ROWS` by `COLUMNS` over the middle 80% of it. Text is drawn in
+ * capitals with the label font, so characters it lacks are left blank.
+ */
+pub fn draw(frame: &mut Frame, rows: &[(usize, usize, String)]) {
+    let (left, top) = (frame.width / 10, frame.height / 10);
+    let cell_width = frame.width * 8 / 10 / COLUMNS;
+    let cell_height = frame.height * 8 / 10 / ROWS;
+    let scale = (cell_width / (GLYPH_WIDTH + 1)).min(cell_height / (GLYPH_HEIGHT + 1)).max(1);
+    for (row, column, text) in rows {
+        let (x, y) = (left + column * cell_width, top + row * cell_height);
+        let width = text.chars().count() * cell_width;
+        for py in y..(y + cell_height).min(frame.height) {
+            for px in x..(x + width).min(frame.width) {
+                frame.set(px, py, [0.; 3]);
+            }
+        }
+        let baseline = y + cell_height.saturating_sub(GLYPH_HEIGHT * scale) / 2;
+        for (n, c) in text.chars().enumerate() {
+            let glyph = c.to_uppercase().next().unwrap_or(c).to_string();
+            font::draw_text(frame, (x + n * cell_width) as isize, baseline as isize, &glyph, scale, [1.; 3]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sets a byte's top bit where needed to give it odd parity.
+    fn odd(byte: u8) -> u8 {
+        if parity_ok(byte) { byte } else { byte | 0x80 }
+    }
+
+    fn pair(a: u8, b: u8) -> [u8; 2] {
+        [odd(a), odd(b)]
+    }
+
+    /// Feeds a control code twice, as it's sent.
+    fn control(decoder: &mut CaptionDecoder, a: u8, b: u8) {
+        decoder.feed(pair(a, b));
+        decoder.feed(pair(a, b));
+    }
+
+    /// Feeds characters two at a time, padding the last pair with a null.
+    fn characters(decoder: &mut CaptionDecoder, text: &str) {
+        for chunk in text.as_bytes().chunks(2) {
+            decoder.feed(pair(chunk[0], chunk.get(1).cloned().unwrap_or(0)));
+        }
+    }
+
+    #[test]
+    fn parity() {
+        assert!(parity_ok(0x80));
+        assert!(parity_ok(0x01));
+        assert!(parity_ok(0xc1));
+        assert!(!parity_ok(0x00));
+        assert!(!parity_ok(0x41));
+        assert_eq!(NULL_PAIR, pair(0, 0));
+    }
+
+    #[test]
+    fn pop_on() {
+        let mut decoder = CaptionDecoder::new(1);
+        // Resume caption loading, then row 14 indented 4 columns.
+        control(&mut decoder, 0x14, 0x20);
+        control(&mut decoder, 0x14, 0x52);
+        characters(&mut decoder, "HELLO");
+        assert_eq!(decoder.text(), "");
+
+        // End of caption shows it.
+        control(&mut decoder, 0x14, 0x2f);
+        assert_eq!(decoder.rows(), vec![(13, 4, "HELLO".to_string())]);
+
+        // The second channel of the field is ignored.
+        control(&mut decoder, 0x1c, 0x2c);
+        assert_eq!(decoder.text(), "HELLO");
+        control(&mut decoder, 0x14, 0x2c);
+        assert_eq!(decoder.text(), "");
+    }
+
+    #[test]
+    fn roll_up() {
+        let mut decoder = CaptionDecoder::new(1);
+        control(&mut decoder, 0x14, 0x25);
+        characters(&mut decoder, "ONE");
+        assert_eq!(decoder.rows(), vec![(14, 0, "ONE".to_string())]);
+
+        control(&mut decoder, 0x14, 0x2d);
+        characters(&mut decoder, "TWO");
+        assert_eq!(decoder.rows(), vec![(13, 0, "ONE".to_string()), (14, 0, "TWO".to_string())]);
+
+        // Two rows are kept, so the first scrolls off.
+        control(&mut decoder, 0x14, 0x2d);
+        characters(&mut decoder, "THREE");
+        assert_eq!(decoder.text(), "TWO\nTHREE");
+    }
+
+    /// Line 21 as the sync channel's levels from the sync leading edge at
+    /// `fall`, carrying `bytes`, or blank without them.
+    fn line_21(fall: usize, bytes: Option<[u8; 2]>) -> Vec<f32> {
+        let line_samples = decode::LINE_SAMPLES;
+        let bit = line_samples as f32 / BITS_PER_LINE;
+        // 50 IRE.
+        let high = crate::capture::WHITE_LEVEL / 2.;
+        let edge = 27.4 * SAMPLE_RATE_MHZ;
+
+        let mut signal = vec![0.; fall + line_samples];
+        for (i, x) in signal[fall..].iter_mut().enumerate() {
+            let t = i as f32;
+            *x = if t < 4.7 * SAMPLE_RATE_MHZ {
+                -56.
+            } else if let Some(bytes) = bytes {
+                let bits = (t - edge) / bit;
+                if (-8.5..-2.).contains(&bits) {
+                    // The clock run-in, ending low two bits before the
+                    // start bit.
+                    high / 2. * (1. - (2. * PI * bits).cos())
+                } else if (0. ..1.).contains(&bits) {
+                    high
+                } else if (1. ..1. + DATA_BITS as f32).contains(&bits) {
+                    let k = bits as usize - 1;
+                    if bytes[k / 8] & (1 << (k % 8)) != 0 { high } else { 0. }
+                } else {
+                    0.
+                }
+            } else {
+                0.
+            };
+        }
+        signal
+    }
+
+    #[test]
+    fn slices_line_21() {
+        let fall = 100;
+        let start = fall + (4.7 * SAMPLE_RATE_MHZ) as usize;
+        let bytes = [odd(b'A'), odd(b'R')];
+        assert_eq!(slice(&line_21(fall, Some(bytes)), start, decode::LINE_SAMPLES), Some(bytes));
+        assert_eq!(slice(&line_21(fall, None), start, decode::LINE_SAMPLES), None);
+    }
+}
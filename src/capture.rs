@@ -0,0 +1,274 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::ops::Range;
+use std::path::Path;
+use byteorder::{ReadBytesExt, NativeEndian};
+use crate::glitch::{self, Detector, Glitch};
+
+/// ADC code of the blanking level, and codes per 300 units of signal.
+pub const ADC_ZERO: i16 = 2080;
+pub const ADC_SCALE: f32 = 410.0;
+
+/// Converts a raw SMI sample (12 bits, left-aligned in 16) into a signal level
+/// where 0 is blanking, sync sits below -40 and white is around 140.
+pub fn volt_decode(input: u16) -> f32 {
+    ((((ADC_ZERO - ((input >> 4) as i16)) as f32) / ADC_SCALE) * 300.0) as f32
+}
+
+/// Word the Pi fills its capture buffer with before DMA, which is left
+/// between blocks of samples.
+pub const FILLER_WORD: u16 = (ADC_ZERO as u16) << 4;
+
+/**
+ * How the Pi lays out a capture: DMA blocks of `samples` words, each
+ * followed by `filler` words of `FILLER_WORD` that DMA doesn't write. The
+ * Pi stops between blocks, so each starts at some unknown time after the
+ * one before.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Layout {
+    pub samples: usize,
+    pub filler: usize,
+}
+
+/// The layout `pi-src` captures with: 16 blocks of 30000 samples, 40 words
+/// apart.
+pub const PI_LAYOUT: Layout = Layout { samples: 30000, filler: 40 };
+
+impl Layout {
+    /// Parses `SAMPLES+FILLER`, in words.
+    pub fn parse(value: &str) -> Option<Layout> {
+        let mut parts = value.split('+');
+        let samples = parts.next()?.trim().parse::<usize>().ok()?;
+        let filler = parts.next()?.trim().parse::<usize>().ok()?;
+        if parts.next().is_some() || samples == 0 {
+            return None;
+        }
+        Some(Layout { samples, filler })
+    }
+
+    /// Reads the `BLOCKS` environment variable, `SAMPLES+FILLER` or `none`
+    /// for a capture that's continuous. Defaults to `PI_LAYOUT`.
+    pub fn from_env() -> Option<Layout> {
+        match std::env::var("BLOCKS") {
+            Ok(value) if value == "none" => None,
+            Ok(value) => Some(Layout::parse(&value).unwrap_or_else(|| panic!("invalid BLOCKS {:?}", value))),
+            Err(_) => Some(PI_LAYOUT),
+        }
+    }
+}
+
+/// Largest 12-bit ADC code.
+pub const ADC_MAX: u16 = 4095;
+
+/// Whether a raw sample is at either end of the ADC's range.
+pub fn is_clipped(input: u16) -> bool {
+    let code = input >> 4;
+    code == 0 || code == ADC_MAX
+}
+
+/// Signal level of reference white, i.e. 100 IRE.
+pub const WHITE_LEVEL: f32 = 140.0;
+
+/// Converts a signal level from `volt_decode` to IRE units.
+pub fn to_ire(level: f32) -> f32 {
+    level * 100. / WHITE_LEVEL
+}
+
+/**
+ * A capture file read from the Pi. Samples are native-endian u16 words. A
+ * composite capture holds a single channel; captures with more channels store
+ * one word per channel for every sample instant, e.g. S-Video is laid out as
+ * `Y0 C0 Y1 C1 ...`.
+ */
+pub struct Capture {
+    channels: Vec<Vec<u16>>,
+    glitches: Vec<Glitch>,
+    blocks: Vec<Range<usize>>,
+}
+
+impl Capture {
+    pub fn open<P: AsRef<Path>>(path: P, channel_count: usize) -> io::Result<Capture> {
+        let mut file = File::open(path)?;
+        let mut bytes = vec![];
+        file.read_to_end(&mut bytes)?;
+
+        let mut raw = vec![0; bytes.len() / 2];
+        (&bytes[..raw.len() * 2]).read_u16_into::<NativeEndian>(&mut raw)?;
+        Ok(Capture::from_interleaved(&raw, channel_count))
+    }
+
+    /// Splits interleaved words into channels. A trailing partial sample
+    /// instant is dropped.
+    pub fn from_interleaved(raw: &[u16], channel_count: usize) -> Capture {
+        assert!(channel_count > 0, "capture needs at least one channel");
+
+        let mut channels = vec![Vec::with_capacity(raw.len() / channel_count); channel_count];
+        for instant in raw.chunks_exact(channel_count) {
+            for (channel, sample) in channels.iter_mut().zip(instant) {
+                channel.push(*sample);
+            }
+        }
+        let blocks = std::iter::once(0..channels[0].len()).collect();
+        Capture { channels, glitches: vec![], blocks }
+    }
+
+    /// Finds glitches in every channel with `detector`, and repairs them
+    /// unless it only counts them.
+    pub fn repair_glitches(&mut self, detector: &Detector) {
+        self.glitches.clear();
+        for (index, channel) in self.channels.iter_mut().enumerate() {
+            let found = detector.find(channel, index);
+            if detector.repair {
+                glitch::repair(channel, &found.iter().map(|x| x.index).collect::<Vec<_>>());
+            }
+            self.glitches.extend(found);
+        }
+    }
+
+    /**
+     * Splits the capture into DMA blocks laid out as `layout` says, leaving
+     * out the filler between them. The capture is only taken to be laid out
+     * that way if the filler after every whole block is there, so captures
+     * from elsewhere stay in one block; returns whether it was. Blocks are
+     * counted in sample instants, so the layout's words must divide evenly
+     * between channels.
+     */
+    pub fn find_blocks(&mut self, layout: Option<Layout>) -> bool {
+        self.blocks = std::iter::once(0..self.len()).collect();
+        let layout = match layout {
+            Some(layout) => layout,
+            None => return false,
+        };
+        let count = self.channel_count();
+        if layout.samples % count != 0 || layout.filler % count != 0 || layout.filler == 0 {
+            return false;
+        }
+        let (samples, period) = (layout.samples / count, (layout.samples + layout.filler) / count);
+        let sync = &self.channels[0];
+        let gaps = (0..)
+            .map(|n| n * period + samples..(n + 1) * period)
+            .take_while(|x| x.end <= sync.len())
+            .collect::<Vec<_>>();
+        if gaps.is_empty() || !gaps.iter().all(|x| sync[x.clone()].iter().all(|w| *w == FILLER_WORD)) {
+            return false;
+        }
+        self.blocks = (0..)
+            .map(|n| n * period..(n * period + samples).min(sync.len()))
+            .take_while(|x| x.start < sync.len())
+            .collect();
+        true
+    }
+
+    /// DMA blocks found by `find_blocks`, or the whole capture.
+    pub fn blocks(&self) -> &[Range<usize>] {
+        &self.blocks
+    }
+
+    /// Filler between and after DMA blocks found by `find_blocks`.
+    pub fn gaps(&self) -> Vec<Range<usize>> {
+        self.blocks.iter()
+            .map(|x| x.end)
+            .zip(self.blocks.iter().skip(1).map(|x| x.start).chain(std::iter::once(self.len())))
+            .filter(|(from, to)| from < to)
+            .map(|(from, to)| from..to)
+            .collect()
+    }
+
+    /// Glitches found by `repair_glitches`, channel by channel.
+    pub fn glitches(&self) -> &[Glitch] {
+        &self.glitches
+    }
+
+    pub fn channel_count(&self) -> usize {
+        self.channels.len()
+    }
+
+    /// Number of sample instants, i.e. samples per channel.
+    pub fn len(&self) -> usize {
+        self.channels[0].len()
+    }
+
+    pub fn channel(&self, index: usize) -> &[u16] {
+        &self.channels[index]
+    }
+
+    /// Decodes a whole channel into signal levels.
+    pub fn levels(&self, index: usize) -> Vec<f32> {
+        self.channels[index].iter().map(|x| volt_decode(*x)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Word standing in for a sample, which is never filler.
+    const SAMPLE: u16 = 0x1000;
+
+    /// `blocks` periods of `PI_LAYOUT`, each `samples` words and its
+    /// filler, with `tail` more sample words after.
+    fn pi_capture(blocks: usize, tail: usize, channel_count: usize) -> Capture {
+        let mut raw = vec![];
+        for _ in 0..blocks {
+            raw.extend(vec![SAMPLE; PI_LAYOUT.samples]);
+            raw.extend(vec![FILLER_WORD; PI_LAYOUT.filler]);
+        }
+        raw.extend(vec![SAMPLE; tail]);
+        Capture::from_interleaved(&raw, channel_count)
+    }
+
+    #[test]
+    fn finds_blocks() {
+        let mut capture = pi_capture(3, 0, 1);
+        assert!(capture.find_blocks(Some(PI_LAYOUT)));
+        assert_eq!(capture.blocks(), &[0..30000, 30040..60040, 60080..90080]);
+        assert_eq!(capture.gaps(), vec![30000..30040, 60040..60080, 90080..90120]);
+
+        // A capture cut short ends in a partial block.
+        let mut capture = pi_capture(2, 100, 1);
+        assert!(capture.find_blocks(Some(PI_LAYOUT)));
+        assert_eq!(capture.blocks(), &[0..30000, 30040..60040, 60080..60180]);
+        assert_eq!(capture.gaps(), vec![30000..30040, 60040..60080]);
+
+        // Channels are interleaved, so each has half of every block.
+        let mut capture = pi_capture(2, 0, 2);
+        assert!(capture.find_blocks(Some(PI_LAYOUT)));
+        assert_eq!(capture.blocks(), &[0..15000, 15020..30020]);
+        assert_eq!(capture.gaps(), vec![15000..15020, 30020..30040]);
+    }
+
+    #[test]
+    fn leaves_other_layouts_whole() {
+        let whole = |capture: &Capture| {
+            assert_eq!(capture.blocks().len(), 1);
+            assert_eq!(capture.blocks()[0], 0..capture.len());
+            assert!(capture.gaps().is_empty());
+        };
+
+        let mut capture = pi_capture(3, 0, 1);
+        assert!(!capture.find_blocks(None));
+        whole(&capture);
+
+        // Samples where filler should be.
+        let mut capture = pi_capture(3, 0, 1);
+        capture.channels[0][60050] = SAMPLE;
+        assert!(!capture.find_blocks(Some(PI_LAYOUT)));
+        whole(&capture);
+
+        // Filler in the wrong place.
+        let mut capture = pi_capture(3, 0, 1);
+        assert!(!capture.find_blocks(Some(Layout { samples: 30020, filler: 40 })));
+        whole(&capture);
+
+        // Too short to have any filler.
+        let mut capture = pi_capture(0, 20000, 1);
+        assert!(!capture.find_blocks(Some(PI_LAYOUT)));
+        whole(&capture);
+
+        // Filler that can't be split evenly between channels.
+        let mut capture = pi_capture(3, 0, 3);
+        assert!(!capture.find_blocks(Some(PI_LAYOUT)));
+        whole(&capture);
+    }
+}
@@ -24,6 +24,10 @@ void main() {
             0.5
         ), 1.0);
         */
+    } else if (v_Index == 1) {
+        // Vectorscope overlay, stored as 8-bit RGBA.
+        uvec4 scope = texture(usampler2D(u_Textures[1], u_Sampler), v_TexCoord);
+        o_Color = vec4(scope) / 255.0;
     } else {
         // We need to write something to output color
         o_Color = vec4(0.0, 0.0, 1.0, 0.0);
@@ -0,0 +1,284 @@
+use crate::frame::Frame;
+
+/// Brightness above which light spills into its surroundings as bloom.
+pub const BLOOM_THRESHOLD: f32 = 0.6;
+
+/// Phosphor mask pattern.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mask {
+    None,
+    /// Vertical red, green and blue stripes, as on a Trinitron.
+    Aperture,
+    /// Red, green and blue slots, with every other triad offset by half a
+    /// slot.
+    Slot,
+}
+
+impl Mask {
+    pub fn parse(name: &str) -> Option<Mask> {
+        match name {
+            "none" => Some(Mask::None),
+            "aperture" | "grille" => Some(Mask::Aperture),
+            "slot" => Some(Mask::Slot),
+            _ => None,
+        }
+    }
+
+    /// Light let through to each channel at output pixel (`x`, `y`), with
+    /// the mask at full strength.
+    fn weights(&self, x: usize, y: usize) -> [f32; 3] {
+        let stripe = |n: usize| {
+            let mut weights = [0.; 3];
+            weights[n % 3] = 1.;
+            weights
+        };
+        match self {
+            Mask::None => [1.; 3],
+            Mask::Aperture => stripe(x),
+            Mask::Slot => {
+                // Slots are four rows tall with one dark row between them.
+                let offset = if (x / 3) % 2 == 0 { 0 } else { 2 };
+                if (y + offset) % 4 == 3 { [0.; 3] } else { stripe(x) }
+            }
+        }
+    }
+}
+
+/**
+ * Post-processing that approximates a CRT: a horizontal beam blur, scanlines
+ * whose width follows the beam's brightness, a phosphor mask and bloom.
+ * Strengths are from 0 (off) to 1.
+ */
+#[derive(Clone, Copy, Debug)]
+pub struct Crt {
+    /// How dark the gaps between scanlines get.
+    pub scanlines: f32,
+    pub mask: Mask,
+    pub mask_strength: f32,
+    /// Standard deviation of the horizontal beam blur, in output pixels.
+    pub blur: f32,
+    pub bloom: f32,
+}
+
+impl Default for Crt {
+    fn default() -> Crt {
+        Crt {
+            scanlines: 0.6,
+            mask: Mask::Aperture,
+            mask_strength: 0.3,
+            blur: 0.75,
+            bloom: 0.2,
+        }
+    }
+}
+
+impl Crt {
+    /// Reads `CRT`, which enables the effect when set to anything but `0` or
+    /// `off`, then overrides the defaults from `CRT_SCANLINES`, `CRT_MASK`,
+    /// `CRT_MASK_STRENGTH`, `CRT_BLUR` and `CRT_BLOOM`.
+    pub fn from_env() -> Option<Crt> {
+        match std::env::var("CRT").as_ref().map(|x| x.as_str()) {
+            Err(_) | Ok("0") | Ok("off") => return None,
+            Ok(_) => {}
+        }
+        let number = |name: &str, default: f32| match std::env::var(name) {
+            Ok(value) => value.parse().unwrap_or_else(|_| panic!("invalid {} {:?}", name, value)),
+            Err(_) => default,
+        };
+
+        let defaults = Crt::default();
+        let mask = match std::env::var("CRT_MASK") {
+            Ok(name) => Mask::parse(&name).unwrap_or_else(|| panic!("unknown CRT_MASK {:?}", name)),
+            Err(_) => defaults.mask,
+        };
+        Some(Crt {
+            scanlines: number("CRT_SCANLINES", defaults.scanlines),
+            mask,
+            mask_strength: number("CRT_MASK_STRENGTH", defaults.mask_strength),
+            blur: number("CRT_BLUR", defaults.blur),
+            bloom: number("CRT_BLOOM", defaults.bloom),
+        })
+    }
+
+    /**
+     * Applies the effect to a picture whose `lines` source lines were scaled
+     * to output rows `top..top + height`, e.g. from `Scaler::placement`.
+     * Scanlines need at least two rows per source line and are left out of
+     * pictures that haven't been scaled up that far.
+     */
+    pub fn apply(&self, frame: &Frame, top: isize, height: usize, lines: usize) -> Frame {
+        let mut out = if self.blur > 0. {
+            blur_rows(frame, self.blur)
+        } else {
+            frame.clone()
+        };
+
+        if self.scanlines > 0. && lines > 0 && height >= lines * 2 {
+            for y in 0..out.height {
+                let row = y as isize - top;
+                if row < 0 || row >= height as isize {
+                    continue;
+                }
+                // Distance from the center of the source line, in lines.
+                let position = (row as f32 + 0.5) * lines as f32 / height as f32;
+                let distance = position.fract() - 0.5;
+                for x in 0..out.width {
+                    let mut pixel = out.get(x, y);
+                    for c in pixel.iter_mut() {
+                        // Brighter beams are wider.
+                        let sigma = 0.2 + 0.15 * num::clamp(*c, 0., 1.);
+                        let beam = (-distance * distance / (2. * sigma * sigma)).exp();
+                        *c *= 1. - self.scanlines * (1. - beam);
+                    }
+                    out.set(x, y, pixel);
+                }
+            }
+        }
+
+        if self.mask != Mask::None && self.mask_strength > 0. {
+            for y in 0..out.height {
+                for x in 0..out.width {
+                    let weights = self.mask.weights(x, y);
+                    let mut pixel = out.get(x, y);
+                    for c in 0..3 {
+                        pixel[c] *= 1. - self.mask_strength * (1. - weights[c]);
+                    }
+                    out.set(x, y, pixel);
+                }
+            }
+        }
+
+        if self.bloom > 0. {
+            // Glow from the unmasked picture, spread over a few source lines.
+            let mut bright = frame.clone();
+            for pixel in bright.pixels.iter_mut() {
+                for c in pixel.iter_mut() {
+                    *c = (*c - BLOOM_THRESHOLD).max(0.);
+                }
+            }
+            let sigma = 1.5 * height.max(1) as f32 / lines.max(1) as f32;
+            let glow = blur_columns(&blur_rows(&bright, sigma), sigma);
+            for (pixel, glow) in out.pixels.iter_mut().zip(&glow.pixels) {
+                for c in 0..3 {
+                    pixel[c] += self.bloom * glow[c];
+                }
+            }
+        }
+
+        out
+    }
+}
+
+/// Normalized Gaussian taps from `-radius..=radius`.
+fn gaussian(sigma: f32) -> Vec<f32> {
+    let radius = (sigma * 3.).ceil() as isize;
+    let taps = (-radius..=radius)
+        .map(|i| (-(i * i) as f32 / (2. * sigma * sigma)).exp())
+        .collect::<Vec<_>>();
+    let total = taps.iter().sum::<f32>();
+    taps.iter().map(|x| x / total).collect()
+}
+
+fn blur_rows(frame: &Frame, sigma: f32) -> Frame {
+    let taps = gaussian(sigma);
+    let radius = (taps.len() / 2) as isize;
+    let mut out = Frame::new(frame.width, frame.height);
+    for y in 0..frame.height {
+        let row = frame.row(y);
+        for x in 0..frame.width {
+            let mut sum = [0.; 3];
+            for (i, w) in taps.iter().enumerate() {
+                let sx = num::clamp(x as isize + i as isize - radius, 0, frame.width as isize - 1) as usize;
+                for c in 0..3 {
+                    sum[c] += row[sx][c] * w;
+                }
+            }
+            out.set(x, y, sum);
+        }
+    }
+    out
+}
+
+fn blur_columns(frame: &Frame, sigma: f32) -> Frame {
+    let taps = gaussian(sigma);
+    let radius = (taps.len() / 2) as isize;
+    let mut out = Frame::new(frame.width, frame.height);
+    for y in 0..frame.height {
+        for x in 0..frame.width {
+            let mut sum = [0.; 3];
+            for (i, w) in taps.iter().enumerate() {
+                let sy = num::clamp(y as isize + i as isize - radius, 0, frame.height as isize - 1) as usize;
+                let pixel = frame.get(x, sy);
+                for c in 0..3 {
+                    sum[c] += pixel[c] * w;
+                }
+            }
+            out.set(x, y, sum);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grey(width: usize, height: usize, value: f32) -> Frame {
+        let mut frame = Frame::new(width, height);
+        frame.pixels.iter_mut().for_each(|p| *p = [value; 3]);
+        frame
+    }
+
+    /// Every effect turned off.
+    fn off() -> Crt {
+        Crt { scanlines: 0., mask: Mask::None, mask_strength: 0., blur: 0., bloom: 0. }
+    }
+
+    #[test]
+    fn masks() {
+        assert_eq!(Mask::Aperture.weights(4, 0), [0., 1., 0.]);
+        assert_eq!(Mask::Slot.weights(0, 3), [0.; 3]);
+        // The next triad's dark row is two rows further on.
+        assert_eq!(Mask::Slot.weights(3, 3), [1., 0., 0.]);
+        assert_eq!(Mask::Slot.weights(3, 1), [0.; 3]);
+
+        let crt = Crt { mask: Mask::Aperture, mask_strength: 0.5, ..off() };
+        let out = crt.apply(&grey(3, 1, 0.8), 0, 1, 1);
+        assert_eq!(out.row(0), &[[0.8, 0.4, 0.4], [0.4, 0.8, 0.4], [0.4, 0.4, 0.8]][..]);
+    }
+
+    #[test]
+    fn scanlines() {
+        let crt = Crt { scanlines: 1., ..off() };
+        // Two source lines over eight rows, starting at row 1.
+        let out = crt.apply(&grey(1, 10, 1.), 1, 8, 2);
+        let column = (0..10).map(|y| out.get(0, y)[0]).collect::<Vec<_>>();
+        assert_eq!((column[0], column[9]), (1., 1.));
+        // Brightest at the middle of each line, darkest between them.
+        assert!(column[2] > column[1] && column[2] > column[4], "{:?}", column);
+        assert!(column[6] > column[5] && column[6] > column[8], "{:?}", column);
+        assert!(column[4] < 0.6, "{:?}", column);
+
+        // Left out when there's less than two rows a line.
+        let out = crt.apply(&grey(1, 8, 1.), 0, 6, 4);
+        assert!(out.pixels.iter().all(|p| *p == [1.; 3]));
+    }
+
+    #[test]
+    fn blur_and_bloom() {
+        assert!((gaussian(1.2).iter().sum::<f32>() - 1.).abs() < 1e-5);
+
+        let mut frame = grey(9, 9, 0.);
+        frame.set(4, 4, [1.; 3]);
+        let crt = Crt { bloom: 1., ..off() };
+        let out = crt.apply(&frame, 0, 9, 9);
+        // Light above the threshold spreads around it, keeping its share.
+        assert!(out.get(4, 5)[0] > 0. && out.get(5, 4)[0] > 0.);
+        let glow = out.pixels.iter().map(|p| p[0]).sum::<f32>() - 1.;
+        assert!((glow - (1. - BLOOM_THRESHOLD)).abs() < 0.05, "{}", glow);
+
+        // Blur only runs along the row.
+        let out = Crt { blur: 1., ..off() }.apply(&frame, 0, 9, 9);
+        assert!(out.get(3, 4)[0] > 0. && out.get(4, 3)[0] == 0.);
+    }
+}
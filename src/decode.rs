@@ -0,0 +1,961 @@
+use crate::capture::Capture;
+use crate::frame::Frame;
+use crate::resample;
+use crate::standard::Standard;
+
+/// Samples per NTSC scanline at the SMI sample rate.
+pub const LINE_SAMPLES: usize = 2654;
+/// Sync pulses longer than this (about 15µs) are vertical sync broad pulses.
+pub const BROAD_PULSE_SAMPLES: usize = 600;
+pub const SAMPLE_RATE_MHZ: f32 = 41.66;
+pub const SUBCARRIER_MHZ: f32 = 3.58;
+
+/// Which signals a capture carries, and therefore how many channels it has.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InputMode {
+    /// One channel, luma and chroma sharing the same signal.
+    Composite,
+    /// Two channels, Y (with sync) and C.
+    SVideo,
+    /// Three channels, Y (with sync), Pb and Pr.
+    Component,
+}
+
+impl InputMode {
+    /// Reads the `INPUT` environment variable, defaulting to composite.
+    pub fn from_env() -> InputMode {
+        match std::env::var("INPUT").as_ref().map(|x| x.as_str()) {
+            Ok("svideo") | Ok("yc") => InputMode::SVideo,
+            Ok("component") | Ok("ypbpr") => InputMode::Component,
+            Ok("composite") | Err(_) => InputMode::Composite,
+            Ok(other) => panic!("unknown INPUT mode {:?}", other),
+        }
+    }
+
+    pub fn channel_count(&self) -> usize {
+        match self {
+            InputMode::Composite => 1,
+            InputMode::SVideo => 2,
+            InputMode::Component => 3,
+        }
+    }
+}
+
+/// How the samples of a line become output pixels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sampling {
+    /// One pixel per `chunk_width` samples from the end of sync, covering
+    /// the whole line.
+    Boxcar,
+    /// The active line resampled to 720 pixels at 13.5MHz, per BT.601.
+    Bt601,
+    /// The active line resampled to square pixels, 640 wide for NTSC and
+    /// 768 for PAL.
+    SquarePixel,
+}
+
+impl Sampling {
+    /// Reads the `SAMPLING` environment variable, defaulting to BT.601.
+    pub fn from_env() -> Sampling {
+        match std::env::var("SAMPLING").as_ref().map(|x| x.as_str()) {
+            Ok("boxcar") => Sampling::Boxcar,
+            Ok("square") => Sampling::SquarePixel,
+            Ok("601") | Ok("bt601") | Err(_) => Sampling::Bt601,
+            Ok(other) => panic!("unknown SAMPLING {:?}", other),
+        }
+    }
+
+    /// Output pixels per line, the pixel clock in MHz, and how long after the
+    /// sync leading edge (0H) the first pixel is, in µs. None for boxcar.
+    pub fn geometry(&self, standard: Standard) -> Option<(usize, f32, f32)> {
+        // BT.601 starts the digital active line 122 (525-line) or 132
+        // (625-line) samples after 0H. Square pixels cover the same center.
+        let bt601_start = match standard {
+            Standard::Ntsc => 122. / 13.5,
+            Standard::Pal => 132. / 13.5,
+        };
+        let center = bt601_start + 720. / 13.5 / 2.;
+        match (self, standard) {
+            (Sampling::Boxcar, _) => None,
+            (Sampling::Bt601, _) => Some((720, 13.5, bt601_start)),
+            (Sampling::SquarePixel, Standard::Ntsc) => Some((640, 12.272727, center - 640. / 12.272727 / 2.)),
+            (Sampling::SquarePixel, Standard::Pal) => Some((768, 14.75, center - 768. / 14.75 / 2.)),
+        }
+    }
+}
+
+/// Sync and burst measurements gathered while decoding a field.
+#[derive(Clone, Debug, Default)]
+pub struct FieldStats {
+    pub lines: usize,
+    /// Lines where a horizontal sync pulse was found.
+    pub sync_lines: usize,
+    /// Mean level of the sync tip.
+    pub sync_level: f32,
+    /// None for input without a color burst.
+    pub burst: Option<BurstStats>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BurstStats {
+    pub amplitude: f32,
+    /// Mean phase against the decoder's carrier, in radians.
+    pub phase: f32,
+    /// Circular standard deviation of the phase, in radians.
+    pub phase_deviation: f32,
+}
+
+impl BurstStats {
+    /// Summarizes `(amplitude, phase)` measurements of bursts, as from
+    /// `Decoder::measure_burst`. Returns None if there are none.
+    pub fn from_bursts(bursts: &[(f32, f32)]) -> Option<BurstStats> {
+        if bursts.is_empty() {
+            return None;
+        }
+        let len = bursts.len() as f32;
+        let (sin, cos) = bursts.iter()
+            .fold((0f32, 0f32), |(s, c), (_, phase)| (s + phase.sin(), c + phase.cos()));
+        let resultant = (sin * sin + cos * cos).sqrt() / len;
+        Some(BurstStats {
+            amplitude: bursts.iter().map(|(amp, _)| amp).sum::<f32>() / len,
+            phase: sin.atan2(cos),
+            phase_deviation: (-2. * resultant.max(1e-6).ln()).sqrt(),
+        })
+    }
+}
+
+pub struct Field {
+    pub frame: Frame,
+    pub stats: FieldStats,
+    /// Sample offsets where the field's first line starts and its last line
+    /// ends.
+    pub start: usize,
+    pub end: usize,
+    /// Line number of the first row, when the field was found by its
+    /// vertical sync.
+    pub first_line: Option<usize>,
+    /// Whether this is the second (bottom) field of an interlaced frame,
+    /// when the field was found by its vertical sync. Progressive (240p)
+    /// sources repeat the same parity every field.
+    pub second: Option<bool>,
+}
+
+pub struct Decoder {
+    pub mode: InputMode,
+    pub standard: Standard,
+    pub sampling: Sampling,
+    /// Carrier phase offset, in samples.
+    pub shift: f32,
+    /// Samples averaged into a single output pixel.
+    pub chunk_width: usize,
+    pub line_samples: usize,
+}
+
+impl Decoder {
+    pub fn new(mode: InputMode) -> Decoder {
+        let shift = std::env::var("SHIFT").unwrap_or("7.0".to_string()).parse::<f32>().unwrap();
+        let standard = Standard::from_env();
+        Decoder {
+            mode,
+            standard,
+            sampling: Sampling::from_env(),
+            shift,
+            chunk_width: 12,
+            line_samples: standard.line_samples(),
+        }
+    }
+
+    pub fn carrier_freq(&self, x: usize) -> f32 {
+        (x as f32 + self.shift) * (2.0 * std::f32::consts::PI) * (SUBCARRIER_MHZ / SAMPLE_RATE_MHZ)
+    }
+
+    /// Decodes every field in a capture, measuring sync and burst along the
+    /// way. Fields start at their first active line when vertical sync is
+    /// found; otherwise the capture is split every `height` lines. With
+    /// `partial`, a field running past the end of the capture is padded with
+    /// blanking rather than dropped.
+    pub fn decode_fields(&self, capture: &Capture, height: usize, partial: bool) -> Vec<Field> {
+        assert_eq!(capture.channel_count(), self.mode.channel_count(),
+            "capture channel count doesn't match {:?}", self.mode);
+
+        // Sync is always carried on the first channel (composite or Y).
+        let channels = (0..capture.channel_count())
+            .map(|c| capture.levels(c))
+            .collect::<Vec<_>>();
+
+        // Component sources may be HD, so their line length is measured
+        // rather than assumed.
+        let line_samples = match self.mode {
+            InputMode::Component => measure_line_period(&channels[0]).unwrap_or(self.line_samples),
+            _ => self.line_samples,
+        };
+        let starts = assemble_lines(&channels[0], capture.blocks(), line_samples, self.chunk_width);
+
+        // Resampling windows are defined for SD timing, so HD component
+        // sources fall back to boxcar pixels.
+        let sd = (line_samples as f32 - self.line_samples as f32).abs() < self.line_samples as f32 * 0.05;
+        let geometry = if sd { self.sampling.geometry(self.standard) } else { None };
+
+        // Index of each field's first row into `starts`.
+        let vsyncs = find_vsyncs(&channels[0], line_samples);
+        let active_offset = self.standard.first_active_line() - self.standard.vsync_line();
+        let firsts = if vsyncs.is_empty() {
+            (0..starts.len()).step_by(height).map(|n| (n, None, None)).collect::<Vec<_>>()
+        } else {
+            vsyncs.iter()
+                .filter_map(|v| starts.iter().position(|s| s >= v).map(|n| (n, *v)))
+                .map(|(n, v)| {
+                    // The second field's vertical sync starts half a line
+                    // later relative to horizontal sync. Measure against a
+                    // line before the equalizing pulses.
+                    let reference = find_sync_fall(&channels[0], starts[n.saturating_sub(PARITY_REFERENCE_LINES)]);
+                    let phase = ((v as isize - reference as isize).rem_euclid(line_samples as isize)) as f32 / line_samples as f32;
+                    let second = (phase - 0.5).abs() < 0.25;
+                    (n + active_offset, Some(self.standard.first_active_line()), Some(second))
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let mut fields = vec![];
+        for (first, first_line, second) in firsts {
+            let last = first + height;
+            if first >= starts.len() || (!partial && (last > starts.len() || starts[last - 1] + line_samples > channels[0].len())) {
+                continue;
+            }
+            let lines = &starts[first..std::cmp::min(last, starts.len())];
+            let mut field = self.decode_lines(&channels, capture.blocks(), lines, line_samples, height, geometry);
+            field.first_line = first_line;
+            field.second = second;
+            fields.push(field);
+        }
+        if fields.is_empty() && partial {
+            let lines = &starts[..std::cmp::min(height, starts.len())];
+            fields.push(self.decode_lines(&channels, capture.blocks(), lines, line_samples, height, geometry));
+        }
+        fields
+    }
+
+    /// Decodes the lines starting at `starts` into an image `height` rows
+    /// tall, resampled to `geometry` (see `Sampling::geometry`) if given.
+    /// `blocks` are the capture's DMA blocks.
+    fn decode_lines(
+        &self,
+        channels: &[Vec<f32>],
+        blocks: &[std::ops::Range<usize>],
+        starts: &[usize],
+        line_samples: usize,
+        height: usize,
+        geometry: Option<(usize, f32, f32)>,
+    ) -> Field {
+        let width = match geometry {
+            Some((pixels, _, _)) => pixels,
+            None => line_samples / self.chunk_width,
+        };
+        let mut frame = Frame::new(width, height);
+        let mut stats = FieldStats::default();
+        let mut sync_levels = vec![];
+        let mut bursts = vec![];
+        for (y, &start) in starts.iter().take(height).enumerate() {
+            let lines = self.prepare_line(channels, start, line_samples);
+            let tri_level = is_tri_level(&lines[0]);
+
+            stats.lines += 1;
+            // Lines are laid out from the start of their block, and those
+            // without sync start right where they were cut.
+            let block = blocks.iter().find(|x| x.contains(&start)).map_or(0, |x| x.start);
+            if (start - block) % line_samples != 0 {
+                stats.sync_lines += 1;
+                let tip = &channels[0][start.saturating_sub(SYNC_LOOKBACK)..start];
+                sync_levels.extend(tip.iter().filter(|x| **x < -40.));
+            }
+            match self.mode {
+                InputMode::Composite => bursts.push(self.measure_burst(&lines[0])),
+                InputMode::SVideo => bursts.push(self.measure_burst(&lines[1])),
+                InputMode::Component => {}
+            }
+
+            match geometry {
+                None => {
+                    for x in 0..width {
+                        let range = x * self.chunk_width..(x + 1) * self.chunk_width;
+                        frame.set(x, y, self.decode_window(&lines, range, tri_level));
+                    }
+                }
+                Some((pixels, clock, offset)) => {
+                    // Position of the first pixel relative to the line, which
+                    // starts at the end of sync.
+                    let zero_h = find_sync_fall(&channels[0], start) as f32 - start as f32;
+                    let first = zero_h + offset * SAMPLE_RATE_MHZ;
+                    let step = SAMPLE_RATE_MHZ / clock;
+
+                    // Decode every sample under the filter, each from a window
+                    // centered on it.
+                    let radius = (resample::LANCZOS_LOBES * step.max(1.)).ceil() as isize + 1;
+                    let lo = num::clamp(first.floor() as isize - radius, 0, line_samples as isize) as usize;
+                    let hi = num::clamp((first + pixels as f32 * step).ceil() as isize + radius, 0, line_samples as isize) as usize;
+                    let half = self.chunk_width / 2;
+                    let rgb = (lo..hi).map(|n| {
+                        let window_start = num::clamp(n.saturating_sub(half), 0, line_samples - self.chunk_width);
+                        self.decode_window(&lines, window_start..window_start + self.chunk_width, tri_level)
+                    }).collect::<Vec<_>>();
+
+                    let row = resample::resample_line(&rgb, first - lo as f32, step, pixels);
+                    frame.pixels[y * width..(y + 1) * width].copy_from_slice(&row);
+                }
+            }
+        }
+
+        if !sync_levels.is_empty() {
+            stats.sync_level = sync_levels.iter().sum::<f32>() / (sync_levels.len() as f32);
+        }
+        stats.burst = BurstStats::from_bursts(&bursts);
+
+        Field {
+            frame,
+            stats,
+            start: starts.first().cloned().unwrap_or(0),
+            end: starts.last().map(|s| s + line_samples).unwrap_or(0),
+            first_line: None,
+            second: None,
+        }
+    }
+
+    /// Measures the amplitude and phase of the color burst in a line that
+    /// starts at the end of sync.
+    pub fn measure_burst(&self, line: &[f32]) -> (f32, f32) {
+        let burst = &line[BURST_WINDOW];
+        let (mut i, mut q) = (0., 0.);
+        for (n, x) in burst.iter().enumerate() {
+            let phase = self.carrier_freq(n + BURST_WINDOW.start);
+            i += x * phase.sin();
+            q += x * phase.cos();
+        }
+        let len = burst.len() as f32;
+        (2. * (i * i + q * q).sqrt() / len, q.atan2(i))
+    }
+
+    /// Extracts one line from every channel, starting at the end of sync,
+    /// and restores each channel's DC level as the input mode requires.
+    pub fn prepare_line(&self, channels: &[Vec<f32>], start: usize, line_samples: usize) -> Vec<Vec<f32>> {
+        let mut lines = channels.iter()
+            .map(|c| line_at(c, start, line_samples))
+            .collect::<Vec<_>>();
+
+        match self.mode {
+            InputMode::Composite => {}
+            InputMode::SVideo => {
+                // C has no DC component of its own; drop whatever bias the ADC adds.
+                let mean = lines[1].iter().sum::<f32>() / (line_samples as f32);
+                lines[1].iter_mut().for_each(|x| *x -= mean);
+            }
+            InputMode::Component => {
+                // Clamp every channel so its back porch sits at zero.
+                for line in lines.iter_mut() {
+                    let porch = &line[CLAMP_WINDOW];
+                    let level = porch.iter().sum::<f32>() / (porch.len() as f32);
+                    line.iter_mut().for_each(|x| *x -= level);
+                }
+            }
+        }
+        lines
+    }
+
+    /// Converts the samples in `range` of a prepared line into RGB.
+    fn decode_window(&self, lines: &[Vec<f32>], range: std::ops::Range<usize>, tri_level: bool) -> [f32; 3] {
+        let chunks = lines.iter().map(|l| &l[range.clone()]).collect::<Vec<_>>();
+        match self.mode {
+            InputMode::Composite => self.decode_chunk(chunks[0], chunks[0], range.start),
+            InputMode::SVideo => self.decode_chunk(chunks[0], chunks[1], range.start),
+            InputMode::Component => decode_component_chunk(chunks[0], chunks[1], chunks[2], tri_level),
+        }
+    }
+
+    /// Converts one chunk of samples into RGB. For composite input `luma` and
+    /// `chroma` are the same samples.
+    fn decode_chunk(&self, luma: &[f32], chroma: &[f32], chunk_index: usize) -> [f32; 3] {
+        let y_sample = luma.iter().sum::<f32>() / (luma.len() as f32);
+        let (i_sample, q_sample, i_amp, q_amp) = self.demodulate(chroma, chunk_index);
+
+        let y_clamped = num::clamp(y_sample, 0., 140.) / 140.;
+        let i_clamped = (num::clamp(i_sample, -60., 60.) / 60.) * i_amp;
+        let q_clamped = (num::clamp(q_sample, -60., 60.) / 60.) * q_amp;
+
+        yiq_to_rgb(y_clamped, i_clamped, q_clamped)
+    }
+
+    /// Calculates I and Q against the carrier frequency, along with how much
+    /// carrier is present in each, in the 0.0..1.0 range.
+    pub fn demodulate(&self, samples: &[f32], chunk_index: usize) -> (f32, f32, f32, f32) {
+        let len = samples.len() as f32;
+        let i_mixed = samples.iter()
+            .enumerate()
+            .map(|(i, x)| x * self.carrier_freq(i + chunk_index).sin() * 4.)
+            .collect::<Vec<_>>();
+        let q_mixed = samples.iter()
+            .enumerate()
+            .map(|(i, x)| x * self.carrier_freq(i + chunk_index).cos() * 4.)
+            .collect::<Vec<_>>();
+
+        let amp = |mixed: &[f32]| {
+            let amps = mixed.iter()
+                .map(|y| (y * 100.) as u32) // two digit precision
+                .collect::<Vec<_>>();
+            let input = (amps.iter().max().unwrap() - amps.iter().min().unwrap()) as f32;
+            num::clamp(input / 80000., 0., 1.0)
+        };
+
+        (
+            i_mixed.iter().sum::<f32>() / len,
+            q_mixed.iter().sum::<f32>() / len,
+            amp(&i_mixed),
+            amp(&q_mixed),
+        )
+    }
+}
+
+/// Converts one chunk of component samples into RGB, using BT.709 for HD
+/// (tri-level sync) sources and BT.601 otherwise.
+pub fn decode_component_chunk(y: &[f32], pb: &[f32], pr: &[f32], tri_level: bool) -> [f32; 3] {
+    let avg = |samples: &[f32]| samples.iter().sum::<f32>() / (samples.len() as f32);
+
+    // Y spans 0..140 and Pb/Pr span -70..70, so all scale by the same amount.
+    let y = num::clamp(avg(y), 0., 140.) / 140.;
+    let pb = num::clamp(avg(pb), -70., 70.) / 140.;
+    let pr = num::clamp(avg(pr), -70., 70.) / 140.;
+
+    if tri_level {
+        [
+            y + (1.5748 * pr),
+            y - (0.1873 * pb) - (0.4681 * pr),
+            y + (1.8556 * pb),
+        ]
+    } else {
+        [
+            y + (1.402 * pr),
+            y - (0.344136 * pb) - (0.714136 * pr),
+            y + (1.772 * pb),
+        ]
+    }
+}
+
+pub fn yiq_to_rgb(y: f32, i: f32, q: f32) -> [f32; 3] {
+    [
+        y + (2.4563 * i) + (1.6190 * q),
+        y - (0.2721 * i) - (0.6474 * q),
+        y - (1.1070 * i) + (1.7046 * q),
+    ]
+}
+
+/// Finds where the horizontal sync pulse of a line ends, i.e. the start of
+/// the back porch. Returns 0 if the line has no sync pulse.
+pub fn find_sync_rise(line: &[f32], chunk_width: usize) -> usize {
+    let avg = |samples: &[f32]| samples.iter().sum::<f32>() / (chunk_width as f32);
+
+    let i_fall = line.windows(chunk_width).enumerate().rev().find_map(|(i, samples)| {
+        let sample = avg(samples);
+        if sample < -40. && sample > -100. {
+            Some(i)
+        } else {
+            None
+        }
+    }).unwrap_or(0);
+    line.windows(chunk_width).enumerate().find_map(|(i, samples)| {
+        if i < i_fall {
+            return None;
+        }
+        if avg(samples) > -5. {
+            Some(i)
+        } else {
+            None
+        }
+    }).unwrap_or(0)
+}
+
+/// Samples after the end of sync holding the color burst, skipping the
+/// breezeway and the burst's ramps.
+pub const BURST_WINDOW: std::ops::Range<usize> = 30..120;
+
+/// Lines before vertical sync whose horizontal sync is used to tell the two
+/// fields of an interlaced frame apart.
+pub const PARITY_REFERENCE_LINES: usize = 8;
+
+/// Samples before the end of sync that are searched for the sync tip and
+/// leading edge; a little longer than a 4.7µs sync pulse.
+pub const SYNC_LOOKBACK: usize = 240;
+
+/// Samples after the end of sync used to measure a line's blanking level.
+/// This starts 1µs in, past the positive half of a tri-level sync pulse, and
+/// fits within both SD and HD back porches.
+pub const CLAMP_WINDOW: std::ops::Range<usize> = 42..84;
+
+/// Level below which a sample counts as sync, about -14 IRE: a third of the
+/// way from blanking down to the sync tip at about -56.
+pub const SYNC_THRESHOLD: f32 = -20.;
+
+/// Finds the sync leading edge (0H) of the line whose sync ends at `rise`,
+/// where the signal first drops below `SYNC_THRESHOLD`. Falls back to the
+/// nominal 4.7µs sync width if no edge is found.
+pub fn find_sync_fall(sync: &[f32], rise: usize) -> usize {
+    let nominal = rise.saturating_sub((4.7 * SAMPLE_RATE_MHZ) as usize);
+    let from = rise.saturating_sub(SYNC_LOOKBACK);
+    let mut seen_sync = false;
+    for i in (from..rise).rev() {
+        if sync[i] < SYNC_THRESHOLD {
+            seen_sync = true;
+        } else if seen_sync {
+            return i + 1;
+        }
+    }
+    nominal
+}
+
+/// Level the positive half of a tri-level sync pulse has to pass. It's
+/// nominally +300mV, about 59 on this scale where 140 is 714mV, so pulses
+/// coming in at a third of that are still found.
+pub const TRI_LEVEL_THRESHOLD: f32 = 20.;
+
+/// Whether a line, starting at the end of its sync pulse, uses HD tri-level
+/// sync, i.e. the negative sync pulse is followed by a positive one.
+pub fn is_tri_level(line: &[f32]) -> bool {
+    line.iter().take(CLAMP_WINDOW.start).any(|x| *x > TRI_LEVEL_THRESHOLD)
+}
+
+/// Finds the median distance between sync pulses, which is the line length
+/// in samples. Returns None if there's too little sync.
+pub fn measure_line_period(sync: &[f32]) -> Option<usize> {
+    let mut falls = vec![];
+    let mut low = 0;
+    for (i, x) in sync.iter().enumerate() {
+        if *x < -40. {
+            low += 1;
+            // Require a few samples below threshold to ignore noise.
+            if low == 8 {
+                falls.push(i - 7);
+            }
+        } else {
+            low = 0;
+        }
+    }
+
+    let mut periods = falls.windows(2)
+        .map(|w| w[1] - w[0])
+        .filter(|p| *p > 500 && *p < 4000)
+        .collect::<Vec<_>>();
+    if periods.len() < 4 {
+        return None;
+    }
+    periods.sort();
+    Some(periods[periods.len() / 2])
+}
+
+/// Finds the start of each vertical sync, i.e. the first broad pulse of each
+/// run of them.
+pub fn find_vsyncs(sync: &[f32], line_samples: usize) -> Vec<usize> {
+    let mut vsyncs: Vec<usize> = vec![];
+    let mut last_broad: Option<usize> = None;
+    let mut low = 0;
+    for (i, x) in sync.iter().enumerate() {
+        if *x < -40. {
+            low += 1;
+            if low == BROAD_PULSE_SAMPLES {
+                let start = i + 1 - low;
+                // Broad pulses come every half line for a few lines.
+                if last_broad.map_or(true, |b| start - b > line_samples * 4) {
+                    vsyncs.push(start);
+                }
+                last_broad = Some(start);
+            }
+        } else {
+            low = 0;
+        }
+    }
+    vsyncs
+}
+
+/**
+ * Splits each of a signal's `blocks` (see `Capture::blocks`) into
+ * fixed-length scanlines and returns the offset at which each one starts,
+ * aligned to the end of its sync pulse. Sync is found afresh in every
+ * block, and lines that would run on past the end of a block into the gap
+ * after it are left out.
+ */
+pub fn assemble_lines(sync: &[f32], blocks: &[std::ops::Range<usize>], line_samples: usize, chunk_width: usize) -> Vec<usize> {
+    let mut starts = vec![];
+    for block in blocks {
+        starts.extend(sync[block.clone()].chunks(line_samples)
+            .enumerate()
+            .map(|(n, line)| block.start + n * line_samples + find_sync_rise(line, chunk_width))
+            .filter(|start| block.end == sync.len() || start + line_samples <= block.end));
+    }
+    starts
+}
+
+/**
+ * Estimates the time between each of `blocks` and the one before, in
+ * samples, from where sync falls on either side: lines carry on at the
+ * same rate through the gap, so the last sync before it and the first
+ * after are a whole number of lines apart. That only pins the gap down to
+ * a multiple of `line_samples`, so it's given modulo that. `starts` are
+ * the lines from `assemble_lines`. Blocks without a line either side, and
+ * the first block, have no estimate.
+ */
+pub fn block_gaps(sync: &[f32], blocks: &[std::ops::Range<usize>], starts: &[usize], line_samples: usize) -> Vec<Option<usize>> {
+    // Leading edges of the lines in a block, leaving out any cut off by its
+    // start.
+    let falls = |block: &std::ops::Range<usize>| starts.iter()
+        .filter(|x| block.contains(x))
+        .map(|x| find_sync_fall(sync, *x))
+        .filter(|x| *x > block.start)
+        .collect::<Vec<_>>();
+    let mut gaps = vec![None];
+    for pair in blocks.windows(2) {
+        let gap = match (falls(&pair[0]).last(), falls(&pair[1]).first()) {
+            (Some(before), Some(after)) => {
+                let samples = (pair[0].end - before) + (after - pair[1].start);
+                Some((-(samples as isize)).rem_euclid(line_samples as isize) as usize)
+            }
+            _ => None,
+        };
+        gaps.push(gap);
+    }
+    gaps.truncate(blocks.len());
+    gaps
+}
+
+/// Copies `len` samples starting at `start`, padding with blanking past the
+/// end of the signal.
+pub fn line_at(signal: &[f32], start: usize, len: usize) -> Vec<f32> {
+    let mut line = vec![0.; len];
+    let end = std::cmp::min(signal.len(), start + len);
+    if start < end {
+        line[..end - start].copy_from_slice(&signal[start..end]);
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::{ADC_SCALE, ADC_ZERO};
+
+    const LINES: usize = 10;
+    const SYNC_SAMPLES: usize = 196;
+    const ACTIVE_START: usize = 400;
+
+    /// Raw SMI word for a signal level, the inverse of `volt_decode`.
+    fn word(level: f32) -> u16 {
+        ((ADC_ZERO as f32 - level * ADC_SCALE / 300.).round() as u16) << 4
+    }
+
+    fn svideo_decoder() -> Decoder {
+        Decoder {
+            mode: InputMode::SVideo,
+            standard: Standard::Ntsc,
+            sampling: Sampling::Boxcar,
+            shift: 7.,
+            chunk_width: 12,
+            line_samples: LINE_SAMPLES,
+        }
+    }
+
+    /// An S-Video capture of grey lines, with sync only on Y and chroma at
+    /// `phase` against the decoder's carrier on C, and on Y if `luma_phase`
+    /// is given. There's a line to spare after `LINES`.
+    fn svideo_capture(decoder: &Decoder, phase: f32, luma_phase: Option<f32>) -> Capture {
+        let mut sync_line = vec![0.; LINE_SAMPLES];
+        sync_line[..SYNC_SAMPLES].iter_mut().for_each(|x| *x = -56.);
+        let rise = find_sync_rise(&sync_line, decoder.chunk_width);
+        let chroma = |i: usize, phase: f32| {
+            if i < ACTIVE_START { 0. } else { 30. * (decoder.carrier_freq(i - rise) + phase).sin() }
+        };
+
+        let mut raw = vec![];
+        for _ in 0..LINES + 1 {
+            for i in 0..LINE_SAMPLES {
+                let y = if i < SYNC_SAMPLES {
+                    -56.
+                } else if i < ACTIVE_START {
+                    0.
+                } else {
+                    70. + luma_phase.map_or(0., |phase| chroma(i, phase))
+                };
+                raw.push(word(y));
+                raw.push(word(chroma(i, phase)));
+            }
+        }
+        Capture::from_interleaved(&raw, 2)
+    }
+
+    /// The middle pixel of the first line.
+    fn middle(decoder: &Decoder, capture: &Capture) -> [f32; 3] {
+        let frame = decoder.decode_fields(capture, LINES, true).remove(0).frame;
+        frame.pixels[frame.width / 2]
+    }
+
+    #[test]
+    fn svideo_sync_from_y() {
+        let decoder = svideo_decoder();
+        let capture = svideo_capture(&decoder, 0., None);
+        assert_eq!(capture.channel_count(), 2);
+        assert_eq!(capture.len(), (LINES + 1) * LINE_SAMPLES);
+
+        // C has no sync of its own, so lines can only be aligned to Y's.
+        let starts = assemble_lines(&capture.levels(0), capture.blocks(), LINE_SAMPLES, decoder.chunk_width);
+        for (n, start) in starts.into_iter().enumerate() {
+            assert!((SYNC_SAMPLES - 1..=SYNC_SAMPLES).contains(&(start - n * LINE_SAMPLES)));
+        }
+        assert_eq!(assemble_lines(&capture.levels(1), capture.blocks(), LINE_SAMPLES, decoder.chunk_width)[0], 0);
+
+        let field = decoder.decode_fields(&capture, LINES, true).remove(0);
+        assert_eq!((field.frame.width, field.frame.height), (LINE_SAMPLES / decoder.chunk_width, LINES));
+        let stats = &field.stats;
+        assert_eq!(stats.lines, LINES);
+        assert_eq!(stats.sync_lines, LINES);
+        assert!((stats.sync_level + 56.).abs() < 1.);
+    }
+
+    #[test]
+    fn svideo_hue_from_c() {
+        use std::f32::consts::FRAC_PI_2;
+        let decoder = svideo_decoder();
+
+        // In phase with the carrier is I, towards red.
+        let [r, g, b] = middle(&decoder, &svideo_capture(&decoder, 0., None));
+        assert!(r > g + 0.2 && r > b + 0.2, "{:?}", [r, g, b]);
+        // In quadrature is Q, towards magenta.
+        let [r, g, b] = middle(&decoder, &svideo_capture(&decoder, FRAC_PI_2, None));
+        assert!(b >= r && g < r - 0.05, "{:?}", [r, g, b]);
+
+        // Chroma on Y is ignored in favour of C's.
+        let [r, g, b] = middle(&decoder, &svideo_capture(&decoder, 0., Some(FRAC_PI_2)));
+        assert!(r > g + 0.2 && r > b + 0.2, "{:?}", [r, g, b]);
+    }
+
+    /// A component capture of lines `period` samples long, each a negative
+    /// sync pulse, followed by a positive one if `tri_level`, then a flat
+    /// picture of `level` on every channel. Pb and Pr carry ADC bias.
+    fn component_capture(period: usize, tri_level: bool, level: [f32; 3]) -> Capture {
+        let mut raw = vec![];
+        for _ in 0..LINES + 1 {
+            for i in 0..period {
+                let sync = if i < 25 {
+                    Some(-56.)
+                } else if i < 50 && tri_level {
+                    Some(59.)
+                } else {
+                    None
+                };
+                let active = i >= 300;
+                let y = sync.unwrap_or(if active { level[0] } else { 0. });
+                let pb = 10. + if active { level[1] } else { 0. };
+                let pr = -5. + if active { level[2] } else { 0. };
+                raw.extend(&[word(y), word(pb), word(pr)]);
+            }
+        }
+        Capture::from_interleaved(&raw, 3)
+    }
+
+    #[test]
+    fn measures_line_period() {
+        let capture = component_capture(2200, true, [70., 0., 0.]);
+        assert_eq!(measure_line_period(&capture.levels(0)), Some(2200));
+        // The chroma channels have no sync at all.
+        assert_eq!(measure_line_period(&capture.levels(1)), None);
+    }
+
+    #[test]
+    fn detects_tri_level() {
+        let mut line = vec![0.; 200];
+        assert!(!is_tri_level(&line));
+        line[..25].iter_mut().for_each(|x| *x = 59.);
+        assert!(is_tri_level(&line));
+        // Still found when the pulse comes in well short of nominal.
+        line[..25].iter_mut().for_each(|x| *x = 30.);
+        assert!(is_tri_level(&line));
+        // A positive level past the clamp window is picture, not sync.
+        let mut line = vec![0.; 200];
+        line[CLAMP_WINDOW.end..].iter_mut().for_each(|x| *x = 100.);
+        assert!(!is_tri_level(&line));
+    }
+
+    #[test]
+    fn component_colorimetry() {
+        let decoder = Decoder {
+            mode: InputMode::Component,
+            standard: Standard::Ntsc,
+            sampling: Sampling::Boxcar,
+            shift: 7.,
+            chunk_width: 12,
+            line_samples: LINE_SAMPLES,
+        };
+        let level = [70., 0., 35.];
+        let close = |a: [f32; 3], b: [f32; 3]| a.iter().zip(&b).all(|(x, y)| (x - y).abs() < 0.02);
+
+        // HD is measured from its sync and decoded as BT.709.
+        let frame = decoder.decode_fields(&component_capture(2200, true, level), LINES, true).remove(0).frame;
+        assert_eq!(frame.width, 2200 / decoder.chunk_width);
+        let middle = frame.pixels[frame.width / 2];
+        assert!(close(middle, [0.5 + 1.5748 * 0.25, 0.5 - 0.4681 * 0.25, 0.5]), "{:?}", middle);
+
+        // SD with ordinary sync is BT.601, with Pb and Pr clamped to the porch.
+        let frame = decoder.decode_fields(&component_capture(LINE_SAMPLES, false, level), LINES, true).remove(0).frame;
+        let middle = frame.pixels[frame.width / 2];
+        assert!(close(middle, [0.5 + 1.402 * 0.25, 0.5 - 0.714136 * 0.25, 0.5]), "{:?}", middle);
+    }
+
+    /// A composite capture of `lines` grey lines, where the lines in `broad`
+    /// are vertical sync, with a broad pulse every half line.
+    fn composite_capture(lines: usize, broad: &[usize]) -> Capture {
+        let mut raw = vec![];
+        for n in 0..lines {
+            for i in 0..LINE_SAMPLES {
+                let level = if broad.contains(&n) {
+                    if i % (LINE_SAMPLES / 2) < 1100 { -56. } else { 0. }
+                } else if i < SYNC_SAMPLES {
+                    -56.
+                } else if i < ACTIVE_START {
+                    0.
+                } else {
+                    70.
+                };
+                raw.push(word(level));
+            }
+        }
+        Capture::from_interleaved(&raw, 1)
+    }
+
+    #[test]
+    fn finds_vsyncs() {
+        let capture = composite_capture(30, &[2, 3, 4, 20, 21, 22]);
+        let vsyncs = find_vsyncs(&capture.levels(0), LINE_SAMPLES);
+        assert_eq!(vsyncs, vec![2 * LINE_SAMPLES, 20 * LINE_SAMPLES]);
+        assert!(find_vsyncs(&composite_capture(10, &[]).levels(0), LINE_SAMPLES).is_empty());
+    }
+
+    #[test]
+    fn splits_fields() {
+        let decoder = Decoder {
+            mode: InputMode::Composite,
+            ..svideo_decoder()
+        };
+
+        // Without vertical sync the capture is cut every `height` lines.
+        let capture = composite_capture(2 * LINES + 3, &[]);
+        let fields = decoder.decode_fields(&capture, LINES, false);
+        assert_eq!(fields.len(), 2);
+        assert_eq!(fields[1].start - fields[0].start, LINES * LINE_SAMPLES);
+        assert!(fields.iter().all(|x| x.first_line.is_none()));
+        // The lines left over make a partial field, padded with blanking.
+        let fields = decoder.decode_fields(&capture, LINES, true);
+        assert_eq!(fields.len(), 3);
+        assert_eq!(fields[2].frame.height, LINES);
+        assert_eq!(fields[2].stats.lines, 3);
+
+        // With it, fields start at the first active line after it.
+        let offset = Standard::Ntsc.first_active_line() - Standard::Ntsc.vsync_line();
+        let capture = composite_capture(offset + LINES + 4, &[3, 4, 5]);
+        let fields = decoder.decode_fields(&capture, LINES, false);
+        assert_eq!(fields.len(), 1);
+        assert_eq!(fields[0].first_line, Some(Standard::Ntsc.first_active_line()));
+        assert_eq!(fields[0].start / LINE_SAMPLES, 3 + offset);
+    }
+
+    #[test]
+    fn sampling_geometry() {
+        assert_eq!(Sampling::Boxcar.geometry(Standard::Ntsc), None);
+        assert_eq!(Sampling::Bt601.geometry(Standard::Pal), Some((720, 13.5, 132. / 13.5)));
+        // Square pixels share BT.601's center.
+        for &standard in &[Standard::Ntsc, Standard::Pal] {
+            let center = |(pixels, clock, start): (usize, f32, f32)| start + pixels as f32 / clock / 2.;
+            let bt601 = center(Sampling::Bt601.geometry(standard).unwrap());
+            let square = center(Sampling::SquarePixel.geometry(standard).unwrap());
+            assert!((bt601 - square).abs() < 1e-3, "{:?}", standard);
+        }
+    }
+
+    #[test]
+    fn resamples_from_sync_edge() {
+        // Black lines that turn white 30µs after the sync leading edge, with
+        // no chroma.
+        let edge = (30. * SAMPLE_RATE_MHZ) as usize;
+        let mut raw = vec![];
+        for _ in 0..LINES + 1 {
+            for i in 0..LINE_SAMPLES {
+                let level = if i < SYNC_SAMPLES { -56. } else if i < edge { 0. } else { 140. };
+                raw.extend(&[word(level), word(0.)]);
+            }
+        }
+        let capture = Capture::from_interleaved(&raw, 2);
+        let brightness = |p: [f32; 3]| (p[0] + p[1] + p[2]) / 3.;
+
+        for &sampling in &[Sampling::Bt601, Sampling::SquarePixel] {
+            let decoder = Decoder { sampling, ..svideo_decoder() };
+            let frame = decoder.decode_fields(&capture, LINES, true).remove(0).frame;
+            let (pixels, clock, start) = sampling.geometry(Standard::Ntsc).unwrap();
+            assert_eq!(frame.width, pixels);
+
+            // The first pixel is `start` µs after 0H.
+            let x = ((30. - start) * clock) as usize;
+            let row = frame.row(LINES / 2);
+            assert!(brightness(row[x - 3]) < 0.1, "{:?} {:?}", sampling, &row[x - 3..x + 3]);
+            assert!(brightness(row[x + 3]) > 0.9, "{:?} {:?}", sampling, &row[x - 3..x + 3]);
+        }
+    }
+
+    #[test]
+    fn tells_fields_apart() {
+        // Broad pulses every half line from line 12, or from halfway along
+        // it for the second field.
+        let capture = |second: bool| {
+            let vsync = 12 * LINE_SAMPLES + if second { LINE_SAMPLES / 2 } else { 0 };
+            let mut raw = vec![];
+            for n in 0..LINES + 40 {
+                for i in 0..LINE_SAMPLES {
+                    let at = n * LINE_SAMPLES + i;
+                    let level = if at >= vsync && at < vsync + 3 * LINE_SAMPLES {
+                        if (at - vsync) % (LINE_SAMPLES / 2) < 1100 { -56. } else { 0. }
+                    } else if i < SYNC_SAMPLES {
+                        -56.
+                    } else {
+                        0.
+                    };
+                    raw.push(word(level));
+                }
+            }
+            Capture::from_interleaved(&raw, 1)
+        };
+        let decoder = Decoder { mode: InputMode::Composite, ..svideo_decoder() };
+
+        for &second in &[false, true] {
+            let fields = decoder.decode_fields(&capture(second), LINES, false);
+            assert_eq!(fields.len(), 1);
+            assert_eq!(fields[0].second, Some(second));
+        }
+        // Fields cut without vertical sync have no parity.
+        let fields = decoder.decode_fields(&composite_capture(LINES + 1, &[]), LINES, false);
+        assert_eq!(fields[0].second, None);
+    }
+
+    #[test]
+    fn lines_and_gaps_across_blocks() {
+        const L: usize = LINE_SAMPLES;
+        let mut sync = vec![0.; 11 * L];
+        for n in 0..11 {
+            sync[n * L..n * L + SYNC_SAMPLES].iter_mut().for_each(|x| *x = -56.);
+        }
+        // The second block picks up 600 samples into line 4, L + 100 after
+        // the first ends.
+        let blocks = [0..3 * L + 500, 4 * L + 600..10 * L + 300];
+
+        let starts = assemble_lines(&sync, &blocks, L, 12);
+        let lines = starts.iter().map(|x| x / L).collect::<Vec<_>>();
+        // Line 3 and 10 run on past the end of their blocks.
+        assert_eq!(lines, vec![0, 1, 2, 5, 6, 7, 8, 9]);
+        for start in &starts {
+            assert!((SYNC_SAMPLES - 1..=SYNC_SAMPLES).contains(&(start % L)));
+        }
+
+        assert_eq!(block_gaps(&sync, &blocks, &starts, L), vec![None, Some(100)]);
+        assert_eq!(block_gaps(&sync, &blocks[..1], &starts, L), vec![None]);
+    }
+}
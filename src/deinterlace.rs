@@ -0,0 +1,243 @@
+use crate::frame::Frame;
+
+/// Largest per-channel difference between fields of the same parity that is
+/// still treated as a static picture by the motion-adaptive mode.
+pub const MOTION_THRESHOLD: f32 = 0.05;
+
+/// How fields are combined into progressive pictures.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    /// Line doubling, with each field offset by half a line to its parity.
+    Bob,
+    /// Interleaves each field with the one before it.
+    Weave,
+    /// Weaves, then averages neighbouring lines to hide combing.
+    Blend,
+    /// Weaves where the picture is still and bobs where it moves.
+    Adaptive,
+}
+
+impl Mode {
+    pub fn parse(name: &str) -> Option<Mode> {
+        match name {
+            "bob" => Some(Mode::Bob),
+            "weave" => Some(Mode::Weave),
+            "blend" => Some(Mode::Blend),
+            "adaptive" | "motion" => Some(Mode::Adaptive),
+            _ => None,
+        }
+    }
+
+    /// Reads the `DEINTERLACE` environment variable. Returns None when it
+    /// isn't set.
+    pub fn from_env() -> Option<Mode> {
+        let name = std::env::var("DEINTERLACE").ok()?;
+        Some(Mode::parse(&name).unwrap_or_else(|| panic!("unknown DEINTERLACE {:?}", name)))
+    }
+}
+
+/// Whether fields of the given parities come from a progressive (240p)
+/// source, which sends the same field over and over. Unknown parities are
+/// assumed to alternate.
+pub fn is_progressive(parities: &[Option<bool>]) -> bool {
+    let known = parities.iter().filter_map(|x| *x).collect::<Vec<_>>();
+    known.len() > 1 && known.windows(2).all(|pair| pair[0] == pair[1])
+}
+
+/// Parity of each field, as `true` for the second (bottom) field. Fields
+/// without a known parity alternate from the last known one.
+pub fn resolve_parities(parities: &[Option<bool>]) -> Vec<bool> {
+    let mut last = true;
+    parities.iter()
+        .map(|parity| {
+            last = parity.unwrap_or(!last);
+            last
+        })
+        .collect()
+}
+
+/**
+ * Deinterlaces fields into progressive pictures of twice their height, one
+ * per field. `bottom[n]` says whether field `n` holds the odd rows of the
+ * picture. Weaving needs a previous field of the other parity, so the first
+ * field, and any that follows one of the same parity, is bobbed instead.
+ */
+pub fn deinterlace(fields: &[Frame], bottom: &[bool], mode: Mode) -> Vec<Frame> {
+    (0..fields.len()).map(|n| {
+        let previous = if n > 0 && bottom[n - 1] != bottom[n] && fields[n - 1].height == fields[n].height {
+            Some(&fields[n - 1])
+        } else {
+            None
+        };
+        let field = &fields[n];
+        match (mode, previous) {
+            (Mode::Bob, _) | (_, None) => bob(field, bottom[n]),
+            (Mode::Weave, Some(previous)) => weave(field, previous, bottom[n]),
+            (Mode::Blend, Some(previous)) => blend(&weave(field, previous, bottom[n])),
+            (Mode::Adaptive, Some(previous)) => {
+                let before = if n > 1 && bottom[n - 2] == bottom[n] && fields[n - 2].height == field.height {
+                    Some(&fields[n - 2])
+                } else {
+                    None
+                };
+                let before_previous = if n > 2 && bottom[n - 3] == bottom[n - 1] && fields[n - 3].height == field.height {
+                    Some(&fields[n - 3])
+                } else {
+                    None
+                };
+                adaptive(field, previous, before, before_previous, bottom[n])
+            }
+        }
+    }).collect()
+}
+
+fn average(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [(a[0] + b[0]) / 2., (a[1] + b[1]) / 2., (a[2] + b[2]) / 2.]
+}
+
+fn difference(a: [f32; 3], b: [f32; 3]) -> f32 {
+    (0..3).map(|c| (a[c] - b[c]).abs()).fold(0., f32::max)
+}
+
+/// Row `y` of a picture built from `field` placed at `bottom`, interpolated
+/// from the field rows above and below when the field doesn't carry it.
+fn interpolate(field: &Frame, bottom: bool, x: usize, y: usize) -> [f32; 3] {
+    let offset = bottom as usize;
+    let last = field.height - 1;
+    let above = (y.saturating_sub(1 + offset) / 2).min(last);
+    let below = ((y + 1).saturating_sub(offset) / 2).min(last);
+    average(field.get(x, above), field.get(x, below))
+}
+
+fn bob(field: &Frame, bottom: bool) -> Frame {
+    let offset = bottom as usize;
+    let mut out = Frame::new(field.width, field.height * 2);
+    for y in 0..out.height {
+        for x in 0..field.width {
+            let pixel = if y % 2 == offset {
+                field.get(x, y / 2)
+            } else {
+                interpolate(field, bottom, x, y)
+            };
+            out.set(x, y, pixel);
+        }
+    }
+    out
+}
+
+fn weave(field: &Frame, previous: &Frame, bottom: bool) -> Frame {
+    if bottom {
+        crate::frame::weave(previous, field)
+    } else {
+        crate::frame::weave(field, previous)
+    }
+}
+
+/// Vertical [1 2 1] filter over a woven picture.
+fn blend(woven: &Frame) -> Frame {
+    let mut out = Frame::new(woven.width, woven.height);
+    let last = woven.height.saturating_sub(1);
+    for y in 0..woven.height {
+        for x in 0..woven.width {
+            let above = woven.get(x, y.saturating_sub(1));
+            let below = woven.get(x, (y + 1).min(last));
+            out.set(x, y, average(woven.get(x, y), average(above, below)));
+        }
+    }
+    out
+}
+
+/**
+ * Weaves each missing row from the previous field where it matches the field
+ * of the same parity before that, and where the current field matches its
+ * own predecessor around it. Elsewhere the row is interpolated as in bob.
+ */
+fn adaptive(field: &Frame, previous: &Frame, before: Option<&Frame>, before_previous: Option<&Frame>, bottom: bool) -> Frame {
+    let offset = bottom as usize;
+    let last = field.height - 1;
+    let mut out = bob(field, bottom);
+    for y in (0..out.height).filter(|y| y % 2 != offset) {
+        let row = y / 2;
+        let above = (y.saturating_sub(1 + offset) / 2).min(last);
+        let below = ((y + 1).saturating_sub(offset) / 2).min(last);
+        for x in 0..field.width {
+            let woven = previous.get(x, row);
+            let mut motion = 0f32;
+            if let Some(before_previous) = before_previous {
+                motion = motion.max(difference(woven, before_previous.get(x, row)));
+            }
+            if let Some(before) = before {
+                motion = motion.max(difference(field.get(x, above), before.get(x, above)));
+                motion = motion.max(difference(field.get(x, below), before.get(x, below)));
+            }
+            // Without history to compare against, assume motion.
+            if (before.is_some() || before_previous.is_some()) && motion < MOTION_THRESHOLD {
+                out.set(x, y, woven);
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A one pixel wide field with the given grey rows.
+    fn field(rows: &[f32]) -> Frame {
+        let mut frame = Frame::new(1, rows.len());
+        for (y, v) in rows.iter().enumerate() {
+            frame.set(0, y, [*v; 3]);
+        }
+        frame
+    }
+
+    fn column(frame: &Frame) -> Vec<f32> {
+        (0..frame.height).map(|y| frame.get(0, y)[0]).collect()
+    }
+
+    #[test]
+    fn parities() {
+        assert!(is_progressive(&[Some(false), None, Some(false), Some(false)]));
+        assert!(!is_progressive(&[Some(false), Some(true)]));
+        assert!(!is_progressive(&[None, None, Some(true)]));
+        assert_eq!(resolve_parities(&[None, Some(true), None, None]), vec![false, true, false, true]);
+    }
+
+    #[test]
+    fn bob_and_weave() {
+        let top = field(&[0., 0.4]);
+        let bottom = field(&[0.2, 0.6]);
+
+        // Each field sits on its own rows, with the others interpolated.
+        assert_eq!(column(&bob(&top, false)), vec![0., 0.2, 0.4, 0.4]);
+        assert_eq!(column(&bob(&bottom, true)), vec![0.2, 0.2, 0.4, 0.6]);
+
+        let fields = [top, bottom];
+        let parities = [false, true];
+        // The first field has nothing before it to weave with.
+        let out = deinterlace(&fields, &parities, Mode::Weave);
+        assert_eq!(column(&out[0]), vec![0., 0.2, 0.4, 0.4]);
+        assert_eq!(column(&out[1]), vec![0., 0.2, 0.4, 0.6]);
+        let out = deinterlace(&fields, &parities, Mode::Blend);
+        assert_eq!(column(&out[1]), vec![0.05, 0.2, 0.4, 0.55]);
+
+        // Fields of the same parity are never woven together.
+        let out = deinterlace(&fields, &[true, true], Mode::Weave);
+        assert_eq!(column(&out[1]), column(&bob(&fields[1], true)));
+    }
+
+    #[test]
+    fn adaptive_weaves_still_areas() {
+        let still = [field(&[0., 1.]), field(&[0.5, 0.5]), field(&[0., 1.]), field(&[0.5, 0.5])];
+        let parities = [false, true, false, true];
+        let out = deinterlace(&still, &parities, Mode::Adaptive);
+        assert_eq!(column(&out[3]), column(&crate::frame::weave(&still[2], &still[3])));
+
+        // Where a field changed from the last one of its parity, bob.
+        let moving = [field(&[0., 1.]), field(&[0.5, 0.5]), field(&[0., 1.]), field(&[0.9, 0.9])];
+        let out = deinterlace(&moving, &parities, Mode::Adaptive);
+        assert_eq!(column(&out[2]), column(&crate::frame::weave(&moving[2], &moving[1])));
+        assert_eq!(column(&out[3]), vec![0.9; 4]);
+    }
+}
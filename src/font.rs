@@ -0,0 +1,106 @@
+use crate::frame::Frame;
+
+/// Size of a glyph in font pixels, before scaling.
+pub const GLYPH_WIDTH: usize = 3;
+pub const GLYPH_HEIGHT: usize = 5;
+
+/// Rows of a 3x5 glyph, most significant of the low three bits leftmost.
+fn glyph(c: char) -> Option<[u8; 5]> {
+    Some(match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b011, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '+' => [0b000, 0b010, 0b111, 0b010, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+        '!' => [0b010, 0b010, 0b010, 0b000, 0b010],
+        '?' => [0b111, 0b001, 0b010, 0b000, 0b010],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        '\'' => [0b010, 0b010, 0b000, 0b000, 0b000],
+        '"' => [0b101, 0b101, 0b000, 0b000, 0b000],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '(' => [0b001, 0b010, 0b010, 0b010, 0b001],
+        ')' => [0b100, 0b010, 0b010, 0b010, 0b100],
+        '&' => [0b010, 0b101, 0b010, 0b101, 0b011],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b111, 0b100, 0b100, 0b100, 0b111],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b111, 0b100, 0b101, 0b101, 0b111],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b111],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b111, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b101, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        'c' => [0b000, 0b111, 0b100, 0b100, 0b111],
+        'g' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'k' => [0b100, 0b101, 0b110, 0b101, 0b101],
+        's' => [0b000, 0b111, 0b110, 0b011, 0b111],
+        'u' => [0b000, 0b101, 0b101, 0b101, 0b111],
+        'z' => [0b000, 0b111, 0b010, 0b100, 0b111],
+        ' ' => [0; 5],
+        _ => return None,
+    })
+}
+
+/// Width in frame pixels of `text` drawn at `scale`.
+pub fn text_width(text: &str, scale: usize) -> usize {
+    let count = text.chars().count();
+    (count * (GLYPH_WIDTH + 1)).saturating_sub(1) * scale
+}
+
+/**
+ * Draws `text` with its top left corner at (`x`, `y`), each font pixel
+ * `scale` frame pixels square. Characters without a glyph are skipped and
+ * anything outside the frame is clipped.
+ */
+pub fn draw_text(frame: &mut Frame, x: isize, y: isize, text: &str, scale: usize, color: [f32; 3]) {
+    for (n, c) in text.chars().enumerate() {
+        let rows = match glyph(c) {
+            Some(rows) => rows,
+            None => continue,
+        };
+        let left = x + (n * (GLYPH_WIDTH + 1) * scale) as isize;
+        for (gy, bits) in rows.iter().enumerate() {
+            for gx in 0..GLYPH_WIDTH {
+                if bits & (0b100 >> gx) == 0 {
+                    continue;
+                }
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        let px = left + (gx * scale + dx) as isize;
+                        let py = y + (gy * scale + dy) as isize;
+                        if px >= 0 && py >= 0 && (px as usize) < frame.width && (py as usize) < frame.height {
+                            frame.set(px as usize, py as usize, color);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,106 @@
+/**
+ * A decoded image. Pixels are RGB in the 0.0..1.0 range, row-major, and are
+ * only clamped when converted to an 8-bit format.
+ */
+#[derive(Clone)]
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<[f32; 3]>,
+}
+
+impl Frame {
+    pub fn new(width: usize, height: usize) -> Frame {
+        Frame {
+            width,
+            height,
+            pixels: vec![[0.; 3]; width * height],
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> [f32; 3] {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, rgb: [f32; 3]) {
+        self.pixels[y * self.width + x] = rgb;
+    }
+
+    pub fn row(&self, y: usize) -> &[[f32; 3]] {
+        &self.pixels[y * self.width..(y + 1) * self.width]
+    }
+
+    /// Copies out a `width` by `height` region starting at (`x`, `y`).
+    pub fn crop(&self, x: usize, y: usize, width: usize, height: usize) -> Frame {
+        let mut out = Frame::new(width, height);
+        for row in 0..height {
+            out.pixels[row * width..(row + 1) * width]
+                .copy_from_slice(&self.row(y + row)[x..x + width]);
+        }
+        out
+    }
+
+    pub fn to_rgb8(&self) -> Vec<u8> {
+        self.pixels.iter()
+            .flat_map(|p| p.iter().map(|c| to_u8(*c)).collect::<Vec<_>>())
+            .collect()
+    }
+
+    pub fn to_rgba8(&self) -> Vec<u8> {
+        self.pixels.iter()
+            .flat_map(|p| vec![to_u8(p[0]), to_u8(p[1]), to_u8(p[2]), 255])
+            .collect()
+    }
+}
+
+pub fn to_u8(value: f32) -> u8 {
+    (num::clamp(value, 0., 1.) * 255.) as u8
+}
+
+/// Converts RGB to studio-range BT.601 Y'CbCr.
+pub fn rgb_to_ycbcr(rgb: [f32; 3]) -> [u8; 3] {
+    let r = num::clamp(rgb[0], 0., 1.);
+    let g = num::clamp(rgb[1], 0., 1.);
+    let b = num::clamp(rgb[2], 0., 1.);
+    [
+        (16. + 65.481 * r + 128.553 * g + 24.966 * b).round() as u8,
+        (128. - 37.797 * r - 74.203 * g + 112.0 * b).round() as u8,
+        (128. + 112.0 * r - 93.786 * g - 18.214 * b).round() as u8,
+    ]
+}
+
+/// Interleaves two fields into a frame, with `top` on the even rows.
+pub fn weave(top: &Frame, bottom: &Frame) -> Frame {
+    assert_eq!(top.width, bottom.width);
+    let height = top.height.min(bottom.height);
+
+    let mut frame = Frame::new(top.width, height * 2);
+    for y in 0..height {
+        frame.pixels[(y * 2) * top.width..(y * 2 + 1) * top.width].copy_from_slice(top.row(y));
+        frame.pixels[(y * 2 + 1) * top.width..(y * 2 + 2) * top.width].copy_from_slice(bottom.row(y));
+    }
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weaves_fields() {
+        let field = |value: f32, height: usize| {
+            let mut frame = Frame::new(2, height);
+            frame.pixels.iter_mut().enumerate().for_each(|(i, p)| *p = [value, i as f32, 0.]);
+            frame
+        };
+        let frame = weave(&field(1., 3), &field(2., 2));
+        assert_eq!((frame.width, frame.height), (2, 4));
+        let rows = (0..4).map(|y| frame.row(y).to_vec()).collect::<Vec<_>>();
+        assert_eq!(rows, vec![
+            vec![[1., 0., 0.], [1., 1., 0.]],
+            vec![[2., 0., 0.], [2., 1., 0.]],
+            vec![[1., 2., 0.], [1., 3., 0.]],
+            vec![[2., 2., 0.], [2., 3., 0.]],
+        ]);
+    }
+}
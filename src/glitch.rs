@@ -0,0 +1,136 @@
+/// Bits below the 12-bit ADC code, which the SMI bus should always leave
+/// clear.
+pub const LOW_BITS: u16 = 0x000f;
+
+/// Default slew limit, in ADC codes between neighbouring samples. It's
+/// more than the whole range from sync tip to peak white, about 270 codes,
+/// which real edges take several samples to cover.
+pub const DEFAULT_SLEW_CODES: i32 = 400;
+
+/// How a sample was found to be bad.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    /// Bits set below the 12-bit code.
+    LowBits,
+    /// A single sample far from both of its neighbours, in the same
+    /// direction, while they agree with each other.
+    Spike,
+}
+
+/// A bad sample in a capture.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Glitch {
+    pub channel: usize,
+    /// Index of the sample in its channel.
+    pub index: usize,
+    pub kind: Kind,
+}
+
+/// Finds digital glitches in raw SMI samples: words that break the 12-bit
+/// left-aligned layout, and single-sample spikes faster than any signal
+/// slews.
+#[derive(Clone, Copy, Debug)]
+pub struct Detector {
+    /// Largest change between neighbouring samples, in ADC codes, that a
+    /// spike has to exceed on both sides.
+    pub slew: i32,
+    /// Whether glitches are repaired, or only counted.
+    pub repair: bool,
+}
+
+impl Detector {
+    /// Reads the `GLITCH_SLEW` environment variable, the slew limit in ADC
+    /// codes, and `GLITCH_REPAIR`, which is `0` to count glitches without
+    /// repairing them.
+    pub fn from_env() -> Detector {
+        let slew = std::env::var("GLITCH_SLEW").ok()
+            .map(|x| x.parse::<i32>().unwrap_or_else(|_| panic!("invalid GLITCH_SLEW {:?}", x)))
+            .unwrap_or(DEFAULT_SLEW_CODES);
+        let repair = std::env::var("GLITCH_REPAIR").map_or(true, |x| x != "0");
+        Detector { slew, repair }
+    }
+
+    /// Finds the glitches in one channel's samples, in order. A sample with
+    /// low bits set is only counted as that, even if it's also a spike.
+    pub fn find(&self, samples: &[u16], channel: usize) -> Vec<Glitch> {
+        let code = |i: usize| (samples[i] >> 4) as i32;
+        let mut glitches = vec![];
+        for (index, sample) in samples.iter().enumerate() {
+            let kind = if sample & LOW_BITS != 0 {
+                Kind::LowBits
+            } else if index > 0 && index + 1 < samples.len() {
+                let (before, at, after) = (code(index - 1), code(index), code(index + 1));
+                let (rise, fall) = (at - before, at - after);
+                if rise.abs() > self.slew && fall.abs() > self.slew && rise.signum() == fall.signum() && (after - before).abs() <= self.slew {
+                    Kind::Spike
+                } else {
+                    continue;
+                }
+            } else {
+                continue;
+            };
+            glitches.push(Glitch { channel, index, kind });
+        }
+        glitches
+    }
+}
+
+/// Replaces the samples at `bad`, in order, by interpolating linearly
+/// between the nearest good samples either side. Bad samples at either end
+/// take the nearest good one.
+pub fn repair(samples: &mut [u16], bad: &[usize]) {
+    let mut n = 0;
+    while n < bad.len() {
+        // A run of neighbouring bad samples is bridged in one go.
+        let mut end = n + 1;
+        while end < bad.len() && bad[end] == bad[end - 1] + 1 {
+            end += 1;
+        }
+        let (first, last) = (bad[n], bad[end - 1]);
+        let before = first.checked_sub(1).map(|i| (i, (samples[i] >> 4) as f32));
+        let after = samples.get(last + 1).map(|x| (last + 1, (x >> 4) as f32));
+        for (i, sample) in samples.iter_mut().enumerate().take(last + 1).skip(first) {
+            let code = match (before, after) {
+                (Some((a, x)), Some((b, y))) => x + (y - x) * (i - a) as f32 / (b - a) as f32,
+                (Some((_, x)), None) | (None, Some((_, x))) => x,
+                (None, None) => break,
+            };
+            *sample = (code.round() as u16) << 4;
+        }
+        n = end;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(codes: &[u16]) -> Vec<u16> {
+        codes.iter().map(|x| x << 4).collect()
+    }
+
+    #[test]
+    fn finds_glitches() {
+        let detector = Detector { slew: 400, repair: true };
+        let mut samples = words(&[2000, 2000, 3000, 2010, 2020, 2000, 2500, 3000, 2000]);
+        samples[4] |= 1;
+        let glitches = detector.find(&samples, 1);
+        assert_eq!(glitches, vec![
+            Glitch { channel: 1, index: 2, kind: Kind::Spike },
+            Glitch { channel: 1, index: 4, kind: Kind::LowBits },
+        ]);
+        // A fast edge that settles at a new level isn't a spike.
+        assert!(detector.find(&words(&[2000, 2000, 3000, 3000]), 0).is_empty());
+    }
+
+    #[test]
+    fn repairs_runs() {
+        let mut samples = words(&[100, 0, 0, 0, 200, 0, 300]);
+        repair(&mut samples, &[1, 2, 3, 5]);
+        assert_eq!(samples, words(&[100, 125, 150, 175, 200, 250, 300]));
+
+        let mut samples = words(&[0, 0, 50, 0]);
+        repair(&mut samples, &[0, 1, 3]);
+        assert_eq!(samples, words(&[50, 50, 50, 50]));
+    }
+}
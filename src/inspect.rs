@@ -0,0 +1,374 @@
+use std::ops::Range;
+use crate::caption;
+use crate::capture::{self, to_ire, Capture};
+use crate::decode::{self, BurstStats, Decoder, InputMode, SAMPLE_RATE_MHZ, SYNC_THRESHOLD};
+use crate::glitch;
+use crate::json::Json;
+use crate::vectorscope::ACTIVE_US;
+use crate::vitc;
+use crate::waveform;
+use crate::xds;
+
+/// Horizontal sync pulses are 4.7µs; anything outside this range, in µs,
+/// is a broad or equalizing pulse or a glitch, and isn't counted in sync
+/// width.
+pub const SYNC_WIDTH_US: Range<f32> = 3.5..6.;
+
+/// Part of the front porch measured for the blanking level, in µs before
+/// the sync leading edge.
+pub const FRONT_PORCH_US: Range<f32> = 0.2..1.3;
+
+/// How far a line period may stray from the median, as a share of it, and
+/// still count towards jitter. Longer and shorter ones are half lines in
+/// vertical sync, or gaps.
+pub const LINE_PERIOD_TOLERANCE: f32 = 0.02;
+
+/// Length of the windows luma noise is measured over, in µs.
+pub const NOISE_WINDOW_US: f32 = 1.;
+
+/// Share of the quietest windows taken to be flat picture, whose deviation
+/// is the noise.
+pub const FLAT_FRACTION: f32 = 0.1;
+
+/// Mean and standard deviation of `values`, or None if there are none.
+fn mean_deviation(values: &[f32]) -> Option<(f32, f32)> {
+    if values.is_empty() {
+        return None;
+    }
+    let len = values.len() as f32;
+    let mean = values.iter().sum::<f32>() / len;
+    let variance = values.iter().map(|x| (x - mean) * (x - mean)).sum::<f32>() / len;
+    Some((mean, variance.sqrt()))
+}
+
+/**
+ * Finds where the sync pulse ending at `rise` crosses `SYNC_THRESHOLD` on
+ * the way down and back up, to a fraction of a sample. Unlike
+ * `find_sync_fall` this follows the pulse back however long it is, up to
+ * twice the longest horizontal sync, so long pulses are measured rather
+ * than assumed. Returns None if there's no whole pulse there.
+ */
+pub fn sync_edges(sync: &[f32], rise: usize, chunk_width: usize) -> Option<(f32, f32)> {
+    let crossing = |from: usize| {
+        let (a, b) = (sync[from], sync[from + 1]);
+        from as f32 + (a - SYNC_THRESHOLD) / (a - b)
+    };
+    let limit = (SYNC_WIDTH_US.end * 2. * SAMPLE_RATE_MHZ) as usize;
+    let end = (rise + chunk_width).min(sync.len());
+    let from = end.saturating_sub(limit);
+    let last = (from..end).rev().find(|i| sync[*i] < SYNC_THRESHOLD)?;
+    if last + 1 >= end {
+        return None;
+    }
+    let first = (from..=last).rev().find(|i| sync[*i] >= SYNC_THRESHOLD)?;
+    Some((crossing(first), crossing(last)))
+}
+
+/// Signal quality measured over some lines of a capture. Levels are in IRE
+/// and times in µs unless noted.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    pub lines: usize,
+    pub sync_tip: Option<f32>,
+    pub blanking: Option<f32>,
+    /// Mean and standard deviation of horizontal sync width.
+    pub sync_width: Option<(f32, f32)>,
+    /// Mean line period, and its standard deviation (jitter) in ns.
+    pub line_period: Option<(f32, f32)>,
+    pub burst: Option<BurstStats>,
+    /// Standard deviation of burst amplitude from line to line.
+    pub burst_amplitude_deviation: Option<f32>,
+    /// Standard deviation of luma over flat parts of the picture.
+    pub luma_noise: Option<f32>,
+    /// Samples at either end of the ADC's range, over all channels.
+    pub clipped: usize,
+    /// Gaps of filler between DMA blocks.
+    pub gaps: usize,
+    /// Samples with bits set below the 12-bit code, and single-sample
+    /// spikes, over all channels.
+    pub low_bit_glitches: usize,
+    pub spike_glitches: usize,
+}
+
+impl Metrics {
+    /**
+     * Measures the lines starting at `starts` (at the end of sync) and the
+     * samples in `range`, which clipping, gaps and glitches are counted
+     * over.
+     * `channels` are the capture's levels and `gaps` the filler between its
+     * DMA blocks.
+     */
+    pub fn measure(decoder: &Decoder, capture: &Capture, channels: &[Vec<f32>], gaps: &[Range<usize>], starts: &[usize], range: Range<usize>) -> Metrics {
+        let sync = &channels[0];
+        let mut tips = vec![];
+        let mut porches = vec![];
+        let mut widths = vec![];
+        let mut leading_edges = vec![];
+        let mut bursts = vec![];
+        let mut windows = vec![];
+
+        let porch = (FRONT_PORCH_US.start * SAMPLE_RATE_MHZ) as usize..(FRONT_PORCH_US.end * SAMPLE_RATE_MHZ) as usize;
+        let active = (ACTIVE_US.start * SAMPLE_RATE_MHZ) as usize..(ACTIVE_US.end * SAMPLE_RATE_MHZ) as usize;
+        let window = (NOISE_WINDOW_US * SAMPLE_RATE_MHZ) as usize;
+        for start in starts {
+            if let Some((leading, trailing)) = sync_edges(sync, *start, decoder.chunk_width) {
+                let (fall, end) = (leading.ceil() as usize, trailing.floor() as usize);
+                tips.extend(sync[fall..=end].iter().filter(|x| **x < -40.).cloned());
+                let width = (trailing - leading) / SAMPLE_RATE_MHZ;
+                if SYNC_WIDTH_US.contains(&width) {
+                    widths.push(width);
+                    leading_edges.push(Some(leading));
+                    if fall >= porch.end {
+                        porches.extend_from_slice(&sync[fall - porch.end..fall - porch.start]);
+                    }
+                } else {
+                    leading_edges.push(None);
+                }
+            } else {
+                leading_edges.push(None);
+            }
+
+            let lines = decoder.prepare_line(channels, *start, decoder.line_samples);
+            match decoder.mode {
+                InputMode::Composite => bursts.push(decoder.measure_burst(&lines[0])),
+                InputMode::SVideo => bursts.push(decoder.measure_burst(&lines[1])),
+                InputMode::Component => {}
+            }
+            let luma = waveform::luma(&decode::line_at(sync, start + active.start, active.len()));
+            for samples in luma.chunks_exact(window) {
+                if let Some((_, deviation)) = mean_deviation(samples) {
+                    windows.push(deviation);
+                }
+            }
+        }
+
+        let mut periods = leading_edges.windows(2)
+            .filter_map(|w| Some(w[1]? - w[0]?))
+            .collect::<Vec<_>>();
+        let mut sorted = periods.clone();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        if let Some(median) = sorted.get(sorted.len() / 2).cloned() {
+            periods.retain(|x| (x - median).abs() <= median * LINE_PERIOD_TOLERANCE);
+        }
+        windows.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let flat = &windows[..(windows.len() as f32 * FLAT_FRACTION).ceil() as usize];
+
+        let clipped = (0..capture.channel_count())
+            .map(|c| capture.channel(c)[range.clone()].iter().filter(|x| capture::is_clipped(**x)).count())
+            .sum();
+        let glitches = |kind: glitch::Kind| capture.glitches().iter()
+            .filter(|x| x.kind == kind && range.contains(&x.index))
+            .count();
+
+        Metrics {
+            lines: starts.len(),
+            sync_tip: mean_deviation(&tips).map(|x| to_ire(x.0)),
+            blanking: mean_deviation(&porches).map(|x| to_ire(x.0)),
+            sync_width: mean_deviation(&widths),
+            line_period: mean_deviation(&periods).map(|(mean, deviation)| (mean / SAMPLE_RATE_MHZ, deviation / SAMPLE_RATE_MHZ * 1000.)),
+            burst: BurstStats::from_bursts(&bursts),
+            burst_amplitude_deviation: mean_deviation(&bursts.iter().map(|x| to_ire(x.0)).collect::<Vec<_>>()).map(|x| x.1),
+            luma_noise: mean_deviation(flat).map(|x| to_ire(x.0)),
+            clipped,
+            gaps: gaps.iter().filter(|x| x.start < range.end && x.end > range.start).count(),
+            low_bit_glitches: glitches(glitch::Kind::LowBits),
+            spike_glitches: glitches(glitch::Kind::Spike),
+        }
+    }
+
+    pub fn to_json(&self) -> Json {
+        let stat = |x: Option<(f32, f32)>, mean: &str, deviation: &str| match x {
+            Some((m, d)) => Json::object(vec![(mean, m.into()), (deviation, d.into())]),
+            None => Json::Null,
+        };
+        let burst = match self.burst {
+            Some(burst) => Json::object(vec![
+                ("amplitude_ire", to_ire(burst.amplitude).into()),
+                ("amplitude_deviation_ire", self.burst_amplitude_deviation.into()),
+                ("phase_degrees", burst.phase.to_degrees().into()),
+                ("phase_deviation_degrees", burst.phase_deviation.to_degrees().into()),
+            ]),
+            None => Json::Null,
+        };
+        Json::object(vec![
+            ("lines", self.lines.into()),
+            ("sync_tip_ire", self.sync_tip.into()),
+            ("blanking_ire", self.blanking.into()),
+            ("sync_amplitude_ire", self.sync_tip.and_then(|tip| self.blanking.map(|blank| blank - tip)).into()),
+            ("sync_width_us", stat(self.sync_width, "mean", "deviation")),
+            ("line_period_us", stat(self.line_period, "mean", "jitter_ns")),
+            ("burst", burst),
+            ("luma_noise_ire", self.luma_noise.into()),
+            ("luma_snr_db", self.luma_noise.map(|x| 20. * (100. / x).log10()).into()),
+            ("clipped_samples", self.clipped.into()),
+            ("dma_gaps", self.gaps.into()),
+            ("glitches", Json::object(vec![
+                ("low_bits", self.low_bit_glitches.into()),
+                ("spikes", self.spike_glitches.into()),
+            ])),
+        ])
+    }
+}
+
+/// What a report is built from: a capture, how it was decoded, and what was
+/// found in its blanking.
+pub struct Report<'a> {
+    pub name: &'a str,
+    pub decoder: &'a Decoder,
+    pub capture: &'a Capture,
+    /// All of the capture's lines, `line_samples` apart as measured with
+    /// them.
+    pub starts: &'a [usize],
+    pub line_samples: usize,
+    pub fields: &'a [decode::Field],
+    /// Line 21 packets, for XDS program metadata.
+    pub captions: &'a [caption::Packet],
+    pub timecodes: &'a [vitc::Reading],
+}
+
+impl<'a> Report<'a> {
+    /// The report: what the capture is, metrics over all of its lines, and
+    /// metrics and the VITC timecode for each field, along with its DMA
+    /// blocks and XDS program metadata.
+    pub fn to_json(&self) -> Json {
+        let (decoder, capture, starts) = (self.decoder, self.capture, self.starts);
+        let channels = (0..capture.channel_count()).map(|c| capture.levels(c)).collect::<Vec<_>>();
+        let gaps = capture.gaps();
+        let overall = Metrics::measure(decoder, capture, &channels, &gaps, starts, 0..capture.len());
+
+        let fields = self.fields.iter()
+            .enumerate()
+            .map(|(n, field)| {
+                let lines = starts.iter().cloned().filter(|x| *x >= field.start && *x < field.end).collect::<Vec<_>>();
+                let end = field.end.min(capture.len());
+                let metrics = Metrics::measure(decoder, capture, &channels, &gaps, &lines, field.start.min(end)..end);
+                let mut json = Json::object(vec![
+                    ("index", n.into()),
+                    ("start_sample", field.start.into()),
+                    ("end_sample", field.end.into()),
+                    ("first_line", field.first_line.into()),
+                    ("parity", field.second.map(|x| if x { "bottom" } else { "top" }).into()),
+                ]);
+                let vitc = self.timecodes.iter().find(|x| x.line.field == n).map(|x| {
+                    let mut json = x.timecode.to_json();
+                    json.insert("line", x.line.number.into());
+                    json
+                });
+                json.insert("vitc", vitc.unwrap_or(Json::Null));
+                if let Json::Object(pairs) = metrics.to_json() {
+                    for (key, value) in pairs {
+                        json.insert(&key, value);
+                    }
+                }
+                json
+            })
+            .collect::<Vec<_>>();
+
+        let block_gaps = decode::block_gaps(&channels[0], capture.blocks(), starts, self.line_samples);
+        let blocks = capture.blocks().iter()
+            .zip(block_gaps)
+            .map(|(block, gap)| Json::object(vec![
+                ("start_sample", block.start.into()),
+                ("end_sample", block.end.into()),
+                ("lines", starts.iter().filter(|x| block.contains(x)).count().into()),
+                ("gap_samples", gap.into()),
+                ("gap_us", gap.map(|x| x as f32 / SAMPLE_RATE_MHZ).into()),
+            ]))
+            .collect::<Vec<_>>();
+
+        Json::object(vec![
+            ("capture", self.name.into()),
+            ("input", format!("{:?}", decoder.mode).to_lowercase().into()),
+            ("standard", decoder.standard.name().into()),
+            ("sample_rate_mhz", SAMPLE_RATE_MHZ.into()),
+            ("samples", capture.len().into()),
+            ("overall", overall.to_json()),
+            ("blocks", Json::Array(blocks)),
+            ("fields", Json::Array(fields)),
+            ("xds", xds::report(&xds::packets(self.captions))),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::{ADC_SCALE, ADC_ZERO};
+    use crate::decode::{Sampling, LINE_SAMPLES};
+    use crate::standard::Standard;
+
+    const SYNC_SAMPLES: usize = 196;
+
+    fn word(level: f32) -> u16 {
+        ((ADC_ZERO as f32 - level * ADC_SCALE / 300.).round() as u16) << 4
+    }
+
+    /// Composite lines with 4.7µs sync and a flat 50 IRE picture, with one
+    /// sample clipped, and where each line's sync ends.
+    fn capture(lines: usize) -> (Capture, Vec<usize>) {
+        let mut raw = vec![];
+        for _ in 0..lines {
+            for i in 0..LINE_SAMPLES {
+                let level = if i < SYNC_SAMPLES {
+                    -56.
+                } else if (400..LINE_SAMPLES - 100).contains(&i) {
+                    70.
+                } else {
+                    0.
+                };
+                raw.push(word(level));
+            }
+        }
+        raw[LINE_SAMPLES * 3 + 1000] = 0;
+        let starts = (0..lines).map(|n| n * LINE_SAMPLES + SYNC_SAMPLES).collect();
+        (Capture::from_interleaved(&raw, 1), starts)
+    }
+
+    #[test]
+    fn finds_sync_edges() {
+        let mut sync = vec![0.; 400];
+        for x in &mut sync[100..300] {
+            *x = -40.;
+        }
+        let (leading, trailing) = sync_edges(&sync, 300, 12).unwrap();
+        assert!((leading - 99.5).abs() < 1e-4, "{}", leading);
+        assert!((trailing - 299.5).abs() < 1e-4, "{}", trailing);
+        // The pulse has to end before the line does.
+        assert!(sync_edges(&sync, 250, 12).is_none());
+    }
+
+    #[test]
+    fn measures_lines() {
+        let decoder = Decoder {
+            mode: InputMode::Composite,
+            standard: Standard::Ntsc,
+            sampling: Sampling::Boxcar,
+            shift: 7.,
+            chunk_width: 12,
+            line_samples: LINE_SAMPLES,
+        };
+        let (mut capture, starts) = capture(10);
+        capture.repair_glitches(&glitch::Detector { slew: 400, repair: false });
+        let channels = vec![capture.levels(0)];
+        let gaps = [5..20, LINE_SAMPLES * 20..LINE_SAMPLES * 20 + 10];
+        let metrics = Metrics::measure(&decoder, &capture, &channels, &gaps, &starts, 0..capture.len());
+
+        assert_eq!(metrics.lines, 10);
+        assert!((metrics.sync_tip.unwrap() - -40.).abs() < 0.5);
+        assert!(metrics.blanking.unwrap().abs() < 0.5);
+        let (width, deviation) = metrics.sync_width.unwrap();
+        assert!((width - 4.71).abs() < 0.01 && deviation < 1e-3, "{}", width);
+        let (period, jitter) = metrics.line_period.unwrap();
+        assert!((period - LINE_SAMPLES as f32 / SAMPLE_RATE_MHZ).abs() < 1e-3 && jitter < 0.1, "{} {}", period, jitter);
+        assert!(metrics.luma_noise.unwrap() < 0.01);
+        assert_eq!(metrics.clipped, 1);
+        assert_eq!(metrics.gaps, 1);
+        // The clipped sample is also a spike, but left as it is.
+        assert_eq!((metrics.low_bit_glitches, metrics.spike_glitches), (0, 1));
+
+        let json = metrics.to_json().to_string();
+        assert!(json.contains("\"lines\": 10,"), "{}", json);
+        assert!(json.contains("\"clipped_samples\": 1,"));
+        assert!(json.contains("\"dma_gaps\": 1,\n  \"glitches\": {\n    \"low_bits\": 0,\n    \"spikes\": 1\n  }\n}"), "{}", json);
+    }
+}
@@ -0,0 +1,161 @@
+use std::fmt;
+
+/// A JSON value, for reports. Objects keep their keys in insertion order.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn object(pairs: Vec<(&str, Json)>) -> Json {
+        Json::Object(pairs.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+    }
+
+    /// Adds a key to an object, replacing any it already has.
+    pub fn insert(&mut self, key: &str, value: Json) {
+        if let Json::Object(pairs) = self {
+            match pairs.iter_mut().find(|(k, _)| k == key) {
+                Some(pair) => pair.1 = value,
+                None => pairs.push((key.to_string(), value)),
+            }
+        }
+    }
+
+    fn write(&self, f: &mut fmt::Formatter, indent: usize) -> fmt::Result {
+        let pad = "  ".repeat(indent + 1);
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(x) => write!(f, "{}", x),
+            // JSON has no NaN or infinity.
+            Json::Number(x) if !x.is_finite() => write!(f, "null"),
+            Json::Number(x) => write!(f, "{}", x),
+            Json::String(x) => write_string(f, x),
+            Json::Array(items) if items.is_empty() => write!(f, "[]"),
+            Json::Array(items) => {
+                writeln!(f, "[")?;
+                for (n, item) in items.iter().enumerate() {
+                    write!(f, "{}", pad)?;
+                    item.write(f, indent + 1)?;
+                    writeln!(f, "{}", if n + 1 < items.len() { "," } else { "" })?;
+                }
+                write!(f, "{}]", "  ".repeat(indent))
+            }
+            Json::Object(pairs) if pairs.is_empty() => write!(f, "{{}}"),
+            Json::Object(pairs) => {
+                writeln!(f, "{{")?;
+                for (n, (key, value)) in pairs.iter().enumerate() {
+                    write!(f, "{}", pad)?;
+                    write_string(f, key)?;
+                    write!(f, ": ")?;
+                    value.write(f, indent + 1)?;
+                    writeln!(f, "{}", if n + 1 < pairs.len() { "," } else { "" })?;
+                }
+                write!(f, "{}}}", "  ".repeat(indent))
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, value: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in value.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+/// Pretty-prints with two-space indents.
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+impl From<bool> for Json {
+    fn from(x: bool) -> Json {
+        Json::Bool(x)
+    }
+}
+
+impl From<f64> for Json {
+    fn from(x: f64) -> Json {
+        Json::Number(x)
+    }
+}
+
+impl From<f32> for Json {
+    fn from(x: f32) -> Json {
+        // Round trip through the shortest decimal, so 0.1f32 isn't 0.10000000149.
+        Json::Number(x.to_string().parse().unwrap_or(f64::NAN))
+    }
+}
+
+impl From<usize> for Json {
+    fn from(x: usize) -> Json {
+        Json::Number(x as f64)
+    }
+}
+
+impl From<isize> for Json {
+    fn from(x: isize) -> Json {
+        Json::Number(x as f64)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(x: &str) -> Json {
+        Json::String(x.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(x: String) -> Json {
+        Json::String(x)
+    }
+}
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(x: Option<T>) -> Json {
+        x.map_or(Json::Null, Into::into)
+    }
+}
+
+impl<T: Into<Json>> From<Vec<T>> for Json {
+    fn from(x: Vec<T>) -> Json {
+        Json::Array(x.into_iter().map(Into::into).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pretty_prints() {
+        let mut json = Json::object(vec![
+            ("name", "a \"b\"\n".into()),
+            ("level", 0.1f32.into()),
+            ("missing", Json::from(None::<f64>)),
+            ("ratio", f64::INFINITY.into()),
+            ("empty", Json::Array(vec![])),
+            ("items", vec![1usize, 2].into()),
+        ]);
+        json.insert("level", 0.5f32.into());
+        json.insert("ok", true.into());
+        assert_eq!(json.to_string(), "{\n  \"name\": \"a \\\"b\\\"\\n\",\n  \"level\": 0.5,\n  \"missing\": null,\n  \"ratio\": null,\n  \"empty\": [],\n  \"items\": [\n    1,\n    2\n  ],\n  \"ok\": true\n}");
+        assert_eq!(Json::from(0.1f32).to_string(), "0.1");
+    }
+}
@@ -1,17 +1,45 @@
 #[path = "../framework.rs"]
 mod framework;
+mod avi;
+mod caption;
+mod capture;
+mod crt;
+mod decode;
+mod deinterlace;
+mod font;
+mod frame;
+mod glitch;
+mod inspect;
+mod json;
+mod overscan;
+mod plot;
+mod pngseq;
+mod raw;
+mod resample;
+mod scale;
+mod signals;
+mod spectrum;
+mod standard;
+mod teletext;
+mod vbi;
+mod vectorscope;
+mod vitc;
+mod waveform;
+mod wss;
+mod xds;
+mod y4m;
 
 use bytemuck::{Pod, Zeroable};
 
 use wgpu::util::DeviceExt;
 use std::fs::File;
 use std::path::Path;
-use std::io::BufWriter;
-use png::HasParameters;
-use byteorder::{ReadBytesExt, NativeEndian};
-use rustfft::num_complex::Complex;
-use rustfft::num_traits::Zero;
-use rustfft::FFTplanner;
+use std::io::{BufWriter, Write};
+use capture::Capture;
+use decode::{Decoder, Field, InputMode};
+use frame::Frame;
+use scale::Scaler;
+use standard::Standard;
 
 const BYTES_PER_SAMPLE: usize = 2;
 
@@ -38,27 +66,36 @@ fn create_vertices() -> Vec<Vertex> {
         vertex([-1, 1], [0, 0], 0),
         vertex([1, 1], [1, 0], 0),
         vertex([1, -1], [1, 1], 0),
+        // vectorscope overlay, top right quarter
+        vertex([0, 0], [0, 1], 1),
+        vertex([0, 1], [0, 0], 1),
+        vertex([1, 1], [1, 0], 1),
+        vertex([1, 0], [1, 1], 1),
     ]
 }
 
-const FRAGMENT_COUNT: u32 = 1;
+const FRAGMENT_COUNT: u32 = 2;
 fn create_indices() -> Vec<u16> {
     vec![
         // Left rectangle
         0, 1, 2, // 1st
         2, 0, 3, // 2nd
+        // Vectorscope
+        4, 5, 6,
+        6, 4, 7,
     ]
 }
 
-fn volt_decode(input: u16) -> f32 {
-    ((((2080 - ((input >> 4) as i16)) as f32) / 410.0) * 300.0) as f32
-}
+/// Size of the vectorscope overlay texture.
+const VECTORSCOPE_SIZE: usize = 256;
 
 struct Example {
     pipeline: wgpu::RenderPipeline,
     bind_group: wgpu::BindGroup,
     vertex_buffer: wgpu::Buffer,
     index_buffer: wgpu::Buffer,
+    /// Whether the vectorscope overlay is drawn, toggled with V.
+    show_vectorscope: bool,
 }
 
 impl framework::Example for Example {
@@ -85,11 +122,10 @@ impl framework::Example for Example {
         device: &wgpu::Device,
         queue: &wgpu::Queue,
     ) -> Self {
-        let (size, texture_data) = process();
-
-        if true {
-            panic!("done");
-        }
+        let (capture, decoder) = open_capture();
+        // Every field is decoded as for export, and the first is shown.
+        let mut fields = decoder.decode_fields(&capture, field_height(&decoder), true);
+        let (size, texture_data) = process(&capture, &fields[0]);
 
         let vs_module = device.create_shader_module(wgpu::include_spirv!("shader.vert.spv"));
         let fs_source = wgpu::include_spirv!("constant.frag.spv");
@@ -122,11 +158,34 @@ impl framework::Example for Example {
 
         let red_texture = device.create_texture(&wgpu::TextureDescriptor {
             label: Some("red"),
-            ..texture_descriptor
+            ..texture_descriptor.clone()
         });
 
         let red_texture_view = red_texture.create_view(&wgpu::TextureViewDescriptor::default());
 
+        let (scope_size, scope_data) = vectorscope_overlay(&capture, &decoder, fields.swap_remove(0));
+        let scope_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("vectorscope"),
+            size: scope_size,
+            format: wgpu::TextureFormat::Rgba8Uint,
+            ..texture_descriptor
+        });
+        let scope_texture_view = scope_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        queue.write_texture(
+            wgpu::TextureCopyView {
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                texture: &scope_texture,
+            },
+            &scope_data,
+            wgpu::TextureDataLayout {
+                offset: 0,
+                bytes_per_row: 4 * scope_size.width,
+                rows_per_image: scope_size.height,
+            },
+            scope_size,
+        );
+
         queue.write_texture(
             wgpu::TextureCopyView {
                 mip_level: 0,
@@ -173,6 +232,7 @@ impl framework::Example for Example {
                     binding: 0,
                     resource: wgpu::BindingResource::TextureViewArray(&[
                         red_texture_view,
+                        scope_texture_view,
                     ]),
                 },
                 wgpu::BindGroupEntry {
@@ -227,6 +287,7 @@ impl framework::Example for Example {
             index_buffer,
             bind_group,
             pipeline,
+            show_vectorscope: std::env::var("VECTORSCOPE").is_ok(),
         }
     }
 
@@ -239,8 +300,17 @@ impl framework::Example for Example {
         // noop
     }
 
-    fn update(&mut self, _event: winit::event::WindowEvent) {
-        // noop
+    fn update(&mut self, event: winit::event::WindowEvent) {
+        if let winit::event::WindowEvent::KeyboardInput {
+            input: winit::event::KeyboardInput {
+                virtual_keycode: Some(winit::event::VirtualKeyCode::V),
+                state: winit::event::ElementState::Pressed,
+                ..
+            },
+            ..
+        } = event {
+            self.show_vectorscope = !self.show_vectorscope;
+        }
     }
 
     fn render(
@@ -270,7 +340,8 @@ impl framework::Example for Example {
         rpass.set_bind_group(0, &self.bind_group, &[]);
         rpass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
         rpass.set_index_buffer(self.index_buffer.slice(..));
-        rpass.draw_indexed(0..(6*FRAGMENT_COUNT), 0, 0..1);
+        let fragments = if self.show_vectorscope { FRAGMENT_COUNT } else { 1 };
+        rpass.draw_indexed(0..(6*fragments), 0, 0..1);
 
         drop(rpass);
 
@@ -278,7 +349,7 @@ impl framework::Example for Example {
     }
 }
 
-fn process() -> (wgpu::Extent3d, Vec<u8>) {
+fn process(capture: &Capture, field: &Field) -> (wgpu::Extent3d, Vec<u8>) {
     // let red_texture_data = [255, 0, 0, 255, 0, 255, 0, 255, 0, 255, 0, 255, 255, 0, 0, 255];
     // let bytes_per_row = 4;
     // let size = wgpu::Extent3d {
@@ -287,399 +358,1019 @@ fn process() -> (wgpu::Extent3d, Vec<u8>) {
     //     depth: 1,
     // };
 
-    let chunk_width = 12;
-    let dim_height = 180;
-    let dim_width = 222;
-    let dim_full_width = 2654;
+    pngseq::write_png("out/frame.png", &field.frame, false, &[]).unwrap();
 
-    let mut file = File::open("./captures/1").unwrap();
-    let mut frame_out: Vec<i16> = vec![0; dim_full_width * dim_height]; //file.metadata().unwrap().len() as usize/2];
-    file.read_i16_into::<NativeEndian>(&mut frame_out[0..(dim_full_width * dim_height)]).unwrap();
+    let dim_height = field.frame.height;
+    let dim_full_width = decode::LINE_SAMPLES;
 
-    let shift = std::env::var("SHIFT").unwrap_or("7.0".to_string()).parse::<f32>().unwrap();
+    // The GPU path only looks at the sync channel.
+    let mut frame_out: Vec<i16> = vec![0; dim_full_width * dim_height];
+    for (out, sample) in frame_out.iter_mut().zip(capture.channel(0)) {
+        *out = *sample as i16;
+    }
 
-    let carrier_freq = |x: usize| -> f32 {
-        (x as f32 + shift) * (2.0 * std::f32::consts::PI) * (3.58/41.66)
+    // let mut fake_frame: Vec<u8> = vec![];
+    // for y in 0..182 {
+    //     for x in 0..165 {
+    //         for i in 0..16 {
+    //             fake_frame.extend(&[y]);
+    //         }
+    //     }
+    // }
+
+    let size = wgpu::Extent3d {
+        width: dim_full_width as u32,
+        height: dim_height as u32,
+        depth: 1,
     };
 
-    fn vec_avg(v: &std::collections::VecDeque<f32>) -> f32 {
-        v.into_iter().sum::<f32>() / v.len() as f32
-    }
-
-    // Do some charts with a sample the first five scanlines.
-    if true {
-        use std::io::Write;
-
-        let dim_offset = 80;
-        let dim_range = dim_offset*dim_full_width..(dim_offset + 5)*dim_full_width;
-
-        // Create out.csv, which is the low-pass filtered signal.
-        let mut file = File::create("./out/out.csv").unwrap();
-        let mut bandpass = vec![];
-        let win_len = 32;
-        for samp in frame_out[dim_range.clone()]
-            .windows(win_len)
-            .enumerate()
-            .map(|(w_i, w)| {
-
-                // compute the RFFT of the samples
-                // println!("samps> {:?}", samples);
-                // let spectrum = microfft::real::rfft_16(&mut samples);
-
-                // // the spectrum has a spike at index `signal_freq`
-                // let amplitudes: Vec<_> = spectrum.iter().map(|c| c.norm() as u32).collect();
-                // // assert_eq!(&amplitudes, &[0, 0, 0, 8, 0, 0, 0, 0]);
-                // println!("amps> {:?}", amplitudes);
-                // let args: Vec<_> = spectrum.iter().map(|c| c.arg() as u32).collect();
-                // // assert_eq!(&amplitudes, &[0, 0, 0, 8, 0, 0, 0, 0]);
-                // println!("args> {:?}", args);
-                // println!();
-
-
-                let samples_real = w.iter().map(|y| {
-                    Complex::new(volt_decode(*y as u16) as f64 + 100.0, 0.0)
-                }).collect::<Vec<_>>();
-                let mut samples = samples_real.clone();
-
-                // For fake data
-                // let mut samples_real = w.iter().map(|y| {
-                //     let a = 20.0 * (((w_i as f64) + 4.8)/(41.66/(3.58 * 2.0 * std::f64::consts::PI))).sin();
-                //     Complex::new(a, 0.0)
-                // }).collect::<Vec<_>>();
-                // let mut samples = samples_real.clone();
-
-                // let mut indata = vec![0.0f64; 256];
-                let mut spectrum: Vec<Complex<f64>> = vec![Complex::zero(); win_len];
-                let mut outdata: Vec<Complex<f64>> = vec![Complex::zero(); win_len];
-
-                let mut planner = FFTplanner::new(false);
-                let fft = planner.plan_fft(win_len);
-                fft.process(&mut samples, &mut spectrum);
-
-                // spectrum[0] = Complex::zero();
-                // spectrum[1] = Complex::zero();
-                for l in 5..win_len {
-                    spectrum[l] = Complex::zero();
-                }
-                //create an FFT and forward transform the input data
-                // let mut r2c = RealToComplex::<f64>::new(16).unwrap();
-                // r2c.process(&mut samples, &mut spectrum[..]).unwrap();
-
-                // println!("spectrum; {:?}", spectrum);
-                // println!("samps> {:?}", samples_real.iter().map(|x| x.norm()).collect::<Vec<_>>());
-                // println!("spec> {:?}", spectrum.iter().map(|x| x.norm()).collect::<Vec<_>>()[4]);
-                // println!("args> {:?}", spectrum.iter().map(|x| x.arg()).collect::<Vec<_>>()[4]);
-
-
-                // create an iFFT and inverse transform the spectum
-                let _planner_i = FFTplanner::<f64>::new(true);
-                let fft_i = planner.plan_fft(win_len);
-                fft_i.process(&mut spectrum, &mut outdata);
-                // println!("samp2> {:?}", outdata.iter().map(|x| x.norm()/(win_len as f64)).collect::<Vec<_>>());
-                // println!();
-
-                // let spectrum = microfft::real::rfft_16(&mut samps[..]);
-                // let amplitudes: Vec<_> = spectrum.iter().map(|c| c.norm()).collect();
-                // println!("--[a]> {:?}", amplitudes);
-                // let phases: Vec<_> = spectrum.iter().map(|c| c.arg()).collect();
-                // println!("-----> {:?}", phases);
-                // println!();
-                // }
-
-                let segments = false;
-                if segments {
-                    if w_i % win_len == (win_len / 2) {
-                        outdata.iter().map(|x| (x.norm() / (win_len as f64)) as f32).collect::<Vec<_>>()
-                    } else {
-                        vec![]
-                    }
-                } else {
-                    vec![
-                        ((outdata[win_len / 2].norm() / (win_len as f64)) as f32) - 100.0,
-                    ]
-                }
-            }).flatten() {
-            bandpass.push(samp);
-            writeln!(file, "{}", samp).ok();
+    let texture_data = unsafe {
+        &std::slice::from_raw_parts(frame_out.as_ptr() as *const u8, frame_out.len() * (BYTES_PER_SAMPLE as usize))
+    };
+
+    (size, texture_data.to_vec())
+}
+
+/// Opens the capture named by `CAPTURE` with a decoder for `INPUT`.
+fn open_capture() -> (Capture, Decoder) {
+    let mode = InputMode::from_env();
+    let capture_path = std::env::var("CAPTURE").unwrap_or("./captures/1".to_string());
+    let mut capture = Capture::open(&capture_path, mode.channel_count()).unwrap();
+    find_blocks(&mut capture);
+    repair_glitches(&mut capture);
+    (capture, Decoder::new(mode))
+}
+
+/// Splits a capture into DMA blocks as `BLOCKS` says, noting when it's laid
+/// out that way.
+fn find_blocks(capture: &mut Capture) {
+    if capture.find_blocks(capture::Layout::from_env()) {
+        let blocks = capture.blocks();
+        eprintln!("{} DMA blocks of {} samples, decoded separately", blocks.len(), blocks[0].len());
+    }
+}
+
+/// Finds digital glitches in a capture and repairs them, as `GLITCH_SLEW`
+/// and `GLITCH_REPAIR` say, noting how many there were.
+fn repair_glitches(capture: &mut Capture) {
+    let detector = glitch::Detector::from_env();
+    capture.repair_glitches(&detector);
+    let glitches = capture.glitches();
+    if !glitches.is_empty() {
+        let spikes = glitches.iter().filter(|x| x.kind == glitch::Kind::Spike).count();
+        eprintln!(
+            "{} {} glitches: {} with low bits set, {} spikes",
+            if detector.repair { "repaired" } else { "found" }, glitches.len(), glitches.len() - spikes, spikes,
+        );
+    }
+}
+
+/// Rows per decoded field, from `HEIGHT` or the standard's active lines.
+fn field_height(decoder: &Decoder) -> usize {
+    std::env::var("HEIGHT").ok()
+        .map(|x| match x.parse::<usize>() {
+            Ok(height) if height > 0 => height,
+            _ => panic!("invalid HEIGHT {:?}, expected a number of lines above 0", x),
+        })
+        .unwrap_or(decoder.standard.active_lines())
+}
+
+/// Renders the vectorscope of a field the viewer has already decoded, as
+/// RGBA.
+fn vectorscope_overlay(capture: &Capture, decoder: &Decoder, field: Field) -> (wgpu::Extent3d, Vec<u8>) {
+    let (starts, line_samples) = line_starts(capture, decoder);
+    let scan = CaptureLines {
+        field,
+        starts,
+        line_samples,
+    };
+    let channels = (0..capture.channel_count()).map(|c| capture.levels(c)).collect::<Vec<_>>();
+    let points = vectorscope::chroma_points(decoder, &channels, &scan.field_starts(), scan.line_samples, true);
+    let scope = vectorscope::Vectorscope::new(VECTORSCOPE_SIZE, vectorscope::Space::for_input(decoder.mode), decoder.standard);
+    let size = wgpu::Extent3d {
+        width: VECTORSCOPE_SIZE as u32,
+        height: VECTORSCOPE_SIZE as u32,
+        depth: 1,
+    };
+    (size, scope.render(&points).to_rgba8())
+}
+
+/// Decodes every complete field in the capture. A capture too short to hold
+/// one still decodes to a single field padded with blanking. Fields are
+/// cropped to the area picked by `CROP` and `OVERSCAN`, which is returned.
+fn decode_fields(capture: &Capture, decoder: &Decoder) -> (Vec<Field>, overscan::Area) {
+    let mut fields = decoder.decode_fields(capture, field_height(decoder), false);
+    if fields.is_empty() {
+        fields = decoder.decode_fields(capture, field_height(decoder), true);
+    }
+    let area = crop_fields(&mut fields, None);
+    if let Some(channel) = caption::channel_from_env() {
+        overlay_captions(capture, decoder, &mut fields, channel);
+    }
+    (fields, area)
+}
+
+/// Draws caption channel `channel` over each field as it would be on
+/// screen once the field is shown.
+fn overlay_captions(capture: &Capture, decoder: &Decoder, fields: &mut [Field], channel: usize) {
+    let (starts, line_samples) = line_starts(capture, decoder);
+    let lines = vbi::lines(fields, &starts, caption::SEARCH_LINES);
+    let packets = caption::packets(&capture.levels(0), &lines, line_samples);
+    let packets = packets.iter().filter(|x| caption::carries(channel, &x.line)).collect::<Vec<_>>();
+
+    let mut captions = caption::CaptionDecoder::new(channel);
+    let mut next = 0;
+    for (n, field) in fields.iter_mut().enumerate() {
+        while next < packets.len() && packets[next].line.field <= n {
+            captions.feed(packets[next].bytes);
+            next += 1;
         }
+        caption::draw(&mut field.frame, &captions.rows());
+    }
+}
+
+/// Each field's VITC timecode, if it has one.
+fn field_timecodes(capture: &Capture, decoder: &Decoder, fields: &[Field]) -> Vec<Option<vitc::Timecode>> {
+    let (starts, line_samples) = line_starts(capture, decoder);
+    let lines = vbi::lines(fields, &starts, vitc::SEARCH_LINES);
+    let readings = vitc::readings(&capture.levels(0), &lines, line_samples, decoder.standard);
+    vitc::per_field(&readings, fields.len())
+}
+
+/// Each field's widescreen signalling, if it has it. Only 625-line sources
+/// carry it.
+fn field_wss(capture: &Capture, decoder: &Decoder, fields: &[Field]) -> Vec<Option<wss::Wss>> {
+    if decoder.standard != Standard::Pal {
+        return vec![None; fields.len()];
+    }
+    let (starts, line_samples) = line_starts(capture, decoder);
+    let lines = vbi::lines(fields, &starts, wss::SEARCH_LINES);
+    let readings = wss::readings(&capture.levels(0), &lines, line_samples);
+    wss::per_field(&readings, fields.len())
+}
+
+/// Width over height of the source as displayed: 16:9 when WSS signals an
+/// anamorphic picture on most fields, 4:3 otherwise.
+fn source_aspect(wss: &[Option<wss::Wss>]) -> (u32, u32) {
+    match wss::common_format(wss) {
+        Some(format) => {
+            let count = wss.iter().flatten().filter(|x| x.format == format).count();
+            eprintln!("WSS {} on {} of {} fields, displaying at {}:{}", format.name(), count, wss.len(), format.display_aspect().0, format.display_aspect().1);
+            format.display_aspect()
+        }
+        None => standard::DISPLAY_ASPECT,
+    }
+}
+
+/// Crops fields to `area`, or to the area picked by `CROP` and `OVERSCAN`
+/// for these fields if None.
+fn crop_fields(fields: &mut [Field], area: Option<overscan::Area>) -> overscan::Area {
+    let area = area
+        .or_else(|| overscan::area_from_env(&fields.iter().map(|x| x.frame.clone()).collect::<Vec<_>>()))
+        .unwrap_or(overscan::Area::full(0, 0));
+    for field in fields.iter_mut() {
+        field.frame = area.apply(&field.frame);
+    }
+    area
+}
+
+/// Scales a decoded picture to the output size if `SCALE` is set, then
+/// applies CRT emulation if `CRT` is set. `display_aspect` is the picture's
+/// width over height as displayed.
+fn postprocess(frame: Frame, display_aspect: f32) -> Frame {
+    let lines = frame.height;
+    let (frame, top, height) = match Scaler::from_env() {
+        Some(scaler) => {
+            let (_, top, _, height) = scaler.placement(frame.height, display_aspect);
+            (scaler.scale(&frame, display_aspect), top, height)
+        }
+        None => {
+            let height = frame.height;
+            (frame, 0, height)
+        }
+    };
+    match crt::Crt::from_env() {
+        Some(crt) => crt.apply(&frame, top, height, lines),
+        None => frame,
+    }
+}
+
+/// Decoded pictures ready to be written to a video container.
+struct Pictures {
+    frames: Vec<Frame>,
+    /// Pictures per second.
+    rate: (u32, u32),
+    interlace: y4m::Interlace,
+    /// Pixel aspect ratio.
+    aspect: (u32, u32),
+    /// Each picture's VITC timecode, from its first field that has one.
+    timecodes: Vec<Option<vitc::Timecode>>,
+}
 
+/// Decodes the capture for video export. Fields are kept as progressive
+/// pictures at field rate unless `--frames` is given, which weaves pairs of
+/// fields into interlaced frames (top field first, or `--bff`) from the
+/// first field of that parity on, or `DEINTERLACE` is set, which makes a
+/// full-height progressive picture of each field. Deinterlacing is skipped
+/// for progressive (240p) sources.
+fn decode_pictures(args: &[String]) -> Pictures {
+    let frames = args.iter().any(|x| x == "--frames");
+    let bff = args.iter().any(|x| x == "--bff");
+
+    let (capture, decoder) = open_capture();
+    let standard = decoder.standard;
+    let (fields, area) = decode_fields(&capture, &decoder);
+    let timecodes = field_timecodes(&capture, &decoder, &fields);
+    let source_aspect = source_aspect(&field_wss(&capture, &decoder, &fields));
+    let (fields, parities): (Vec<_>, Vec<_>) = fields.into_iter()
+        .map(|field| (field.frame, field.second))
+        .unzip();
+
+    let mut deinterlacing = deinterlace::Mode::from_env();
+    if deinterlacing.is_some() && deinterlace::is_progressive(&parities) {
+        eprintln!("progressive source, not deinterlacing");
+        deinterlacing = None;
+    }
 
-        let sample_subset = frame_out[dim_range].iter()
-            .map(|x| volt_decode(*x as u16))
+    let (pictures, rate, interlace, timecodes) = if let Some(mode) = deinterlacing {
+        let mut bottom = deinterlace::resolve_parities(&parities);
+        if bff {
+            bottom.iter_mut().for_each(|x| *x = !*x);
+        }
+        (deinterlace::deinterlace(&fields, &bottom, mode), standard.field_rate(), y4m::Interlace::Progressive, timecodes)
+    } else if frames {
+        // Pair fields from the first one that starts a frame.
+        let first = deinterlace::resolve_parities(&parities).iter().position(|x| *x == bff).unwrap_or(0);
+        let woven = fields[first..].chunks_exact(2)
+            .map(|pair| if bff {
+                frame::weave(&pair[1], &pair[0])
+            } else {
+                frame::weave(&pair[0], &pair[1])
+            })
             .collect::<Vec<_>>();
+        let interlace = if bff { y4m::Interlace::BottomFieldFirst } else { y4m::Interlace::TopFieldFirst };
+        let timecodes = timecodes[first..].chunks_exact(2).map(|pair| pair[0].or(pair[1])).collect();
+        (woven, standard.frame_rate(), interlace, timecodes)
+    } else {
+        (fields, standard.field_rate(), y4m::Interlace::Progressive, timecodes)
+    };
 
-        // Create out-sine.csv, which is a sine wave at the carrier frequency.
-        let mut file = File::create("./out/out-sine.csv").unwrap();
-        for (i, _samp) in sample_subset.iter().enumerate() {
-            writeln!(file, "{}", 20.0 * carrier_freq(i).sin()).ok();
+    let height = match pictures.first() {
+        Some(picture) => picture.height,
+        None => {
+            eprintln!("capture has no complete {}", if frames { "frames" } else { "fields" });
+            std::process::exit(1);
         }
+    };
+    // A lone field is displayed at twice its line count.
+    let displayed_height = if frames || deinterlacing.is_some() { height } else { height * 2 };
+    // Cropping doesn't change the shape of pixels, so take it from the
+    // whole picture.
+    let mut aspect = standard::pixel_aspect(
+        area.source_width,
+        area.source_height * displayed_height / area.height,
+        source_aspect,
+    );
+
+    // Scaled output is already shaped for square pixels.
+    if Scaler::from_env().is_some() {
+        aspect = (1, 1);
+    }
+    let display_aspect = area.display_aspect(standard::ratio(source_aspect));
+    let pictures = pictures.into_iter().map(|x| postprocess(x, display_aspect)).collect();
+
+    Pictures {
+        frames: pictures,
+        rate,
+        interlace,
+        aspect,
+        timecodes,
+    }
+}
 
-        // Create out-Y.csv
-        let mut avg = std::collections::VecDeque::new();
-        let mut y_out = vec![];
-        for samp in &sample_subset {
-            avg.push_front(*samp);
-            if avg.len() > 12 {
-                avg.pop_back();
-            }
-            let mut res = vec_avg(&avg);
-            if res < -100. {
-                res = -100.
-            }
-            y_out.push(res);
+/// The first argument that isn't a flag or a flag's value.
+fn path_arg<'a>(args: &'a [String], flags_with_values: &[&str]) -> Option<&'a str> {
+    let mut skip = false;
+    for arg in args {
+        if skip {
+            skip = false;
+        } else if flags_with_values.contains(&arg.as_str()) {
+            skip = true;
+        } else if !arg.starts_with("--") {
+            return Some(arg);
         }
-        // Write out file.
-        let mut file = File::create("./out/out-Y.csv").unwrap();
-        for samp in &y_out {
-            writeln!(file, "{}", samp).ok();
+    }
+    None
+}
+
+/// The value following `flag`, if given.
+fn flag_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter().position(|x| x == flag).and_then(|i| args.get(i + 1)).map(|x| x.as_str())
+}
+
+/// Opens `path` for writing, creating its directory, or stdout for `-`.
+fn open_output(path: &str) -> BufWriter<Box<dyn Write>> {
+    let out: Box<dyn Write> = if path == "-" {
+        Box::new(std::io::stdout())
+    } else {
+        if let Some(dir) = Path::new(path).parent() {
+            std::fs::create_dir_all(dir).unwrap();
         }
+        Box::new(File::create(path).unwrap())
+    };
+    BufWriter::new(out)
+}
 
-        // Create out-I.csv
-        let mut avg = std::collections::VecDeque::new();
-        let mut i_out = vec![];
-        for (i, samp) in sample_subset.iter().enumerate() {
-            avg.push_front(samp * carrier_freq(i).sin() * 4.);
-            if avg.len() > 12 {
-                avg.pop_back();
-            }
-            let mut res = vec_avg(&avg);
-            if res < -100. {
-                res = -100.
+/// `y4m [path|-] [--frames] [--bff]`: writes decoded video as YUV4MPEG2,
+/// with each picture's VITC timecode as an `XVITC` frame parameter.
+fn export_y4m(args: &[String]) {
+    let path = path_arg(args, &[]).unwrap_or("-");
+    let pictures = decode_pictures(args);
+
+    let first = &pictures.frames[0];
+    let mut writer = y4m::Y4mWriter::new(
+        open_output(path), first.width, first.height, pictures.rate, pictures.interlace, pictures.aspect,
+    ).unwrap();
+    for (picture, timecode) in pictures.frames.iter().zip(&pictures.timecodes) {
+        match timecode {
+            Some(timecode) => writer.write_frame_with(picture, &[format!("XVITC={}", timecode)]).unwrap(),
+            None => writer.write_frame(picture).unwrap(),
+        }
+    }
+    writer.finish().unwrap();
+}
+
+/// `avi [path] [--uyvy] [--frames] [--bff] [--audio file.wav]`: writes
+/// decoded video as an uncompressed AVI. Audio comes from `--audio`, or a
+/// `.wav` file next to the capture if there is one. The first VITC timecode
+/// is recorded as the start timecode.
+fn export_avi(args: &[String]) {
+    let path = path_arg(args, &["--audio"]).unwrap_or("out/capture.avi");
+    let format = if args.iter().any(|x| x == "--uyvy") {
+        avi::PixelFormat::Uyvy
+    } else {
+        avi::PixelFormat::Rgb24
+    };
+    let pictures = decode_pictures(args);
+
+    let audio_path = flag_value(args, "--audio").map(|x| x.to_string()).or_else(|| {
+        let capture_path = std::env::var("CAPTURE").unwrap_or("./captures/1".to_string());
+        let sidecar = format!("{}.wav", capture_path);
+        if Path::new(&sidecar).exists() { Some(sidecar) } else { None }
+    });
+    let audio = audio_path.map(|x| avi::read_wav(&x).unwrap());
+
+    let first = &pictures.frames[0];
+    let mut width = first.width;
+    if format == avi::PixelFormat::Uyvy && width % 2 == 1 {
+        // UYVY pairs pixels, so drop the odd column.
+        width -= 1;
+    }
+    let file = File::create(path).unwrap();
+    let timecode = pictures.timecodes.iter().flatten().next().map(|x| x.to_string());
+    let mut writer = avi::AviWriter::new(
+        BufWriter::new(file), width, first.height, pictures.rate, format, audio.as_ref().map(|a| a.0), timecode,
+    ).unwrap();
+
+    let mut audio_written = 0;
+    for (i, picture) in pictures.frames.iter().enumerate() {
+        let written = if width == picture.width {
+            writer.write_frame(picture)
+        } else {
+            writer.write_frame(&picture.crop(0, 0, width, picture.height))
+        };
+
+        // Keep audio interleaved with the frame it plays under.
+        let written = written.and_then(|_| {
+            if let Some((format, ref samples)) = audio {
+                let end = (i as u64 + 1) * format.sample_rate as u64 * pictures.rate.1 as u64 / pictures.rate.0 as u64;
+                let end = std::cmp::min(end as usize * format.channels as usize, samples.len());
+                if end > audio_written {
+                    writer.write_audio(&samples[audio_written..end])?;
+                    audio_written = end;
+                }
             }
-            i_out.push(res);
+            Ok(())
+        });
+        if let Err(err) = written {
+            eprintln!("stopped at frame {} of {}: {}", i, pictures.frames.len(), err);
+            break;
+        }
+    }
+    writer.finish().unwrap();
+}
+
+/// `png [dir] [--16]`: writes every field as a numbered PNG, recording how
+/// it was decoded, its sync and burst measurements and its VITC timecode in
+/// tEXt chunks.
+/// `--16` writes 16 bits per channel.
+fn export_png(args: &[String]) {
+    let dir = path_arg(args, &[]).unwrap_or("out/fields");
+    let sixteen_bit = args.iter().any(|x| x == "--16");
+    std::fs::create_dir_all(dir).unwrap();
+
+    let capture_path = std::env::var("CAPTURE").unwrap_or("./captures/1".to_string());
+    let (capture, decoder) = open_capture();
+    let standard = decoder.standard;
+
+    let (fields, area) = decode_fields(&capture, &decoder);
+    let timecodes = field_timecodes(&capture, &decoder, &fields);
+    let wss = field_wss(&capture, &decoder, &fields);
+    let display_aspect = area.display_aspect(standard::ratio(source_aspect(&wss)));
+    for (n, field) in fields.iter().enumerate() {
+        let stats = &field.stats;
+        let mut text = vec![
+            ("Software".to_string(), "av2hdmi frame-decode".to_string()),
+            ("Capture".to_string(), capture_path.clone()),
+            ("Standard".to_string(), standard.name().to_string()),
+            ("Input".to_string(), format!("{:?}", decoder.mode)),
+            ("Phase offset".to_string(), format!("{} samples", decoder.shift)),
+            ("Calibration".to_string(), format!("ADC zero {}, {} codes per 300", capture::ADC_ZERO, capture::ADC_SCALE)),
+            ("Filter".to_string(), format!("boxcar, {} samples", decoder.chunk_width)),
+            ("Crop".to_string(), format!("{}x{}+{}+{} of {}x{}", area.width, area.height, area.x, area.y, area.source_width, area.source_height)),
+            ("Field".to_string(), n.to_string()),
+            ("Sync lines".to_string(), format!("{}/{}", stats.sync_lines, stats.lines)),
+            ("Sync level".to_string(), format!("{:.2}", stats.sync_level)),
+        ];
+        if let Some(burst) = stats.burst {
+            text.push(("Burst amplitude".to_string(), format!("{:.2}", burst.amplitude)));
+            text.push(("Burst phase".to_string(), format!("{:.2} deg", burst.phase.to_degrees())));
+            text.push(("Burst phase deviation".to_string(), format!("{:.2} deg", burst.phase_deviation.to_degrees())));
         }
-        // Write out file.
-        let mut file = File::create("./out/out-I.csv").unwrap();
-        for samp in &i_out {
-            writeln!(file, "{}", samp).ok();
+        if let Some(timecode) = timecodes[n] {
+            text.push(("Timecode".to_string(), timecode.to_string()));
+        }
+        if let Some(wss) = wss[n] {
+            text.push(("WSS".to_string(), wss.format.name().to_string()));
         }
 
-        // Create out-Q.csv
-        let mut avg = std::collections::VecDeque::<f32>::new();
-        let mut q_out = vec![];
-        for (i, samp) in bandpass.iter().enumerate() {
-            avg.push_front(samp * carrier_freq(i).cos() * 4.);
-            if avg.len() > 12 {
-                avg.pop_back();
-            }
-            let mut res = vec_avg(&avg);
-            if res < -100. {
-                res = -100.
+        let path = Path::new(dir).join(format!("field-{:05}.png", n));
+        let frame = postprocess(field.frame.clone(), display_aspect);
+        pngseq::write_png(&path, &frame, sixteen_bit, &text).unwrap();
+    }
+}
+
+/// `raw [--yuv422p]`: decodes the capture and writes raw fields to stdout,
+/// printing their geometry to stderr so they can be piped into
+/// `ffmpeg -f rawvideo`. With `CAPTURE=-` samples are read from stdin as they
+/// arrive, and each field is written once it is complete.
+fn export_raw(args: &[String]) {
+    use std::io::Read;
+
+    let format = if args.iter().any(|x| x == "--yuv422p") {
+        raw::RawFormat::Yuv422p
+    } else {
+        raw::RawFormat::Rgb24
+    };
+    let mode = InputMode::from_env();
+    let decoder = Decoder::new(mode);
+    let height = field_height(&decoder);
+    let (rate_num, rate_den) = decoder.standard.field_rate();
+
+    let stdout = std::io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    let mut geometry = None;
+    let mut write_field = |frame: &Frame, display_aspect: f32, out: &mut BufWriter<std::io::StdoutLock>| {
+        let frame = &postprocess(frame.clone(), display_aspect);
+        let width = format.fit_width(frame.width);
+        if geometry.is_none() {
+            eprintln!("{}x{} {} @ {}/{}", width, frame.height, format.name(), rate_num, rate_den);
+            eprintln!(
+                "ffmpeg -f rawvideo -pix_fmt {} -s {}x{} -r {}/{} -i - out.mkv",
+                format.name(), width, frame.height, rate_num, rate_den,
+            );
+            geometry = Some(width);
+        }
+        let frame = if width == frame.width { frame.clone() } else { frame.crop(0, 0, width, frame.height) };
+        out.write_all(&raw::frame_bytes(&frame, format)).unwrap();
+    };
+
+    let capture_path = std::env::var("CAPTURE").unwrap_or("./captures/1".to_string());
+    if capture_path != "-" {
+        let mut capture = Capture::open(&capture_path, mode.channel_count()).unwrap();
+        find_blocks(&mut capture);
+        repair_glitches(&mut capture);
+        let (fields, area) = decode_fields(&capture, &decoder);
+        let display_aspect = area.display_aspect(standard::ratio(source_aspect(&field_wss(&capture, &decoder, &fields))));
+        for field in fields {
+            write_field(&field.frame, display_aspect, &mut out);
+        }
+        return;
+    }
+
+    // Decode whatever complete fields are buffered, then keep everything
+    // after the last one for the next read.
+    let detector = glitch::Detector::from_env();
+    let block_bytes = decoder.line_samples * height * mode.channel_count() * BYTES_PER_SAMPLE;
+    let mut pending: Vec<u16> = vec![];
+    let mut area = None;
+    let mut bytes = vec![0; block_bytes];
+    let mut odd_byte = None;
+    let stdin = std::io::stdin();
+    let mut input = stdin.lock();
+    loop {
+        let read = input.read(&mut bytes).unwrap();
+        if read == 0 {
+            break;
+        }
+        let mut data = odd_byte.take().into_iter().chain(bytes[..read].iter().cloned()).collect::<Vec<_>>();
+        if data.len() % 2 == 1 {
+            odd_byte = data.pop();
+        }
+        pending.extend(data.chunks_exact(2).map(|b| u16::from_ne_bytes([b[0], b[1]])));
+
+        let mut capture = Capture::from_interleaved(&pending, mode.channel_count());
+        capture.repair_glitches(&detector);
+        let mut fields = decoder.decode_fields(&capture, height, false);
+        if let Some(end) = fields.last().map(|x| x.end) {
+            // The picture area is picked from the first fields and kept, so
+            // every field has the same size.
+            let picked = crop_fields(&mut fields, area);
+            area = Some(picked);
+            for field in &fields {
+                write_field(&field.frame, picked.display_aspect(standard::ratio(standard::DISPLAY_ASPECT)), &mut out);
             }
-            q_out.push(res);
+            pending.drain(..end * mode.channel_count());
+            out.flush().unwrap();
         }
-        // Write out file.
-        let mut file = File::create("./out/out-Q.csv").unwrap();
-        for samp in &q_out {
-            writeln!(file, "{}", samp).ok();
+    }
+}
+
+/// The lines of a capture, for analysis.
+struct CaptureLines {
+    /// The field being looked at.
+    field: Field,
+    /// Where every line in the capture starts, at the end of its sync.
+    starts: Vec<usize>,
+    line_samples: usize,
+}
+
+impl CaptureLines {
+    /// Starts of the field's own lines.
+    fn field_starts(&self) -> Vec<usize> {
+        self.starts.iter().cloned().filter(|x| *x >= self.field.start && *x < self.field.end).collect()
+    }
+
+    /// Index in `starts` of line `n`, numbered from vertical sync when the
+    /// capture has it and from the field's first row otherwise.
+    fn line_index(&self, n: usize) -> usize {
+        let first = self.starts.iter().position(|x| *x >= self.field.start).unwrap_or(0);
+        let index = first as isize + n as isize - self.field.first_line.unwrap_or(0) as isize;
+        if index < 0 || index as usize >= self.starts.len() {
+            panic!("line {} isn't in the capture", n);
         }
+        index as usize
+    }
 
-        // let _ = bandpass
-        //     .windows(win_len)
-        //     .enumerate()
-        //     .map(|(w_i, w)| {
-        //         let mut samples_real = w.iter().map(|y| {
-        //             // TODO don't volt_decode wtf
-        //             Complex::new(*y as f64, 0.0)
-        //         }).collect::<Vec<_>>();
-        //         let mut samples = samples_real.clone();
-
-        //         // let mut indata = vec![0.0f64; 256];
-        //         let mut spectrum: Vec<Complex<f64>> = vec![Complex::zero(); win_len];
-        //         let mut outdata: Vec<Complex<f64>> = vec![Complex::zero(); win_len];
-
-        //         let mut planner = FFTplanner::new(false);
-        //         let fft = planner.plan_fft(win_len);
-        //         fft.process(&mut samples, &mut spectrum);
-
-        //         println!("spectrum; {:?}", &spectrum[2..5]);
-
-
-        //         // For fake data
-        //         let mut samples_real = w.iter().map(|y| {
-        //             let a = 20.0 * (((w_i as f64) + *y as f64 + 4.8)/(41.66/(3.58 * 2.0 * std::f64::consts::PI))).sin();
-        //             Complex::new(a, 0.0)
-        //         }).collect::<Vec<_>>();
-        //         let mut samples = samples_real.clone();
-
-        //         // let mut indata = vec![0.0f64; 256];
-        //         let mut spectrum: Vec<Complex<f64>> = vec![Complex::zero(); win_len];
-        //         let mut outdata: Vec<Complex<f64>> = vec![Complex::zero(); win_len];
-
-        //         let mut planner = FFTplanner::new(false);
-        //         let fft = planner.plan_fft(win_len);
-        //         fft.process(&mut samples, &mut spectrum);
-
-        //         println!("comparison; {:?}", &spectrum[2..5]);
-
-        //         println!();
-        //     })
-        //     .collect::<Vec<_>>();
-
-        // for i in 0..dim_full_width*5 {
-        //     // value += ((frame_out[i] as f32) - value) / smoothing;
-        //     writeln!(file, "{}", volt_decode(frame_out[i] as u16));
-        // }
-    }
-
-    if true {
-        let path = Path::new(r"out/frame.png");
-        let file = File::create(path).unwrap();
-        let ref mut w = BufWriter::new(file);
-
-        let mut encoder = png::Encoder::new(w, dim_width as u32, dim_height as u32); // Width is 2 pixels and height is 1.
-        encoder.set(png::ColorType::RGBA).set(png::BitDepth::Eight);
-        let mut writer = encoder.write_header().unwrap();
-
-        // Enumerate over frame chunks.
-        let data = {
-            frame_out
-                .chunks(dim_full_width)
-                .map(|x| {
-                    let mut input = x.iter()
-                        .map(|x| *x as u16)
-                        .collect::<Vec<_>>();
-
-                    // Find the inline carrier signal.
-                    let i_fall = input.windows(chunk_width).enumerate().rev().find_map(|(i, samples)| {
-                        let sample = samples.iter()
-                            .map(|x| volt_decode(*x))
-                            .sum::<f32>() / (chunk_width as f32);
-                        if sample < -40. && sample > -100. {
-                            Some(i)
-                        } else {
-                            None
-                        }
-                    }).unwrap_or(0);
-                    let i_rise = input.windows(chunk_width).enumerate().find_map(|(i, samples)| {
-                        if i < i_fall {
-                            return None;
-                        }
-                        let sample = samples.iter()
-                            .map(|x| volt_decode(*x))
-                            .sum::<f32>() / (chunk_width as f32);
-                        if sample > -5. {
-                            Some(i)
-                        } else {
-                            None
-                        }
-                    }).unwrap_or(0);
-
-                    // TODO this should rotate on x, not samples_vec, for precise targeting
-                    input.rotate_left(i_rise);
-
-                    // Compute average voltage over every chunk.
-                    let samples_vec = input
-                        .chunks(chunk_width)
-                        .map(move |y| {
-                            y.iter().map(|s| {
-                                volt_decode(*s as u16)
-                            }).collect::<Vec<_>>()
-                        })
-                        .collect::<Vec<_>>();
-
-                    // Convert to RGB values.
-                    return samples_vec.clone().into_iter()
-                        .enumerate()
-                        .map(move |(chunk_i, samples)| {
-                        let chunk_index = chunk_i * chunk_width;
-
-                        // Calculate YIQ against carrier frequency.
-                        let y_sample = samples.iter().sum::<f32>() / (chunk_width as f32);
-                        let i_sample = samples.iter()
-                            .enumerate()
-                            .map(|(i, x)| x * carrier_freq(i + chunk_index).sin() * 4.)
-                            .sum::<f32>() / (chunk_width as f32);
-                        let q_sample = samples.iter()
-                            .enumerate()
-                            .map(|(i, x)| x * carrier_freq(i + chunk_index).cos() * 4.)
-                            .sum::<f32>() / (chunk_width as f32);
-
-                        let i_amps = samples.iter()
-                            .enumerate()
-                            .map(|(i, x)| x * carrier_freq(i + chunk_index).sin() * 4.)
-                            .map(|y| (y * 100.) as u32) // two digit precision
-                            .collect::<Vec<_>>();
-                        let i_amp_input = (i_amps.iter().max().unwrap() - i_amps.iter().min().unwrap()) as f32;
-                        let i_amp = num::clamp(i_amp_input / 80000., 0., 1.0);
-                        // let i_amp = 1.0;
-                        let q_amps = samples.iter()
-                            .enumerate()
-                            .map(|(i, x)| x * carrier_freq(i + chunk_index).cos() * 4.)
-                            .map(|y| (y * 100.) as u32) // two digit precision
-                            .collect::<Vec<_>>();
-                        let q_amp_input = (q_amps.iter().max().unwrap() - q_amps.iter().min().unwrap()) as f32;
-                        let q_amp = num::clamp(q_amp_input / 80000., 0., 1.0);
-                        // let q_amp = 1.0;
-
-                        // println!("i_amp {:?} q_amp {:?}", i_amp_input, q_amp_input);
-
-                        let y_clamped = num::clamp(y_sample, 0., 140.) / 140.;
-                        let i_clamped = (num::clamp(i_sample, -60., 60.) / 60.) * i_amp;
-                        let q_clamped = (num::clamp(q_sample, -60., 60.) / 60.) * q_amp;
-                        // println!("{:?}", (y_clamped, i_clamped, q_clamped));
-
-                        // Uncomment for monochrome
-                        // i_clamped = 0.;
-                        // q_clamped = 0.;
-
-                        // let matrix = ndarray::arr1(&[y_sample, i_clamped, q_clamped]);
-                        // let con_matrix = ndarray::arr2(&[
-                        //     [1., 0.956, 0.619],
-                        //     [1., -0.272, -0.647],
-                        //     [1., -1.106, 1.703]
-                        // ]);
-                        // let rgb_matrix = matrix.dot(&con_matrix);
-                        // println!("{:?}", rgb_matrix);
-
-                        let r = y_clamped + (2.4563 * i_clamped) + (1.6190 * q_clamped);
-                        let g = y_clamped - (0.2721 * i_clamped) - (0.6474 * q_clamped);
-                        let b = y_clamped - (1.1070 * i_clamped) + (1.7046 * q_clamped);
-                        // println!("    rgb -----> {:?}", (r, g, b));
-                        let r_ = r * 255.;
-                        let g_ = g * 255.;
-                        let b_ = b * 255.;
-
-                        // if avg.len() > 12 {
-                        //     avg.pop_back();
-                        // }
-                        // let mut res = vec_avg(&avg);
-                        // if res < -60. {
-                        //     res = -60.
-                        // } else if res > 60. {
-                        // res = 60
-                        // }
-
-                        return vec![r_ as u8, g_ as u8, b_ as u8, 255];
-                    })
-                    .flatten();
-            }).flatten().collect::<Vec<_>>()
-        };
-        writer.write_image_data(&data).unwrap(); // Save
+    /// Number of the line at `index` in `starts`, see `line_index`.
+    fn line_number(&self, index: usize) -> isize {
+        let first = self.starts.iter().position(|x| *x >= self.field.start).unwrap_or(0);
+        index as isize - first as isize + self.field.first_line.unwrap_or(0) as isize
     }
+}
 
-    // let mut fake_frame: Vec<u8> = vec![];
-    // for y in 0..182 {
-    //     for x in 0..165 {
-    //         for i in 0..16 {
-    //             fake_frame.extend(&[y]);
-    //         }
-    //     }
-    // }
+/// Where every line of a capture starts, at the end of its sync, and the
+/// length of a line in samples, as the decoder finds them.
+fn line_starts(capture: &Capture, decoder: &Decoder) -> (Vec<usize>, usize) {
+    let sync = capture.levels(0);
+    let line_samples = match decoder.mode {
+        InputMode::Component => decode::measure_line_period(&sync).unwrap_or(decoder.line_samples),
+        _ => decoder.line_samples,
+    };
+    (decode::assemble_lines(&sync, capture.blocks(), line_samples, decoder.chunk_width), line_samples)
+}
 
-    let size = wgpu::Extent3d {
-        width: dim_full_width as u32,
-        height: dim_height as u32,
-        depth: 1,
+/// Finds the lines of a capture and decodes field `field_index`, which may
+/// be partial.
+fn capture_lines(capture: &Capture, decoder: &Decoder, field_index: usize) -> CaptureLines {
+    let mut fields = decoder.decode_fields(capture, field_height(decoder), true);
+    if field_index >= fields.len() {
+        panic!("capture has {} fields", fields.len());
+    }
+    let field = fields.swap_remove(field_index);
+    let (starts, line_samples) = line_starts(capture, decoder);
+    CaptureLines {
+        field,
+        starts,
+        line_samples,
+    }
+}
+
+/// `waveform [path] [--field N] [--line N] [--composite] [--size WxH]`:
+/// renders a field's lines overlaid on an IRE graticule, `out/waveform.png`
+/// by default. `--line` shows a single line, numbered from vertical sync
+/// when the capture has it and from the field's first row otherwise.
+/// `--composite` keeps the chroma subcarrier, adding S-Video's channels
+/// back together.
+fn export_waveform(args: &[String]) {
+    let path = path_arg(args, &["--field", "--line", "--size"]).unwrap_or("out/waveform.png");
+    let mode = if args.iter().any(|x| x == "--composite") {
+        waveform::Mode::Composite
+    } else {
+        waveform::Mode::Luma
+    };
+    let (width, height) = flag_value(args, "--size")
+        .map(|x| scale::parse_size(x).unwrap_or_else(|| panic!("invalid --size {:?}", x)))
+        .unwrap_or((1024, 512));
+    if width < waveform::MIN_WIDTH || height < waveform::MIN_HEIGHT {
+        panic!("--size {}x{} too small for the waveform, expected at least {}x{}", width, height, waveform::MIN_WIDTH, waveform::MIN_HEIGHT);
+    }
+    let field_index = flag_value(args, "--field").map(|x| x.parse::<usize>().unwrap()).unwrap_or(0);
+    let line = flag_value(args, "--line").map(|x| x.parse::<usize>().unwrap());
+
+    let (capture, decoder) = open_capture();
+    let scan = capture_lines(&capture, &decoder, field_index);
+    let (starts, line_samples) = (&scan.starts, scan.line_samples);
+
+    let sync = capture.levels(0);
+    let signal = if mode == waveform::Mode::Composite && decoder.mode == InputMode::SVideo {
+        sync.iter().zip(capture.levels(1)).map(|(y, c)| y + c).collect()
+    } else {
+        sync.clone()
+    };
+    let rows = match line {
+        Some(n) => vec![starts[scan.line_index(n)]],
+        None => scan.field_starts(),
     };
 
-    let texture_data = unsafe {
-        &std::slice::from_raw_parts(frame_out.as_ptr() as *const u8, frame_out.len() * (BYTES_PER_SAMPLE as usize))
+    let lead = waveform::Waveform::lead_samples();
+    let lines = rows.iter()
+        .map(|rise| {
+            let fall = decode::find_sync_fall(&sync, *rise);
+            decode::line_at(&signal, fall.saturating_sub(lead), line_samples)
+        })
+        .collect::<Vec<_>>();
+
+    let monitor = waveform::Waveform::new(width, height, mode, line_samples);
+    let text = vec![
+        ("Software".to_string(), "av2hdmi frame-decode".to_string()),
+        ("Mode".to_string(), format!("{:?}", mode)),
+        ("Field".to_string(), field_index.to_string()),
+        ("Lines".to_string(), match line { Some(n) => n.to_string(), None => format!("{} overlaid", lines.len()) }),
+    ];
+    if let Some(dir) = Path::new(path).parent() {
+        std::fs::create_dir_all(dir).unwrap();
+    }
+    pngseq::write_png(path, &monitor.render(&lines), false, &text).unwrap();
+}
+
+/// Demodulates the chroma of a field's lines for the vectorscope, see
+/// `vectorscope::chroma_points`.
+fn field_chroma(capture: &Capture, decoder: &Decoder, field_index: usize, locked: bool) -> Vec<(f32, f32)> {
+    let scan = capture_lines(capture, decoder, field_index);
+    let channels = (0..capture.channel_count()).map(|c| capture.levels(c)).collect::<Vec<_>>();
+    vectorscope::chroma_points(decoder, &channels, &scan.field_starts(), scan.line_samples, locked)
+}
+
+/// `vectorscope [path] [--field N] [--size N] [--unlocked]`: plots a
+/// field's chroma on a vectorscope graticule with 75% bar targets and the
+/// burst reference, `out/vectorscope.png` by default. Hue is measured
+/// against each line's burst unless `--unlocked` is given, which uses the
+/// decoder's carrier and `SHIFT` instead.
+fn export_vectorscope(args: &[String]) {
+    let path = path_arg(args, &["--field", "--size"]).unwrap_or("out/vectorscope.png");
+    let size = flag_value(args, "--size").map(|x| x.parse::<usize>().unwrap()).unwrap_or(512);
+    let field_index = flag_value(args, "--field").map(|x| x.parse::<usize>().unwrap()).unwrap_or(0);
+    let locked = !args.iter().any(|x| x == "--unlocked");
+
+    let (capture, decoder) = open_capture();
+    let points = field_chroma(&capture, &decoder, field_index, locked);
+    let scope = vectorscope::Vectorscope::new(size, vectorscope::Space::for_input(decoder.mode), decoder.standard);
+
+    let text = vec![
+        ("Software".to_string(), "av2hdmi frame-decode".to_string()),
+        ("Input".to_string(), format!("{:?}", decoder.mode)),
+        ("Field".to_string(), field_index.to_string()),
+        ("Reference".to_string(), if locked { "burst".to_string() } else { format!("decoder carrier, shift {}", decoder.shift) }),
+    ];
+    if let Some(dir) = Path::new(path).parent() {
+        std::fs::create_dir_all(dir).unwrap();
+    }
+    pngseq::write_png(path, &scope.render(&points), false, &text).unwrap();
+}
+
+/// Parses a range like `80..85` (end excluded) or a single value `80`.
+fn parse_range(value: &str) -> Option<std::ops::Range<usize>> {
+    match value.find("..") {
+        Some(i) => {
+            let (from, to) = (value[..i].parse().ok()?, value[i + 2..].parse().ok()?);
+            if from < to { Some(from..to) } else { None }
+        }
+        None => value.parse().ok().map(|x: usize| x..x + 1),
+    }
+}
+
+/// `plot [path] [--field N] [--lines A..B] [--signals LIST] [--size WxH]`:
+/// plots signals over a run of whole lines against time in µs and level in
+/// IRE, as SVG if `path` ends in `.svg` and PNG otherwise, `out/plot.svg`
+/// by default. Lines are numbered as for `waveform --line` and default to
+/// `80..85`. `--signals` is a comma-separated list of `raw`, `y`, `i`, `q`,
+/// `burst` and `sync`, all of them by default.
+fn export_plot(args: &[String]) {
+    let path = path_arg(args, &["--field", "--lines", "--signals", "--size"]).unwrap_or("out/plot.svg");
+    let field_index = flag_value(args, "--field").map(|x| x.parse::<usize>().unwrap()).unwrap_or(0);
+    let lines = flag_value(args, "--lines")
+        .map(|x| parse_range(x).unwrap_or_else(|| panic!("invalid --lines {:?}", x)))
+        .unwrap_or(80..85);
+    let signals = flag_value(args, "--signals")
+        .map(|x| plot::Signal::parse_list(x).unwrap_or_else(|| panic!("invalid --signals {:?}", x)))
+        .unwrap_or(plot::Signal::ALL.to_vec());
+
+    let (capture, decoder) = open_capture();
+    let scan = capture_lines(&capture, &decoder, field_index);
+    let channels = (0..capture.channel_count()).map(|c| capture.levels(c)).collect::<Vec<_>>();
+    let first = scan.line_index(lines.start);
+    let span = plot::Span::lines(&channels[0], &scan.starts, first, first + lines.len(), scan.line_samples);
+    let (traces, markers) = plot::traces(&decoder, &channels, &scan.starts, scan.line_samples, &span, scan.line_number(0), &signals);
+
+    let (width, height) = flag_value(args, "--size")
+        .map(|x| scale::parse_size(x).unwrap_or_else(|| panic!("invalid --size {:?}", x)))
+        .unwrap_or((400 * lines.len(), 400));
+    if width < plot::MIN_WIDTH || height < plot::MIN_HEIGHT {
+        panic!("--size {}x{} too small for the plot, expected at least {}x{}", width, height, plot::MIN_WIDTH, plot::MIN_HEIGHT);
+    }
+    let chart = plot::Plot::new(width, height, span.len);
+
+    if let Some(dir) = Path::new(path).parent() {
+        std::fs::create_dir_all(dir).unwrap();
+    }
+    if path.ends_with(".svg") {
+        std::fs::write(path, chart.svg(&traces, &markers)).unwrap();
+    } else {
+        let text = vec![
+            ("Software".to_string(), "av2hdmi frame-decode".to_string()),
+            ("Field".to_string(), field_index.to_string()),
+            ("Lines".to_string(), format!("{}..{}", lines.start, lines.end)),
+            ("Signals".to_string(), traces.iter().map(|x| x.label).collect::<Vec<_>>().join(",")),
+        ];
+        pngseq::write_png(path, &chart.render(&traces, &markers), false, &text).unwrap();
+    }
+}
+
+/// `signals [path|-] [--field N] [--lines A..B | --samples A..B]
+/// [--columns LIST] [--channel N] [--binary]`: writes per-sample signals
+/// over a run of lines or samples as CSV with a header row, or in
+/// `signals`' binary columnar format with `--binary` or a `.bin` path.
+/// Lines are numbered as for `waveform --line` and default to `80..85`;
+/// `--samples` counts from the start of the capture instead. `code`,
+/// `volts` and `ire` come from `--channel`, the sync channel by default.
+fn export_signals(args: &[String]) {
+    let path = path_arg(args, &["--field", "--lines", "--samples", "--columns", "--channel"]).unwrap_or("out/signals.csv");
+    let binary = args.iter().any(|x| x == "--binary") || path.ends_with(".bin");
+    let field_index = flag_value(args, "--field").map(|x| x.parse::<usize>().unwrap()).unwrap_or(0);
+    let columns = flag_value(args, "--columns")
+        .map(|x| signals::Column::parse_list(x).unwrap_or_else(|| panic!("invalid --columns {:?}", x)))
+        .unwrap_or(signals::Column::ALL.to_vec());
+    let channel = flag_value(args, "--channel").map(|x| x.parse::<usize>().unwrap()).unwrap_or(0);
+
+    let (capture, decoder) = open_capture();
+    if channel >= capture.channel_count() {
+        panic!("capture has {} channels", capture.channel_count());
+    }
+    let scan = capture_lines(&capture, &decoder, field_index);
+    let channels = (0..capture.channel_count()).map(|c| capture.levels(c)).collect::<Vec<_>>();
+    let span = match flag_value(args, "--samples") {
+        Some(x) => {
+            let samples = parse_range(x).unwrap_or_else(|| panic!("invalid --samples {:?}", x));
+            let end = samples.end.min(capture.len());
+            plot::Span {
+                start: samples.start.min(end),
+                len: end - samples.start.min(end),
+            }
+        }
+        None => {
+            let lines = flag_value(args, "--lines")
+                .map(|x| parse_range(x).unwrap_or_else(|| panic!("invalid --lines {:?}", x)))
+                .unwrap_or(80..85);
+            let first = scan.line_index(lines.start);
+            plot::Span::lines(&channels[0], &scan.starts, first, first + lines.len(), scan.line_samples)
+        }
+    };
+    let source = signals::Source {
+        decoder: &decoder,
+        channels: &channels,
+        raw: capture.channel(channel),
+        starts: &scan.starts,
+        line_samples: scan.line_samples,
+        first_line: scan.line_number(0),
     };
 
-    (size, texture_data.to_vec())
+    let mut out = open_output(path);
+    if binary {
+        signals::write_binary(&mut out, &source, &columns, &span).unwrap();
+    } else {
+        signals::write_csv(&mut out, &source, &columns, &span).unwrap();
+    }
+    eprintln!("{} samples from {}, {} columns", span.len, span.start, columns.len());
+}
+
+/// `spectrum [dir] [--field N] [--width N]`: writes the spectra of each of
+/// a field's lines as `burst.csv` and `active.csv`, one row per line, and
+/// as images of the same name, in `out/spectrum` by default. Then prints the
+/// subcarrier frequency measured from every burst in the capture.
+fn export_spectrum(args: &[String]) {
+    let dir = Path::new(path_arg(args, &["--field", "--width"]).unwrap_or("out/spectrum"));
+    let field_index = flag_value(args, "--field").map(|x| x.parse::<usize>().unwrap()).unwrap_or(0);
+    let width = flag_value(args, "--width").map(|x| x.parse::<usize>().unwrap()).unwrap_or(512);
+    if width < spectrum::MIN_WIDTH {
+        panic!("--width {} too small for the spectrum, expected at least {}", width, spectrum::MIN_WIDTH);
+    }
+
+    let (capture, decoder) = open_capture();
+    let scan = capture_lines(&capture, &decoder, field_index);
+    let chroma = capture.levels(if decoder.mode == InputMode::SVideo { 1 } else { 0 });
+    let subcarrier_hz = decoder.standard.subcarrier_hz();
+    let starts = scan.field_starts();
+    let first = scan.starts.iter().position(|x| *x >= scan.field.start).unwrap_or(0);
+
+    std::fs::create_dir_all(dir).unwrap();
+    for region in spectrum::Region::ALL.iter() {
+        let spectra = spectrum::line_spectra(&chroma, &starts, *region);
+        let mut csv = BufWriter::new(File::create(dir.join(format!("{}.csv", region.name()))).unwrap());
+        let bins = spectra.first().map(|x| x.len()).unwrap_or(0);
+        let header = (0..bins).map(|x| format!("{:.4}", spectrum::bin_mhz(x, region.fft_size()))).collect::<Vec<_>>();
+        writeln!(csv, "line,{}", header.join(",")).unwrap();
+        for (n, levels) in spectra.iter().enumerate() {
+            let levels = levels.iter().map(|x| format!("{:.2}", x)).collect::<Vec<_>>();
+            writeln!(csv, "{},{}", scan.line_number(first + n), levels.join(",")).unwrap();
+        }
+
+        let text = vec![
+            ("Software".to_string(), "av2hdmi frame-decode".to_string()),
+            ("Region".to_string(), region.name().to_string()),
+            ("Field".to_string(), field_index.to_string()),
+            ("Scale".to_string(), format!("{} to {} dB re 100 IRE", spectrum::DB_MIN, spectrum::DB_MAX)),
+        ];
+        pngseq::write_png(dir.join(format!("{}.png", region.name())), &spectrum::render(&spectra, width, (subcarrier_hz / 1e6) as f32), false, &text).unwrap();
+    }
+
+    match spectrum::estimate_subcarrier(&chroma, &scan.starts, subcarrier_hz) {
+        Some(estimate) => {
+            println!("subcarrier: {:.2} Hz +/- {:.2} Hz at {} MHz sampling, from {} bursts in {} runs",
+                estimate.hz(), estimate.error_hz(), decode::SAMPLE_RATE_MHZ, estimate.lines, estimate.runs);
+            println!("cycles per sample: {:.12} ({:+.2} ppm from an exact {} subcarrier at {} MHz)",
+                estimate.cycles_per_sample, estimate.offset_ppm(subcarrier_hz), decoder.standard.name(), decode::SAMPLE_RATE_MHZ);
+            println!("sample rate for an exact {} subcarrier: {:.2} Hz",
+                decoder.standard.name(), estimate.sample_rate_hz(subcarrier_hz));
+        }
+        None => println!("subcarrier: not enough burst to measure"),
+    }
+}
+
+/// `inspect [path|-]`: writes a JSON report of the capture's signal
+/// quality, overall and for each field, to stdout by default.
+fn export_inspect(args: &[String]) {
+    let path = path_arg(args, &[]).unwrap_or("-");
+
+    let (capture, decoder) = open_capture();
+    let scan = capture_lines(&capture, &decoder, 0);
+    let fields = decoder.decode_fields(&capture, field_height(&decoder), true);
+    let lines = vbi::lines(&fields, &scan.starts, caption::SEARCH_LINES);
+    let captions = caption::packets(&capture.levels(0), &lines, scan.line_samples);
+    let lines = vbi::lines(&fields, &scan.starts, vitc::SEARCH_LINES);
+    let timecodes = vitc::readings(&capture.levels(0), &lines, scan.line_samples, decoder.standard);
+    let name = std::env::var("CAPTURE").unwrap_or("./captures/1".to_string());
+    let report = inspect::Report {
+        name: &name,
+        decoder: &decoder,
+        capture: &capture,
+        starts: &scan.starts,
+        line_samples: scan.line_samples,
+        fields: &fields,
+        captions: &captions,
+        timecodes: &timecodes,
+    };
+
+    let mut out = open_output(path);
+    writeln!(out, "{}", report.to_json()).unwrap();
+}
+
+/// `captions [path|-] [--channel N] [--scc]`: decodes the closed captions
+/// on line 21 of each field and writes channel CC`N` (CC1 by default) as
+/// SRT, `out/captions.srt` by default, or as Scenarist SCC with `--scc` or
+/// a `.scc` path.
+fn export_captions(args: &[String]) {
+    let path = path_arg(args, &["--channel"]).unwrap_or("out/captions.srt");
+    let scc = args.iter().any(|x| x == "--scc") || path.ends_with(".scc");
+    let channel = flag_value(args, "--channel")
+        .map(|x| caption::parse_channel(x).unwrap_or_else(|| panic!("invalid --channel {:?}", x)))
+        .unwrap_or(1);
+
+    let (capture, decoder) = open_capture();
+    let fields = decoder.decode_fields(&capture, field_height(&decoder), true);
+    let (starts, line_samples) = line_starts(&capture, &decoder);
+    let lines = vbi::lines(&fields, &starts, caption::SEARCH_LINES);
+    let packets = caption::packets(&capture.levels(0), &lines, line_samples);
+
+    let mut out = open_output(path);
+    let errors = packets.iter()
+        .flat_map(|x| x.bytes.iter())
+        .filter(|x| !caption::parity_ok(**x))
+        .count();
+    if scc {
+        caption::write_scc(&mut out, &packets, channel).unwrap();
+        eprintln!("{} of {} fields sliced, {} parity errors", packets.len(), fields.len(), errors);
+    } else {
+        let cues = caption::cues(&packets, channel);
+        caption::write_srt(&mut out, &cues).unwrap();
+        eprintln!("{} of {} fields sliced, {} parity errors, {} cues on CC{}", packets.len(), fields.len(), errors, cues.len(), channel);
+    }
+}
+
+/// `vitc [path|-]`: reads the VITC timecode in each field and writes a CSV
+/// row for each one found, `out/vitc.csv` by default.
+fn export_vitc(args: &[String]) {
+    let path = path_arg(args, &[]).unwrap_or("out/vitc.csv");
+
+    let (capture, decoder) = open_capture();
+    let fields = decoder.decode_fields(&capture, field_height(&decoder), true);
+    let (starts, line_samples) = line_starts(&capture, &decoder);
+    let lines = vbi::lines(&fields, &starts, vitc::SEARCH_LINES);
+    let readings = vitc::readings(&capture.levels(0), &lines, line_samples, decoder.standard);
+
+    let mut out = open_output(path);
+    writeln!(out, "field,line,start_sample,time,timecode,drop_frame,color_frame,field_mark,user_bits").unwrap();
+    for reading in &readings {
+        let (line, timecode) = (&reading.line, &reading.timecode);
+        writeln!(
+            out, "{},{},{},{:.6},{},{},{},{},{:08x}",
+            line.field, line.number, line.start, line.time(), timecode,
+            timecode.drop_frame as u8, timecode.color_frame as u8, timecode.field_mark as u8, timecode.user_bits,
+        ).unwrap();
+    }
+    eprintln!("{} of {} fields with timecode", readings.len(), fields.len());
+}
+
+/// `vbi [path|-] [--lines A..B]`: writes the vertical blanking lines of
+/// every field as calibrated raw samples for external decoders,
+/// `out/vbi.bin` by default. Lines default to the standard's VBI after
+/// vertical sync.
+fn export_vbi(args: &[String]) {
+    let path = path_arg(args, &["--lines"]).unwrap_or("out/vbi.bin");
+
+    let (capture, decoder) = open_capture();
+    let numbers = flag_value(args, "--lines")
+        .map(|x| parse_range(x).unwrap_or_else(|| panic!("invalid --lines {:?}", x)))
+        .unwrap_or_else(|| vbi::default_lines(decoder.standard));
+    if numbers.is_empty() {
+        panic!("--lines is empty");
+    }
+    let fields = decoder.decode_fields(&capture, field_height(&decoder), true);
+    let (starts, line_samples) = line_starts(&capture, &decoder);
+    let lines = vbi::lines(&fields, &starts, numbers.start..=numbers.end - 1);
+
+    let mut out = open_output(path);
+    vbi::write_raw(&mut out, &capture.levels(0), &lines, line_samples, decoder.standard).unwrap();
+    eprintln!("{} lines of {} samples from {} fields", lines.len(), line_samples, fields.len());
+}
+
+/// `teletext [path|-] [--page NNN]`: decodes the teletext in the blanking
+/// lines of each field and writes every page received, or only page `NNN`,
+/// as text, `out/teletext.txt` by default.
+fn export_teletext(args: &[String]) {
+    let path = path_arg(args, &["--page"]).unwrap_or("out/teletext.txt");
+    let page = flag_value(args, "--page").map(|x| {
+        u16::from_str_radix(x, 16).ok()
+            .filter(|x| (0x100..0x900).contains(x))
+            .unwrap_or_else(|| panic!("invalid --page {:?}", x))
+    });
+
+    let (capture, decoder) = open_capture();
+    let fields = decoder.decode_fields(&capture, field_height(&decoder), true);
+    let (starts, line_samples) = line_starts(&capture, &decoder);
+    let lines = vbi::lines(&fields, &starts, teletext::LINES);
+    let packets = teletext::packets(&capture.levels(0), &lines, line_samples);
+    let mut assembler = teletext::Assembler::new();
+    for packet in &packets {
+        assembler.feed(packet);
+    }
+    let pages = assembler.finish();
+
+    let mut out = open_output(path);
+    for page in pages.iter().filter(|x| page.map_or(true, |n| x.number == n)) {
+        writeln!(out, "page {:03X} subpage {:04X}, first at {:.3}s", page.number, page.subcode, page.time).unwrap();
+        writeln!(out, "{}", page.text()).unwrap();
+    }
+    eprintln!("{} packets on {} lines, {} pages", packets.len(), lines.len(), pages.len());
 }
 
 fn main() {
-    framework::run::<Example>("texture-arrays");
+    let args = std::env::args().collect::<Vec<_>>();
+    match args.get(1).map(|x| x.as_str()) {
+        Some("y4m") => export_y4m(&args[2..]),
+        Some("avi") => export_avi(&args[2..]),
+        Some("png") => export_png(&args[2..]),
+        Some("raw") => export_raw(&args[2..]),
+        Some("waveform") => export_waveform(&args[2..]),
+        Some("vectorscope") => export_vectorscope(&args[2..]),
+        Some("plot") => export_plot(&args[2..]),
+        Some("signals") => export_signals(&args[2..]),
+        Some("spectrum") => export_spectrum(&args[2..]),
+        Some("inspect") => export_inspect(&args[2..]),
+        Some("captions") => export_captions(&args[2..]),
+        Some("vitc") => export_vitc(&args[2..]),
+        Some("vbi") => export_vbi(&args[2..]),
+        Some("teletext") => export_teletext(&args[2..]),
+        _ => framework::run::<Example>("texture-arrays"),
+    }
 }
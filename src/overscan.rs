@@ -0,0 +1,265 @@
+use crate::frame::Frame;
+
+/// Luma at or below which a pixel counts as blanking rather than picture.
+pub const BLACK_LEVEL: f32 = 0.08;
+
+/// Share of a row or column's pixels that must be above `BLACK_LEVEL` for it
+/// to count as picture, so noise and the odd hot pixel in blanking don't.
+pub const PICTURE_FRACTION: f32 = 0.02;
+
+/// The part of a decoded picture that is kept.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Area {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    /// Size of the picture the area was taken from.
+    pub source_width: usize,
+    pub source_height: usize,
+}
+
+impl Area {
+    /// The whole of a `width` by `height` picture.
+    pub fn full(width: usize, height: usize) -> Area {
+        Area {
+            x: 0,
+            y: 0,
+            width,
+            height,
+            source_width: width,
+            source_height: height,
+        }
+    }
+
+    /// Crops a picture to the area. Pictures of another size, e.g. a
+    /// partial field, are cropped to as much of the area as they hold.
+    pub fn apply(&self, frame: &Frame) -> Frame {
+        if *self == Area::full(frame.width, frame.height) {
+            return frame.clone();
+        }
+        let x = self.x.min(frame.width);
+        let y = self.y.min(frame.height);
+        frame.crop(x, y, self.width.min(frame.width - x), self.height.min(frame.height - y))
+    }
+
+    /// Width over height of the area as displayed, given that of the whole
+    /// picture.
+    pub fn display_aspect(&self, source_aspect: f32) -> f32 {
+        if self.width == 0 || self.height == 0 {
+            return source_aspect;
+        }
+        source_aspect
+            * (self.width as f32 / self.source_width as f32)
+            * (self.source_height as f32 / self.height as f32)
+    }
+}
+
+/// Margin cropped from one side, in pixels or as a share of the picture.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Margin {
+    Pixels(usize),
+    Percent(f32),
+}
+
+impl Margin {
+    pub fn parse(value: &str) -> Option<Margin> {
+        let value = value.trim();
+        if let Some(percent) = value.strip_suffix('%') {
+            percent.parse().ok().map(Margin::Percent)
+        } else {
+            value.parse().ok().map(Margin::Pixels)
+        }
+    }
+
+    fn pixels(&self, size: usize) -> usize {
+        match *self {
+            Margin::Pixels(pixels) => pixels,
+            Margin::Percent(percent) => (size as f32 * percent / 100.).round() as usize,
+        }
+    }
+}
+
+/// Overscan cropped from each side of the picture area.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Overscan {
+    pub top: Margin,
+    pub right: Margin,
+    pub bottom: Margin,
+    pub left: Margin,
+}
+
+impl Overscan {
+    pub fn none() -> Overscan {
+        Overscan {
+            top: Margin::Pixels(0),
+            right: Margin::Pixels(0),
+            bottom: Margin::Pixels(0),
+            left: Margin::Pixels(0),
+        }
+    }
+
+    /// Parses one to four comma-separated margins, ordered as in CSS: all
+    /// sides; vertical, horizontal; top, horizontal, bottom; or top, right,
+    /// bottom, left. Each is pixels, or a percentage like `5%`.
+    pub fn parse(value: &str) -> Option<Overscan> {
+        let margins = value.split(',').map(Margin::parse).collect::<Option<Vec<_>>>()?;
+        let (top, right, bottom, left) = match margins[..] {
+            [all] => (all, all, all, all),
+            [vertical, horizontal] => (vertical, horizontal, vertical, horizontal),
+            [top, horizontal, bottom] => (top, horizontal, bottom, horizontal),
+            [top, right, bottom, left] => (top, right, bottom, left),
+            _ => return None,
+        };
+        Some(Overscan { top, right, bottom, left })
+    }
+
+    /// Reads `OVERSCAN`, defaulting to no margins.
+    pub fn from_env() -> Overscan {
+        match std::env::var("OVERSCAN") {
+            Ok(value) => Overscan::parse(&value).unwrap_or_else(|| panic!("invalid OVERSCAN {:?}", value)),
+            Err(_) => Overscan::none(),
+        }
+    }
+
+    /// Shrinks an area by the margins. Percentages are of the area's size.
+    pub fn shrink(&self, area: Area) -> Area {
+        let left = self.left.pixels(area.width);
+        let right = self.right.pixels(area.width);
+        let top = self.top.pixels(area.height);
+        let bottom = self.bottom.pixels(area.height);
+        Area {
+            x: area.x + left.min(area.width),
+            y: area.y + top.min(area.height),
+            width: area.width.saturating_sub(left + right).max(1),
+            height: area.height.saturating_sub(top + bottom).max(1),
+            ..area
+        }
+    }
+}
+
+fn is_picture(pixel: [f32; 3]) -> bool {
+    0.299 * pixel[0] + 0.587 * pixel[1] + 0.114 * pixel[2] > BLACK_LEVEL
+}
+
+/**
+ * Finds the first and last lines and columns holding picture in any of
+ * `frames`, so every picture is cropped the same even when some are dark.
+ * Returns the whole picture if nothing rises above blanking.
+ */
+pub fn detect_active_area(frames: &[Frame]) -> Option<Area> {
+    let first = frames.first()?;
+    let (width, height) = (first.width, first.height);
+    let mut rows = vec![0; height];
+    let mut columns = vec![0; width];
+    let mut count = 0;
+    for frame in frames.iter().filter(|x| x.width == width && x.height == height) {
+        count += 1;
+        for (y, row) in rows.iter_mut().enumerate() {
+            for (x, pixel) in frame.row(y).iter().enumerate() {
+                if is_picture(*pixel) {
+                    *row += 1;
+                    columns[x] += 1;
+                }
+            }
+        }
+    }
+
+    let row_min = ((width * count) as f32 * PICTURE_FRACTION).max(1.) as usize;
+    let column_min = ((height * count) as f32 * PICTURE_FRACTION).max(1.) as usize;
+    let span = |counts: &[usize], min: usize| {
+        let first = counts.iter().position(|x| *x >= min)?;
+        let last = counts.iter().rposition(|x| *x >= min)?;
+        Some((first, last + 1 - first))
+    };
+    match (span(&columns, column_min), span(&rows, row_min)) {
+        (Some((x, width)), Some((y, height))) => Some(Area { x, y, width, height, ..Area::full(first.width, first.height) }),
+        _ => Some(Area::full(width, height)),
+    }
+}
+
+/// Whether `CROP=auto` asks for the picture area to be detected.
+pub fn auto_from_env() -> bool {
+    match std::env::var("CROP").as_ref().map(|x| x.as_str()) {
+        Ok("auto") => true,
+        Ok("none") | Err(_) => false,
+        Ok(other) => panic!("unknown CROP {:?}", other),
+    }
+}
+
+/// The area of `frames` to keep: the detected picture area if `CROP=auto`,
+/// or the whole picture, less the `OVERSCAN` margins.
+pub fn area_from_env(frames: &[Frame]) -> Option<Area> {
+    let first = frames.first()?;
+    let area = if auto_from_env() {
+        detect_active_area(frames)?
+    } else {
+        Area::full(first.width, first.height)
+    };
+    Some(Overscan::from_env().shrink(area))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_css_forms() {
+        use Margin::*;
+        let sides = |value: &str| Overscan::parse(value).map(|o| (o.top, o.right, o.bottom, o.left));
+        assert_eq!(sides("8"), Some((Pixels(8), Pixels(8), Pixels(8), Pixels(8))));
+        assert_eq!(sides("4, 5%"), Some((Pixels(4), Percent(5.), Pixels(4), Percent(5.))));
+        assert_eq!(sides("1,2,3"), Some((Pixels(1), Pixels(2), Pixels(3), Pixels(2))));
+        assert_eq!(sides("1,2,3,4"), Some((Pixels(1), Pixels(2), Pixels(3), Pixels(4))));
+        assert_eq!(sides("1,2,3,4,5"), None);
+        assert_eq!(sides("1,x"), None);
+        assert_eq!(sides("-1"), None);
+    }
+
+    #[test]
+    fn shrinks_and_crops() {
+        let area = Overscan::parse("10,5%").unwrap().shrink(Area::full(720, 240));
+        assert_eq!((area.x, area.y, area.width, area.height), (36, 10, 648, 220));
+        // Keeping 90% of the width and 11/12 of the height.
+        assert!((area.display_aspect(4. / 3.) - 4. / 3. * 0.9 * 12. / 11.).abs() < 1e-5);
+
+        let mut frame = Frame::new(720, 240);
+        frame.set(36, 10, [1.; 3]);
+        let cropped = area.apply(&frame);
+        assert_eq!((cropped.width, cropped.height), (648, 220));
+        assert_eq!(cropped.get(0, 0), [1.; 3]);
+
+        // A partial field keeps as much of the area as it has.
+        let cropped = area.apply(&Frame::new(720, 100));
+        assert_eq!((cropped.width, cropped.height), (648, 90));
+        let cropped = area.apply(&Frame::new(20, 5));
+        assert_eq!((cropped.width, cropped.height), (0, 0));
+
+        // Margins wider than the picture leave a pixel.
+        let area = Overscan::parse("60%").unwrap().shrink(Area::full(10, 10));
+        assert_eq!((area.width, area.height), (1, 1));
+    }
+
+    #[test]
+    fn detects_active_area() {
+        // Picture in columns 20..80 and rows 10..40 of one frame and 10..50
+        // of another, with a hot pixel in blanking.
+        let picture = |rows: std::ops::Range<usize>| {
+            let mut frame = Frame::new(100, 60);
+            for y in rows {
+                for x in 20..80 {
+                    frame.set(x, y, [0.5; 3]);
+                }
+            }
+            frame
+        };
+        let mut first = picture(10..40);
+        first.set(90, 0, [1.; 3]);
+        let area = detect_active_area(&[first, picture(10..50)]).unwrap();
+        assert_eq!((area.x, area.y, area.width, area.height), (20, 10, 60, 40));
+        assert_eq!((area.source_width, area.source_height), (100, 60));
+
+        assert_eq!(detect_active_area(&[Frame::new(4, 3)]), Some(Area::full(4, 3)));
+        assert_eq!(detect_active_area(&[]), None);
+    }
+}
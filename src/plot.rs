@@ -0,0 +1,455 @@
+use std::fmt::Write;
+use crate::capture::to_ire;
+use crate::decode::{self, Decoder, InputMode, SAMPLE_RATE_MHZ};
+use crate::font;
+use crate::frame::{self, Frame};
+use crate::waveform::{self, GRATICULE_IRE, IRE_MAX, IRE_MIN, SETUP_IRE};
+
+/// Time tick spacings to pick from, in µs.
+const TICK_STEPS_US: [f32; 8] = [1., 2., 5., 10., 20., 50., 100., 200.];
+
+/// Closest time ticks may be, in pixels.
+const MIN_TICK_SPACING: f32 = 48.;
+
+const MARGIN_LEFT: usize = 36;
+const MARGIN_RIGHT: usize = 8;
+const MARGIN_TOP: usize = 16;
+const MARGIN_BOTTOM: usize = 20;
+
+/// Smallest plot area worth drawing, in pixels each way.
+const MIN_PLOT: usize = 16;
+
+/// Smallest picture with room for the labels around the plot.
+pub const MIN_WIDTH: usize = MARGIN_LEFT + MARGIN_RIGHT + MIN_PLOT;
+pub const MIN_HEIGHT: usize = MARGIN_TOP + MARGIN_BOTTOM + MIN_PLOT;
+
+const BACKGROUND_COLOR: [f32; 3] = [0., 0., 0.];
+const GRATICULE_COLOR: [f32; 3] = [0.45, 0.35, 0.1];
+const LABEL_COLOR: [f32; 3] = [0.8, 0.65, 0.2];
+const SYNC_COLOR: [f32; 3] = [0.9, 0.2, 0.2];
+
+/// A signal that can be plotted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Signal {
+    /// The first channel as captured.
+    Raw,
+    /// Luma, with the subcarrier filtered out.
+    Y,
+    /// Demodulated chroma against the decoder's carrier, or Pb for component.
+    I,
+    /// Demodulated chroma against the decoder's carrier, or Pr for component.
+    Q,
+    /// Each line's burst, continued across the line as a sine.
+    Burst,
+    /// Sync leading and trailing edges, drawn as markers.
+    Sync,
+}
+
+impl Signal {
+    pub const ALL: [Signal; 6] = [Signal::Raw, Signal::Y, Signal::I, Signal::Q, Signal::Burst, Signal::Sync];
+
+    pub fn parse(value: &str) -> Option<Signal> {
+        match value.trim() {
+            "raw" => Some(Signal::Raw),
+            "y" => Some(Signal::Y),
+            "i" => Some(Signal::I),
+            "q" => Some(Signal::Q),
+            "burst" => Some(Signal::Burst),
+            "sync" => Some(Signal::Sync),
+            _ => None,
+        }
+    }
+
+    /// Parses a comma-separated list like `raw,y,sync`.
+    pub fn parse_list(value: &str) -> Option<Vec<Signal>> {
+        value.split(',').map(Signal::parse).collect()
+    }
+
+    pub fn label(&self, mode: InputMode) -> &'static str {
+        match (self, mode) {
+            (Signal::Raw, _) => "RAW",
+            (Signal::Y, _) => "Y",
+            (Signal::I, InputMode::Component) => "PB",
+            (Signal::I, _) => "I",
+            (Signal::Q, InputMode::Component) => "PR",
+            (Signal::Q, _) => "Q",
+            (Signal::Burst, _) => "BURST",
+            (Signal::Sync, _) => "SYNC",
+        }
+    }
+
+    pub fn color(&self) -> [f32; 3] {
+        match self {
+            Signal::Raw => [0.55, 0.55, 0.55],
+            Signal::Y => [0.3, 1., 0.4],
+            Signal::I => [1., 0.55, 0.15],
+            Signal::Q => [0.3, 0.6, 1.],
+            Signal::Burst => [0.85, 0.35, 0.85],
+            Signal::Sync => SYNC_COLOR,
+        }
+    }
+}
+
+/// A signal's level at each sample of the plotted span, in IRE.
+pub struct Trace {
+    pub signal: Signal,
+    pub label: &'static str,
+    pub levels: Vec<f32>,
+}
+
+/// A sync edge, at a sample of the plotted span.
+pub struct Marker {
+    pub sample: usize,
+    /// Whether this is the leading edge (0H) rather than the end of sync.
+    pub leading: bool,
+    /// Line number shown at leading edges.
+    pub line: Option<isize>,
+}
+
+/// A run of whole lines of a capture, from `LEAD_US` before the first one's
+/// sync leading edge to the same point of the line after the last.
+pub struct Span {
+    pub start: usize,
+    pub len: usize,
+}
+
+impl Span {
+    /// The span holding lines `starts[first..last]`, given where each line's
+    /// sync ends.
+    pub fn lines(sync: &[f32], starts: &[usize], first: usize, last: usize, line_samples: usize) -> Span {
+        let lead = waveform::Waveform::lead_samples();
+        let fall = |n: usize| match starts.get(n) {
+            Some(rise) => decode::find_sync_fall(sync, *rise),
+            None => decode::find_sync_fall(sync, starts[first]) + (n - first) * line_samples,
+        };
+        let start = fall(first).saturating_sub(lead);
+        let end = fall(last).saturating_sub(lead).min(sync.len());
+        Span {
+            start,
+            len: end.saturating_sub(start),
+        }
+    }
+}
+
+/**
+ * Computes `signals` over a span of the capture. Chroma is demodulated one
+ * `chunk_width` window around each sample with the carrier phase of the
+ * line the sample falls in, as the decoder sees it; I and Q are scaled to
+ * the IRE of the subcarrier they stand for. Component input has no burst.
+ * `first_line` numbers the line starting in `starts[0]` for the markers.
+ */
+pub fn traces(decoder: &Decoder, channels: &[Vec<f32>], starts: &[usize], line_samples: usize, span: &Span, first_line: isize, signals: &[Signal]) -> (Vec<Trace>, Vec<Marker>) {
+    let sync = &channels[0];
+    let range = span.start..span.start + span.len;
+    let chroma = match decoder.mode {
+        InputMode::SVideo => &channels[1],
+        _ => &channels[0],
+    };
+    // The line a sample belongs to and how far into it the sample is.
+    let owner = |at: usize| {
+        let n = starts.partition_point(|x| *x <= at).saturating_sub(1);
+        (n, at.saturating_sub(starts.get(n).cloned().unwrap_or(0)))
+    };
+    let width = decoder.chunk_width;
+    let demodulated = |at: usize| {
+        let from = at.saturating_sub(width / 2);
+        let window = decode::line_at(chroma, from, width);
+        let (_, offset) = owner(from);
+        let (i, q, _, _) = decoder.demodulate(&window, offset);
+        (to_ire(i / 2.), to_ire(q / 2.))
+    };
+
+    let mut traces = vec![];
+    for signal in signals {
+        let levels = match (signal, decoder.mode) {
+            (Signal::Raw, _) => sync[range.clone()].iter().map(|x| to_ire(*x)).collect(),
+            (Signal::Y, _) => waveform::luma(&sync[range.clone()]).iter().map(|x| to_ire(*x)).collect(),
+            (Signal::I, InputMode::Component) => channels[1][range.clone()].iter().map(|x| to_ire(*x)).collect(),
+            (Signal::Q, InputMode::Component) => channels[2][range.clone()].iter().map(|x| to_ire(*x)).collect(),
+            (Signal::I, _) => range.clone().map(|at| demodulated(at).0).collect(),
+            (Signal::Q, _) => range.clone().map(|at| demodulated(at).1).collect(),
+            (Signal::Burst, InputMode::Component) => continue,
+            (Signal::Burst, _) => {
+                let mut bursts = std::collections::HashMap::new();
+                range.clone()
+                    .map(|at| {
+                        let (n, offset) = owner(at);
+                        let (amplitude, phase) = *bursts.entry(n).or_insert_with(|| {
+                            let line = decoder.prepare_line(channels, starts[n], line_samples);
+                            decoder.measure_burst(&line[if decoder.mode == InputMode::SVideo { 1 } else { 0 }])
+                        });
+                        to_ire(amplitude * (decoder.carrier_freq(offset) + phase).sin())
+                    })
+                    .collect()
+            }
+            (Signal::Sync, _) => continue,
+        };
+        traces.push(Trace {
+            signal: *signal,
+            label: signal.label(decoder.mode),
+            levels,
+        });
+    }
+
+    let mut markers = vec![];
+    if signals.contains(&Signal::Sync) {
+        for (n, rise) in starts.iter().enumerate() {
+            let fall = decode::find_sync_fall(sync, *rise);
+            for (at, leading) in [(fall, true), (*rise, false)].iter() {
+                if range.contains(at) {
+                    markers.push(Marker {
+                        sample: at - span.start,
+                        leading: *leading,
+                        line: if *leading { Some(first_line + n as isize) } else { None },
+                    });
+                }
+            }
+        }
+    }
+    (traces, markers)
+}
+
+fn hex(color: [f32; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", frame::to_u8(color[0]), frame::to_u8(color[1]), frame::to_u8(color[2]))
+}
+
+/**
+ * Plots traces against time in µs and level in IRE, as SVG or as an image.
+ * Time is counted from the sync leading edge `LEAD_US` into the span.
+ */
+pub struct Plot {
+    pub width: usize,
+    pub height: usize,
+    /// Samples across the plot.
+    pub samples: usize,
+}
+
+impl Plot {
+    pub fn new(width: usize, height: usize, samples: usize) -> Plot {
+        Plot {
+            width,
+            height,
+            samples,
+        }
+    }
+
+    fn plot_width(&self) -> usize {
+        self.width.saturating_sub(MARGIN_LEFT + MARGIN_RIGHT).max(1)
+    }
+
+    fn plot_height(&self) -> usize {
+        self.height.saturating_sub(MARGIN_TOP + MARGIN_BOTTOM).max(1)
+    }
+
+    fn x(&self, sample: f32) -> f32 {
+        MARGIN_LEFT as f32 + sample * self.plot_width() as f32 / self.samples.max(1) as f32
+    }
+
+    fn y(&self, ire: f32) -> f32 {
+        let ire = num::clamp(ire, IRE_MIN, IRE_MAX);
+        MARGIN_TOP as f32 + (IRE_MAX - ire) / (IRE_MAX - IRE_MIN) * (self.plot_height() - 1) as f32
+    }
+
+    fn right(&self) -> f32 {
+        (MARGIN_LEFT + self.plot_width()) as f32
+    }
+
+    fn bottom(&self) -> f32 {
+        (MARGIN_TOP + self.plot_height() - 1) as f32
+    }
+
+    /// Time ticks as `(sample, µs)`, spaced so their labels don't collide.
+    fn ticks(&self) -> Vec<(f32, f32)> {
+        let lead = waveform::Waveform::lead_samples() as f32;
+        let pixels_per_us = self.plot_width() as f32 / self.samples.max(1) as f32 * SAMPLE_RATE_MHZ;
+        let step = TICK_STEPS_US.iter()
+            .cloned()
+            .find(|x| x * pixels_per_us >= MIN_TICK_SPACING)
+            .unwrap_or(TICK_STEPS_US[TICK_STEPS_US.len() - 1]);
+        (0..)
+            .map(|n| (lead + n as f32 * step * SAMPLE_RATE_MHZ, n as f32 * step))
+            .take_while(|(sample, _)| *sample < self.samples as f32)
+            .collect()
+    }
+
+    pub fn svg(&self, traces: &[Trace], markers: &[Marker]) -> String {
+        let mut out = String::new();
+        let (left, right, top, bottom) = (MARGIN_LEFT as f32, self.right(), MARGIN_TOP as f32, self.bottom());
+        writeln!(out, r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}" font-family="monospace" font-size="9">"#, w = self.width, h = self.height).unwrap();
+        writeln!(out, r#"<rect width="100%" height="100%" fill="{}"/>"#, hex(BACKGROUND_COLOR)).unwrap();
+
+        writeln!(out, r#"<g stroke="{}" stroke-width="1">"#, hex(GRATICULE_COLOR)).unwrap();
+        for ire in GRATICULE_IRE.iter() {
+            writeln!(out, r#"<line x1="{}" y1="{y:.1}" x2="{}" y2="{y:.1}"/>"#, left, right, y = self.y(*ire)).unwrap();
+        }
+        writeln!(out, r#"<line x1="{}" y1="{y:.1}" x2="{}" y2="{y:.1}" stroke-dasharray="4"/>"#, left, right, y = self.y(SETUP_IRE)).unwrap();
+        for (sample, _) in self.ticks() {
+            writeln!(out, r#"<line x1="{x:.1}" y1="{}" x2="{x:.1}" y2="{}" stroke-dasharray="1 2"/>"#, top, bottom, x = self.x(sample)).unwrap();
+        }
+        writeln!(out, "</g>").unwrap();
+
+        writeln!(out, r#"<g fill="{}">"#, hex(LABEL_COLOR)).unwrap();
+        for ire in GRATICULE_IRE.iter() {
+            writeln!(out, r#"<text x="{}" y="{:.1}" text-anchor="end" dominant-baseline="middle">{}</text>"#, left - 4., self.y(*ire), *ire as i32).unwrap();
+        }
+        for (sample, us) in self.ticks() {
+            writeln!(out, r#"<text x="{:.1}" y="{}" text-anchor="middle">{}</text>"#, self.x(sample), bottom + 12., us).unwrap();
+        }
+        writeln!(out, r#"<text x="2" y="{}">IRE</text>"#, bottom + 18.).unwrap();
+        writeln!(out, r#"<text x="{}" y="{}" text-anchor="end">µs</text>"#, right, bottom + 18.).unwrap();
+        writeln!(out, "</g>").unwrap();
+
+        writeln!(out, r#"<g stroke="{}" stroke-width="1" fill="{}">"#, hex(SYNC_COLOR), hex(SYNC_COLOR)).unwrap();
+        for marker in markers {
+            let x = self.x(marker.sample as f32);
+            let dash = if marker.leading { "" } else { r#" stroke-dasharray="3""# };
+            writeln!(out, r#"<line x1="{x:.1}" y1="{}" x2="{x:.1}" y2="{}"{}/>"#, top, bottom, dash, x = x).unwrap();
+            if let Some(line) = marker.line {
+                writeln!(out, r#"<text x="{:.1}" y="{}" stroke="none">{}</text>"#, x + 2., top - 4., line).unwrap();
+            }
+        }
+        writeln!(out, "</g>").unwrap();
+
+        for trace in traces {
+            let points = trace.levels.iter()
+                .enumerate()
+                .map(|(n, ire)| format!("{:.1},{:.1}", self.x(n as f32), self.y(*ire)))
+                .collect::<Vec<_>>();
+            writeln!(out, r#"<polyline fill="none" stroke="{}" stroke-width="1" points="{}"><title>{}</title></polyline>"#, hex(trace.signal.color()), points.join(" "), trace.label).unwrap();
+        }
+
+        let mut x = right;
+        for (label, color) in self.legend(traces, markers).iter().rev() {
+            writeln!(out, r#"<text x="{}" y="{}" text-anchor="end" fill="{}">{}</text>"#, x, top - 4., hex(*color), label).unwrap();
+            x -= 6. * label.chars().count() as f32 + 8.;
+        }
+        writeln!(out, "</svg>").unwrap();
+        out
+    }
+
+    fn legend(&self, traces: &[Trace], markers: &[Marker]) -> Vec<(&'static str, [f32; 3])> {
+        let mut legend = traces.iter().map(|x| (x.label, x.signal.color())).collect::<Vec<_>>();
+        if !markers.is_empty() {
+            legend.push((Signal::Sync.label(InputMode::Composite), SYNC_COLOR));
+        }
+        legend
+    }
+
+    pub fn render(&self, traces: &[Trace], markers: &[Marker]) -> Frame {
+        let mut frame = Frame::new(self.width, self.height);
+        let (left, right, top, bottom) = (MARGIN_LEFT, self.right() as usize, MARGIN_TOP, self.bottom() as usize);
+
+        for ire in GRATICULE_IRE.iter() {
+            let y = self.y(*ire).round() as usize;
+            for x in left..right {
+                frame.set(x, y, GRATICULE_COLOR);
+            }
+            let label = format!("{}", *ire as i32);
+            font::draw_text(&mut frame, left as isize - 4 - font::text_width(&label, 1) as isize, y as isize - 2, &label, 1, LABEL_COLOR);
+        }
+        let setup = self.y(SETUP_IRE).round() as usize;
+        for x in (left..right).filter(|x| (x / 4) % 2 == 0) {
+            frame.set(x, setup, GRATICULE_COLOR);
+        }
+        for (sample, us) in self.ticks() {
+            let x = self.x(sample).round() as usize;
+            for y in (top..=bottom).filter(|y| y % 3 == 0) {
+                frame.set(x, y, GRATICULE_COLOR);
+            }
+            let label = format!("{}", us);
+            font::draw_text(&mut frame, x as isize - font::text_width(&label, 1) as isize / 2, bottom as isize + 5, &label, 1, LABEL_COLOR);
+        }
+        font::draw_text(&mut frame, 2, bottom as isize + 12, "IRE", 1, LABEL_COLOR);
+        font::draw_text(&mut frame, right as isize - font::text_width("us", 1) as isize, bottom as isize + 12, "us", 1, LABEL_COLOR);
+
+        for marker in markers {
+            let x = self.x(marker.sample as f32).round() as usize;
+            for y in (top..=bottom).filter(|y| marker.leading || (y / 3) % 2 == 0) {
+                frame.set(x, y, SYNC_COLOR);
+            }
+            if let Some(line) = marker.line {
+                font::draw_text(&mut frame, x as isize + 2, top as isize - 7, &line.to_string(), 1, SYNC_COLOR);
+            }
+        }
+
+        for trace in traces {
+            let color = trace.signal.color();
+            for (n, pair) in trace.levels.windows(2).enumerate() {
+                let from = (self.x(n as f32), self.y(pair[0]));
+                let to = (self.x((n + 1) as f32), self.y(pair[1]));
+                let steps = (to.0 - from.0).abs().max((to.1 - from.1).abs()).ceil().max(1.) as usize;
+                for step in 0..=steps {
+                    let t = step as f32 / steps as f32;
+                    let x = (from.0 + (to.0 - from.0) * t).round() as usize;
+                    let y = (from.1 + (to.1 - from.1) * t).round() as usize;
+                    if x < self.width && y < self.height {
+                        frame.set(x, y, color);
+                    }
+                }
+            }
+        }
+
+        let mut x = right as isize;
+        for (label, color) in self.legend(traces, markers).iter().rev() {
+            x -= font::text_width(label, 1) as isize;
+            font::draw_text(&mut frame, x, top as isize - 7, label, 1, *color);
+            x -= 8;
+        }
+        frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_signals() {
+        assert_eq!(Signal::parse_list("raw, y,sync"), Some(vec![Signal::Raw, Signal::Y, Signal::Sync]));
+        assert_eq!(Signal::parse_list("raw,u"), None);
+        assert_eq!(Signal::I.label(InputMode::Component), "PB");
+        assert_eq!(Signal::I.label(InputMode::SVideo), "I");
+    }
+
+    #[test]
+    fn spans_lines() {
+        let mut sync = vec![0.; 3000];
+        for line in 0..2 {
+            for x in &mut sync[line * 1000 + 200..line * 1000 + 396] {
+                *x = -56.;
+            }
+        }
+        let lead = waveform::Waveform::lead_samples();
+        let span = Span::lines(&sync, &[396, 1396], 0, 2, 1000);
+        assert_eq!(span.start, 200 - lead);
+        assert_eq!(span.len, 2000);
+        let span = Span::lines(&sync, &[396, 1396], 1, 2, 1000);
+        assert_eq!(span.start, 1200 - lead);
+        assert_eq!(span.len, 1000);
+    }
+
+    #[test]
+    fn draws_traces_and_markers() {
+        let plot = Plot::new(200, 100, 10);
+        let traces = [Trace {
+            signal: Signal::Y,
+            label: "Y",
+            levels: vec![50.; 10],
+        }];
+        let markers = [Marker {
+            sample: 2,
+            leading: true,
+            line: Some(7),
+        }];
+
+        let svg = plot.svg(&traces, &markers);
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains("<title>Y</title>"));
+        assert!(svg.contains(">7</text>"));
+
+        let frame = plot.render(&traces, &markers);
+        let y = plot.y(50.).round() as usize;
+        assert_eq!(frame.get(plot.x(5.).round() as usize, y), Signal::Y.color());
+        assert_eq!(frame.get(plot.x(2.).round() as usize, MARGIN_TOP), SYNC_COLOR);
+    }
+}
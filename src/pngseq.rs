@@ -0,0 +1,108 @@
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+use png::HasParameters;
+use crate::frame::Frame;
+
+/**
+ * Writes a frame as an RGB PNG, either 8 or 16 bits per channel, with each
+ * `(keyword, text)` pair stored in a tEXt chunk.
+ */
+pub fn write_png<P: AsRef<Path>>(
+    path: P,
+    frame: &Frame,
+    sixteen_bit: bool,
+    text: &[(String, String)],
+) -> io::Result<()> {
+    let file = File::create(path)?;
+    let w = &mut BufWriter::new(file);
+
+    let mut encoder = png::Encoder::new(w, frame.width as u32, frame.height as u32);
+    let depth = if sixteen_bit { png::BitDepth::Sixteen } else { png::BitDepth::Eight };
+    encoder.set(png::ColorType::RGB).set(depth);
+    let mut writer = encoder.write_header()?;
+
+    for (keyword, value) in text {
+        let mut chunk = latin1(keyword);
+        chunk.truncate(79);
+        chunk.push(0);
+        chunk.extend(latin1(value));
+        writer.write_chunk(*b"tEXt", &chunk)?;
+    }
+
+    let data = if sixteen_bit {
+        frame.pixels.iter()
+            .flat_map(|p| p.to_vec())
+            .flat_map(|c| {
+                let value = (num::clamp(c, 0., 1.) * 65535.).round() as u16;
+                vec![(value >> 8) as u8, value as u8]
+            })
+            .collect::<Vec<_>>()
+    } else {
+        frame.to_rgb8()
+    };
+    writer.write_image_data(&data)?;
+    Ok(())
+}
+
+/// tEXt chunks are Latin-1; anything outside it becomes '?'.
+fn latin1(value: &str) -> Vec<u8> {
+    value.chars().map(|c| if (c as u32) < 256 { c as u8 } else { b'?' }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Each chunk of a PNG file as `(type, data)`.
+    fn chunks(bytes: &[u8]) -> Vec<([u8; 4], Vec<u8>)> {
+        let mut chunks = vec![];
+        let mut pos = 8;
+        while pos < bytes.len() {
+            let len = u32::from_be_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]]) as usize;
+            let mut kind = [0; 4];
+            kind.copy_from_slice(&bytes[pos + 4..pos + 8]);
+            chunks.push((kind, bytes[pos + 8..pos + 8 + len].to_vec()));
+            pos += 12 + len;
+        }
+        chunks
+    }
+
+    #[test]
+    fn writes_text_and_depth() {
+        let mut frame = Frame::new(2, 1);
+        frame.set(0, 0, [1., 0.5, 0.]);
+        frame.set(1, 0, [0., 0., 2.]);
+        let text = vec![
+            ("Standard".to_string(), "ntsc".to_string()),
+            ("Comment".to_string(), "caf\u{e9} \u{263a}".to_string()),
+        ];
+        let dir = std::env::temp_dir();
+
+        let path = dir.join(format!("pngseq-{}-8.png", std::process::id()));
+        write_png(&path, &frame, false, &text).unwrap();
+        let (info, mut reader) = png::Decoder::new(File::open(&path).unwrap()).read_info().unwrap();
+        assert_eq!((info.width, info.height, info.bit_depth), (2, 1, png::BitDepth::Eight));
+        let mut data = vec![0; info.buffer_size()];
+        reader.next_frame(&mut data).unwrap();
+        assert_eq!(data, vec![255, 127, 0, 0, 0, 255]);
+
+        let bytes = std::fs::read(&path).unwrap();
+        let text_chunks = chunks(&bytes).into_iter()
+            .filter(|(kind, _)| kind == b"tEXt")
+            .map(|(_, data)| data)
+            .collect::<Vec<_>>();
+        assert_eq!(text_chunks, vec![b"Standard\0ntsc".to_vec(), b"Comment\0caf\xe9 ?".to_vec()]);
+
+        let path16 = dir.join(format!("pngseq-{}-16.png", std::process::id()));
+        write_png(&path16, &frame, true, &[]).unwrap();
+        let bytes = std::fs::read(&path16).unwrap();
+        let chunks = chunks(&bytes);
+        assert_eq!(&chunks[0].0, b"IHDR");
+        assert_eq!(chunks[0].1[8], 16);
+        assert!(chunks.iter().all(|(kind, _)| kind != b"tEXt"));
+
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(path16).unwrap();
+    }
+}
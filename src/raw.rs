@@ -0,0 +1,63 @@
+use crate::frame::{Frame, rgb_to_ycbcr, to_u8};
+
+/// Pixel formats understood by `ffmpeg -f rawvideo`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RawFormat {
+    Rgb24,
+    /// Planar 4:2:2 studio-range Y'CbCr.
+    Yuv422p,
+}
+
+impl RawFormat {
+    /// ffmpeg's `-pix_fmt` name.
+    pub fn name(&self) -> &'static str {
+        match self {
+            RawFormat::Rgb24 => "rgb24",
+            RawFormat::Yuv422p => "yuv422p",
+        }
+    }
+
+    /// Width the format can hold; yuv422p needs an even width.
+    pub fn fit_width(&self, width: usize) -> usize {
+        match self {
+            RawFormat::Rgb24 => width,
+            RawFormat::Yuv422p => width & !1,
+        }
+    }
+}
+
+/// Packs a frame, whose width must already fit the format.
+pub fn frame_bytes(frame: &Frame, format: RawFormat) -> Vec<u8> {
+    match format {
+        RawFormat::Rgb24 => frame.pixels.iter()
+            .flat_map(|p| vec![to_u8(p[0]), to_u8(p[1]), to_u8(p[2])])
+            .collect(),
+        RawFormat::Yuv422p => {
+            assert!(frame.width % 2 == 0, "yuv422p needs an even width");
+            let ycbcr = frame.pixels.iter().map(|p| rgb_to_ycbcr(*p)).collect::<Vec<_>>();
+            let mut data = Vec::with_capacity(ycbcr.len() * 2);
+            data.extend(ycbcr.iter().map(|p| p[0]));
+            for plane in 1..3 {
+                data.extend(ycbcr.chunks_exact(2).map(|pair| ((pair[0][plane] as u16 + pair[1][plane] as u16) / 2) as u8));
+            }
+            data
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packs_formats() {
+        let mut frame = Frame::new(2, 1);
+        frame.set(0, 0, [1., 1., 1.]);
+        frame.set(1, 0, [0., 0., 1.]);
+        assert_eq!(frame_bytes(&frame, RawFormat::Rgb24), vec![255, 255, 255, 0, 0, 255]);
+        // A full row of luma, then a plane each of Cb and Cr at half width.
+        assert_eq!(frame_bytes(&frame, RawFormat::Yuv422p), vec![235, 41, 184, 119]);
+        assert_eq!(RawFormat::Yuv422p.fit_width(721), 720);
+        assert_eq!(RawFormat::Rgb24.fit_width(721), 721);
+    }
+}
@@ -0,0 +1,78 @@
+/// Lobes on each side of a Lanczos kernel.
+pub const LANCZOS_LOBES: f32 = 3.;
+
+fn sinc(x: f32) -> f32 {
+    if x.abs() < 1e-6 {
+        1.
+    } else {
+        let x = x * std::f32::consts::PI;
+        x.sin() / x
+    }
+}
+
+pub fn lanczos(x: f32, lobes: f32) -> f32 {
+    if x.abs() >= lobes {
+        0.
+    } else {
+        sinc(x) * sinc(x / lobes)
+    }
+}
+
+/**
+ * Resamples a line of RGB samples with a Lanczos filter. Output pixel `n` is
+ * centered on input position `start + n * step`. When `step` is above 1 the
+ * kernel is widened by the same amount, so it also low-passes the signal to
+ * the output's Nyquist rate instead of aliasing.
+ */
+pub fn resample_line(input: &[[f32; 3]], start: f32, step: f32, count: usize) -> Vec<[f32; 3]> {
+    let scale = step.max(1.);
+    let radius = LANCZOS_LOBES * scale;
+
+    (0..count).map(|n| {
+        let center = start + n as f32 * step;
+        let first = (center - radius).ceil().max(0.) as usize;
+        let last = ((center + radius).floor() as usize).min(input.len().saturating_sub(1));
+
+        let mut sum = [0.; 3];
+        let mut weight = 0.;
+        for (i, sample) in input.iter().enumerate().take(last + 1).skip(first) {
+            let w = lanczos((i as f32 - center) / scale, LANCZOS_LOBES);
+            for c in 0..3 {
+                sum[c] += sample[c] * w;
+            }
+            weight += w;
+        }
+        if weight.abs() < 1e-6 {
+            return [0.; 3];
+        }
+        [sum[0] / weight, sum[1] / weight, sum[2] / weight]
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kernel() {
+        assert_eq!(lanczos(0., LANCZOS_LOBES), 1.);
+        for x in 1..4 {
+            assert!(lanczos(x as f32, LANCZOS_LOBES).abs() < 1e-6);
+        }
+        assert_eq!(lanczos(3.5, LANCZOS_LOBES), 0.);
+    }
+
+    #[test]
+    fn resamples_lines() {
+        let ramp = (0..20).map(|i| [i as f32, 1., 0.]).collect::<Vec<_>>();
+        // Whole sample steps pick out the samples themselves.
+        let out = resample_line(&ramp, 4., 1., 4);
+        for (n, p) in out.iter().enumerate() {
+            assert!((p[0] - (4 + n) as f32).abs() < 1e-4 && (p[1] - 1.).abs() < 1e-4, "{:?}", out);
+        }
+        // Half steps land between them, and a flat line stays flat.
+        let out = resample_line(&ramp, 8.5, 0.5, 3);
+        assert!(out.iter().zip(&[8.5, 9., 9.5]).all(|(p, x)| (p[0] - x).abs() < 0.05), "{:?}", out);
+        assert!(resample_line(&ramp, 0., 3., 7).iter().all(|p| (p[1] - 1.).abs() < 1e-4));
+    }
+}
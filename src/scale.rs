@@ -0,0 +1,286 @@
+use crate::frame::Frame;
+
+/// Interpolation used when scaling.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+    Nearest,
+    /// Nearest, with the picture height rounded down to a whole multiple of
+    /// the source so every line is the same size.
+    Integer,
+    Bilinear,
+    /// Catmull-Rom.
+    Bicubic,
+    /// Three-lobe Lanczos.
+    Lanczos,
+}
+
+impl Filter {
+    pub fn parse(name: &str) -> Option<Filter> {
+        match name {
+            "nearest" => Some(Filter::Nearest),
+            "integer" => Some(Filter::Integer),
+            "bilinear" => Some(Filter::Bilinear),
+            "bicubic" => Some(Filter::Bicubic),
+            "lanczos" => Some(Filter::Lanczos),
+            _ => None,
+        }
+    }
+
+    /// Kernel radius in source pixels at 1:1.
+    fn radius(&self) -> f32 {
+        match self {
+            Filter::Nearest | Filter::Integer => 0.5,
+            Filter::Bilinear => 1.,
+            Filter::Bicubic => 2.,
+            Filter::Lanczos => crate::resample::LANCZOS_LOBES,
+        }
+    }
+
+    fn weight(&self, x: f32) -> f32 {
+        let x = x.abs();
+        match self {
+            Filter::Nearest | Filter::Integer => if x < 0.5 { 1. } else { 0. },
+            Filter::Bilinear => (1. - x).max(0.),
+            Filter::Bicubic => {
+                if x < 1. {
+                    1.5 * x * x * x - 2.5 * x * x + 1.
+                } else if x < 2. {
+                    -0.5 * x * x * x + 2.5 * x * x - 4. * x + 2.
+                } else {
+                    0.
+                }
+            }
+            Filter::Lanczos => crate::resample::lanczos(x, crate::resample::LANCZOS_LOBES),
+        }
+    }
+}
+
+/// How the picture is fitted to the output's shape.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Aspect {
+    /// Keep the picture's aspect ratio inside the output, with black bars.
+    Pillarbox,
+    /// Fill the output, distorting the picture.
+    Stretch,
+    /// Keep the aspect ratio and fill the output, cropping what overflows.
+    Zoom,
+}
+
+impl Aspect {
+    pub fn parse(name: &str) -> Option<Aspect> {
+        match name {
+            "pillarbox" | "fit" => Some(Aspect::Pillarbox),
+            "stretch" => Some(Aspect::Stretch),
+            "zoom" => Some(Aspect::Zoom),
+            _ => None,
+        }
+    }
+}
+
+/// Parses an output size, either `WxH` or one of `720p` and `1080p`.
+pub fn parse_size(value: &str) -> Option<(usize, usize)> {
+    match value {
+        "720p" => Some((1280, 720)),
+        "1080p" => Some((1920, 1080)),
+        _ => {
+            let mut parts = value.split('x');
+            let width = parts.next()?.parse().ok()?;
+            let height = parts.next()?.parse().ok()?;
+            Some((width, height))
+        }
+    }
+}
+
+/**
+ * Scales decoded pictures to an output resolution, assuming square output
+ * pixels.
+ */
+#[derive(Clone, Copy, Debug)]
+pub struct Scaler {
+    pub width: usize,
+    pub height: usize,
+    pub filter: Filter,
+    pub aspect: Aspect,
+}
+
+impl Scaler {
+    pub fn new(width: usize, height: usize) -> Scaler {
+        Scaler {
+            width,
+            height,
+            filter: Filter::Bilinear,
+            aspect: Aspect::Pillarbox,
+        }
+    }
+
+    /// Reads `SCALE` (`WxH`, `720p` or `1080p`), `SCALE_FILTER` and
+    /// `ASPECT`. Returns None when `SCALE` isn't set.
+    pub fn from_env() -> Option<Scaler> {
+        let size = std::env::var("SCALE").ok()?;
+        let (width, height) = parse_size(&size).unwrap_or_else(|| panic!("unknown SCALE {:?}", size));
+        let mut scaler = Scaler::new(width, height);
+        scaler.apply_env();
+        Some(scaler)
+    }
+
+    /// Overrides the filter and aspect from `SCALE_FILTER` and `ASPECT`.
+    pub fn apply_env(&mut self) {
+        if let Ok(name) = std::env::var("SCALE_FILTER") {
+            self.filter = Filter::parse(&name).unwrap_or_else(|| panic!("unknown SCALE_FILTER {:?}", name));
+        }
+        if let Ok(name) = std::env::var("ASPECT") {
+            self.aspect = Aspect::parse(&name).unwrap_or_else(|| panic!("unknown ASPECT {:?}", name));
+        }
+    }
+
+    /// The rectangle `(x, y, width, height)` the picture is scaled to, in
+    /// output coordinates. It may extend past the output when zooming.
+    /// `display_aspect` is the picture's intended width over height, e.g.
+    /// 4/3.
+    pub fn placement(&self, source_height: usize, display_aspect: f32) -> (isize, isize, usize, usize) {
+        let (out_w, out_h) = (self.width as f32, self.height as f32);
+        let (mut w, mut h) = match self.aspect {
+            Aspect::Stretch => (out_w, out_h),
+            Aspect::Pillarbox => if out_w / out_h > display_aspect {
+                (out_h * display_aspect, out_h)
+            } else {
+                (out_w, out_w / display_aspect)
+            },
+            Aspect::Zoom => if out_w / out_h > display_aspect {
+                (out_w, out_w / display_aspect)
+            } else {
+                (out_h * display_aspect, out_h)
+            },
+        };
+        if self.filter == Filter::Integer && source_height > 0 {
+            let factor = ((h / source_height as f32).floor()).max(1.);
+            let integer_h = factor * source_height as f32;
+            w *= integer_h / h;
+            h = integer_h;
+        }
+        let (w, h) = (w.round() as usize, h.round() as usize);
+        let x = (self.width as isize - w as isize) / 2;
+        let y = (self.height as isize - h as isize) / 2;
+        (x, y, w, h)
+    }
+
+    /// Scales a picture into a new frame of the output size.
+    pub fn scale(&self, frame: &Frame, display_aspect: f32) -> Frame {
+        let (x, y, w, h) = self.placement(frame.height, display_aspect);
+        let columns = contributions(frame.width, w, self.filter);
+        let rows = contributions(frame.height, h, self.filter);
+
+        // Horizontal pass, then vertical, only over the visible part.
+        let visible_x = num::clamp(-x, 0, w as isize) as usize..num::clamp(self.width as isize - x, 0, w as isize) as usize;
+        let mut wide = Frame::new(w, frame.height);
+        for sy in 0..frame.height {
+            let row = frame.row(sy);
+            for dx in visible_x.clone() {
+                wide.set(dx, sy, apply(&columns[dx], |i| row[i]));
+            }
+        }
+
+        let mut out = Frame::new(self.width, self.height);
+        for (dy, row) in rows.iter().enumerate() {
+            let oy = y + dy as isize;
+            if oy < 0 || oy >= self.height as isize {
+                continue;
+            }
+            for dx in visible_x.clone() {
+                let ox = x + dx as isize;
+                out.set(ox as usize, oy as usize, apply(row, |i| wide.get(dx, i)));
+            }
+        }
+        out
+    }
+}
+
+/// Source indexes and weights making up each of `dst` output pixels.
+fn contributions(src: usize, dst: usize, filter: Filter) -> Vec<Vec<(usize, f32)>> {
+    let ratio = src as f32 / dst.max(1) as f32;
+    // Widen the kernel when shrinking so it low-passes instead of aliasing.
+    let scale = if filter == Filter::Nearest || filter == Filter::Integer { 1. } else { ratio.max(1.) };
+    let radius = filter.radius() * scale;
+
+    (0..dst).map(|d| {
+        let center = (d as f32 + 0.5) * ratio - 0.5;
+        if filter == Filter::Nearest || filter == Filter::Integer {
+            let i = num::clamp(center.round() as isize, 0, src as isize - 1) as usize;
+            return vec![(i, 1.)];
+        }
+
+        let first = (center - radius).ceil() as isize;
+        let last = (center + radius).floor() as isize;
+        let mut taps = (first..=last)
+            .map(|i| (num::clamp(i, 0, src as isize - 1) as usize, filter.weight((i as f32 - center) / scale)))
+            .filter(|(_, w)| *w != 0.)
+            .collect::<Vec<_>>();
+        let total = taps.iter().map(|(_, w)| w).sum::<f32>();
+        if total.abs() > 1e-6 {
+            taps.iter_mut().for_each(|(_, w)| *w /= total);
+        }
+        taps
+    }).collect()
+}
+
+fn apply<F: Fn(usize) -> [f32; 3]>(taps: &[(usize, f32)], pixel: F) -> [f32; 3] {
+    let mut sum = [0.; 3];
+    for (i, w) in taps {
+        let p = pixel(*i);
+        for c in 0..3 {
+            sum[c] += p[c] * w;
+        }
+    }
+    sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scaler(width: usize, height: usize, filter: Filter, aspect: Aspect) -> Scaler {
+        Scaler { width, height, filter, aspect }
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("720p"), Some((1280, 720)));
+        assert_eq!(parse_size("800x600"), Some((800, 600)));
+        assert_eq!(parse_size("800"), None);
+        assert_eq!(parse_size("wide"), None);
+    }
+
+    #[test]
+    fn placement() {
+        let placement = |filter, aspect| scaler(1920, 1080, filter, aspect).placement(480, 4. / 3.);
+        assert_eq!(placement(Filter::Bilinear, Aspect::Pillarbox), (240, 0, 1440, 1080));
+        assert_eq!(placement(Filter::Bilinear, Aspect::Stretch), (0, 0, 1920, 1080));
+        assert_eq!(placement(Filter::Bilinear, Aspect::Zoom), (0, -180, 1920, 1440));
+        // 1080 lines only hold 480 twice over, so the picture shrinks to 960.
+        assert_eq!(placement(Filter::Integer, Aspect::Pillarbox), (320, 60, 1280, 960));
+    }
+
+    #[test]
+    fn scales_with_bars() {
+        let mut frame = Frame::new(4, 3);
+        frame.pixels.iter_mut().for_each(|p| *p = [0.5, 0.25, 1.]);
+        frame.set(0, 0, [1., 1., 1.]);
+
+        // Flat areas stay flat through every filter, and bars are black.
+        for &filter in &[Filter::Nearest, Filter::Bilinear, Filter::Bicubic, Filter::Lanczos] {
+            let out = scaler(16, 6, filter, Aspect::Pillarbox).scale(&frame, 4. / 3.);
+            assert_eq!((out.width, out.height), (16, 6));
+            assert_eq!(out.get(1, 3), [0., 0., 0.]);
+            assert_eq!(out.get(14, 3), [0., 0., 0.]);
+            let p = out.get(9, 4);
+            assert!((p[0] - 0.5).abs() < 1e-3 && (p[2] - 1.).abs() < 1e-3, "{:?} {:?}", filter, p);
+        }
+
+        // Nearest at 2x repeats each source pixel.
+        let out = scaler(8, 6, Filter::Nearest, Aspect::Stretch).scale(&frame, 4. / 3.);
+        for &(x, y) in &[(0, 0), (1, 0), (0, 1), (1, 1)] {
+            assert_eq!(out.get(x, y), [1., 1., 1.]);
+        }
+        assert_eq!(out.get(2, 0), [0.5, 0.25, 1.]);
+    }
+}
@@ -0,0 +1,313 @@
+use std::io::{self, Write};
+use byteorder::{LittleEndian, WriteBytesExt};
+use crate::capture::{to_ire, volt_decode};
+use crate::decode::{self, Decoder, SAMPLE_RATE_MHZ};
+use crate::plot::{self, Signal, Span};
+
+/// Identifies the binary format, followed by its version.
+pub const MAGIC: [u8; 8] = *b"AVSIGNAL";
+pub const VERSION: u16 = 1;
+
+/// Volts per IRE, taking 140 IRE as 1V from sync tip to peak white.
+pub const VOLTS_PER_IRE: f32 = 1. / 140.;
+
+/// A per-sample column that can be exported.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Column {
+    /// Index of the sample in the capture.
+    Sample,
+    /// Line number, counted from each sync leading edge.
+    Line,
+    /// Time since the line's sync leading edge, in µs.
+    Time,
+    /// 12-bit ADC code.
+    Code,
+    /// Level in volts above blanking.
+    Volts,
+    /// Level in IRE.
+    Ire,
+    /// Luma, in IRE.
+    Y,
+    /// Demodulated chroma, in IRE, as for `plot`.
+    I,
+    Q,
+    /// The decoder's subcarrier reference, the sine of its phase.
+    Carrier,
+    /// The line's burst continued as a sine, in IRE.
+    Burst,
+    /// 1 where the signal is below `SYNC_THRESHOLD`, 0 elsewhere.
+    Sync,
+}
+
+/// How a column is stored in the binary format.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    U8 = 0,
+    U16 = 1,
+    I32 = 2,
+    U64 = 3,
+    F32 = 4,
+}
+
+impl Column {
+    pub const ALL: [Column; 12] = [
+        Column::Sample, Column::Line, Column::Time, Column::Code, Column::Volts, Column::Ire,
+        Column::Y, Column::I, Column::Q, Column::Carrier, Column::Burst, Column::Sync,
+    ];
+
+    pub fn parse(value: &str) -> Option<Column> {
+        Column::ALL.iter().cloned().find(|x| x.name() == value.trim())
+    }
+
+    /// Parses a comma-separated list like `time,ire,sync`.
+    pub fn parse_list(value: &str) -> Option<Vec<Column>> {
+        value.split(',').map(Column::parse).collect()
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Column::Sample => "sample",
+            Column::Line => "line",
+            Column::Time => "time",
+            Column::Code => "code",
+            Column::Volts => "volts",
+            Column::Ire => "ire",
+            Column::Y => "y",
+            Column::I => "i",
+            Column::Q => "q",
+            Column::Carrier => "carrier",
+            Column::Burst => "burst",
+            Column::Sync => "sync",
+        }
+    }
+
+    pub fn kind(&self) -> Kind {
+        match self {
+            Column::Sample => Kind::U64,
+            Column::Line => Kind::I32,
+            Column::Code => Kind::U16,
+            Column::Sync => Kind::U8,
+            _ => Kind::F32,
+        }
+    }
+}
+
+/// One column's values over a span.
+pub enum Values {
+    U8(Vec<u8>),
+    U16(Vec<u16>),
+    I32(Vec<i32>),
+    U64(Vec<u64>),
+    F32(Vec<f32>),
+}
+
+impl Values {
+    fn format(&self, row: usize) -> String {
+        match self {
+            Values::U8(x) => x[row].to_string(),
+            Values::U16(x) => x[row].to_string(),
+            Values::I32(x) => x[row].to_string(),
+            Values::U64(x) => x[row].to_string(),
+            Values::F32(x) => x[row].to_string(),
+        }
+    }
+
+    fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        match self {
+            Values::U8(x) => out.write_all(x),
+            Values::U16(x) => x.iter().try_for_each(|v| out.write_u16::<LittleEndian>(*v)),
+            Values::I32(x) => x.iter().try_for_each(|v| out.write_i32::<LittleEndian>(*v)),
+            Values::U64(x) => x.iter().try_for_each(|v| out.write_u64::<LittleEndian>(*v)),
+            Values::F32(x) => x.iter().try_for_each(|v| out.write_f32::<LittleEndian>(*v)),
+        }
+    }
+}
+
+/// A capture's signal, ready to have columns computed over spans of it.
+pub struct Source<'a> {
+    pub decoder: &'a Decoder,
+    /// Every channel's levels, sync first.
+    pub channels: &'a [Vec<f32>],
+    /// The channel's raw samples, for `code`, `volts` and `ire`.
+    pub raw: &'a [u16],
+    /// Where each line starts, at the end of its sync.
+    pub starts: &'a [usize],
+    pub line_samples: usize,
+    /// Number of the line starting at `starts[0]`.
+    pub first_line: isize,
+}
+
+impl<'a> Source<'a> {
+    /// The signal of `plot` named the same, over the span.
+    fn plotted(&self, signal: Signal, span: &Span) -> Vec<f32> {
+        let (traces, _) = plot::traces(self.decoder, self.channels, self.starts, self.line_samples, span, self.first_line, &[signal]);
+        match traces.into_iter().next() {
+            Some(trace) => trace.levels,
+            None => vec![f32::NAN; span.len],
+        }
+    }
+
+    /**
+     * Computes a column over a span. Lines are counted from sync leading
+     * edges here, so a line's sync belongs to it, while chroma and the
+     * carrier follow the decoder in starting each line at the end of sync.
+     */
+    pub fn column(&self, column: Column, span: &Span) -> Values {
+        let range = span.start..span.start + span.len;
+        let sync = &self.channels[0];
+        let falls = || self.starts.iter().map(|x| decode::find_sync_fall(sync, *x)).collect::<Vec<_>>();
+        let line_of = |edges: &[usize], at: usize| edges.partition_point(|x| *x <= at).saturating_sub(1);
+        match column {
+            Column::Sample => Values::U64(range.map(|x| x as u64).collect()),
+            Column::Line => {
+                let falls = falls();
+                Values::I32(range.map(|at| (self.first_line + line_of(&falls, at) as isize) as i32).collect())
+            }
+            Column::Time => {
+                let falls = falls();
+                Values::F32(range
+                    .map(|at| {
+                        let fall = falls.get(line_of(&falls, at)).cloned().unwrap_or(0);
+                        (at as f32 - fall as f32) / SAMPLE_RATE_MHZ
+                    })
+                    .collect())
+            }
+            Column::Code => Values::U16(self.raw[range].iter().map(|x| x >> 4).collect()),
+            Column::Volts => Values::F32(self.raw[range].iter().map(|x| to_ire(volt_decode(*x)) * VOLTS_PER_IRE).collect()),
+            Column::Ire => Values::F32(self.raw[range].iter().map(|x| to_ire(volt_decode(*x))).collect()),
+            Column::Y => Values::F32(self.plotted(Signal::Y, span)),
+            Column::I => Values::F32(self.plotted(Signal::I, span)),
+            Column::Q => Values::F32(self.plotted(Signal::Q, span)),
+            Column::Burst => Values::F32(self.plotted(Signal::Burst, span)),
+            Column::Carrier => Values::F32(range
+                .map(|at| {
+                    let start = self.starts.get(line_of(self.starts, at)).cloned().unwrap_or(0);
+                    self.decoder.carrier_freq(at.saturating_sub(start)).sin()
+                })
+                .collect()),
+            Column::Sync => Values::U8(sync[range].iter().map(|x| (*x < decode::SYNC_THRESHOLD) as u8).collect()),
+        }
+    }
+}
+
+/// Writes columns over a span as CSV, with a header row of column names.
+pub fn write_csv<W: Write>(out: &mut W, source: &Source, columns: &[Column], span: &Span) -> io::Result<()> {
+    let names = columns.iter().map(|x| x.name()).collect::<Vec<_>>();
+    writeln!(out, "{}", names.join(","))?;
+    let values = columns.iter().map(|x| source.column(*x, span)).collect::<Vec<_>>();
+    for row in 0..span.len {
+        let fields = values.iter().map(|x| x.format(row)).collect::<Vec<_>>();
+        writeln!(out, "{}", fields.join(","))?;
+    }
+    Ok(())
+}
+
+/**
+ * Writes columns over a span in the binary columnar format, all
+ * little-endian:
+ *
+ * - `MAGIC`, then `VERSION` as u16
+ * - sample rate in MHz as f64
+ * - column count as u16, row count as u64
+ * - for each column, its name's length as u8, the name in ASCII, and its
+ *   `Kind` as u8
+ * - each column's values in turn, `rows` of them, packed
+ *
+ * Columns are computed and written one at a time, so large ranges only
+ * need memory for one column.
+ */
+pub fn write_binary<W: Write>(out: &mut W, source: &Source, columns: &[Column], span: &Span) -> io::Result<()> {
+    out.write_all(&MAGIC)?;
+    out.write_u16::<LittleEndian>(VERSION)?;
+    out.write_f64::<LittleEndian>(SAMPLE_RATE_MHZ as f64)?;
+    out.write_u16::<LittleEndian>(columns.len() as u16)?;
+    out.write_u64::<LittleEndian>(span.len as u64)?;
+    for column in columns {
+        out.write_u8(column.name().len() as u8)?;
+        out.write_all(column.name().as_bytes())?;
+        out.write_u8(column.kind() as u8)?;
+    }
+    for column in columns {
+        source.column(*column, span).write(out)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::{InputMode, Sampling};
+    use crate::standard::Standard;
+
+    fn decoder() -> Decoder {
+        Decoder {
+            mode: InputMode::Composite,
+            standard: Standard::Ntsc,
+            sampling: Sampling::Boxcar,
+            shift: 7.,
+            chunk_width: 12,
+            line_samples: 1000,
+        }
+    }
+
+    /// Two lines of 1000 samples with sync from 200 to 396 in each.
+    fn sync() -> Vec<f32> {
+        let mut sync = vec![0.; 3000];
+        for line in 0..2 {
+            for x in &mut sync[line * 1000 + 200..line * 1000 + 396] {
+                *x = -56.;
+            }
+        }
+        sync
+    }
+
+    #[test]
+    fn columns() {
+        let decoder = decoder();
+        let channels = [sync()];
+        let raw = vec![0x1234; 3000];
+        let source = Source {
+            decoder: &decoder,
+            channels: &channels,
+            raw: &raw,
+            starts: &[396, 1396],
+            line_samples: 1000,
+            first_line: 10,
+        };
+        let span = Span {
+            start: 1198,
+            len: 4,
+        };
+        match source.column(Column::Line, &span) {
+            Values::I32(x) => assert_eq!(x, vec![10, 10, 11, 11]),
+            _ => panic!("line is i32"),
+        }
+        match source.column(Column::Time, &span) {
+            Values::F32(x) => assert_eq!(x[2..], [0., 1. / SAMPLE_RATE_MHZ]),
+            _ => panic!("time is f32"),
+        }
+        match source.column(Column::Sync, &span) {
+            Values::U8(x) => assert_eq!(x, vec![0, 0, 1, 1]),
+            _ => panic!("sync is u8"),
+        }
+        match source.column(Column::Code, &span) {
+            Values::U16(x) => assert_eq!(x, vec![0x123; 4]),
+            _ => panic!("code is u16"),
+        }
+
+        let mut csv = vec![];
+        write_csv(&mut csv, &source, &[Column::Sample, Column::Sync], &span).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), "sample,sync\n1198,0\n1199,0\n1200,1\n1201,1\n");
+
+        let mut binary = vec![];
+        write_binary(&mut binary, &source, &[Column::Sample, Column::Sync], &span).unwrap();
+        assert_eq!(binary[..8], MAGIC);
+        assert_eq!(binary[8..10], [1, 0]);
+        assert_eq!(binary[18..20], [2, 0]);
+        assert_eq!(binary[20..28], [4, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(binary[28..36], *b"\x06sample\x03");
+        assert_eq!(binary[36..42], *b"\x04sync\x00");
+        assert_eq!(binary[42..50], 1198u64.to_le_bytes());
+        assert_eq!(binary[74..], [0, 0, 1, 1]);
+    }
+}
@@ -0,0 +1,382 @@
+use std::f64::consts::PI;
+use rustfft::num_complex::Complex;
+use rustfft::num_traits::Zero;
+use rustfft::FFTplanner;
+use crate::capture::to_ire;
+use crate::decode::{BURST_WINDOW, SAMPLE_RATE_MHZ};
+use crate::font;
+use crate::frame::Frame;
+use crate::vectorscope::ACTIVE_US;
+
+/// Range of levels shown in spectrum images, in dB relative to 100 IRE.
+pub const DB_MIN: f32 = -60.;
+pub const DB_MAX: f32 = 0.;
+
+/// Bursts weaker than this share of the median are left out of the
+/// subcarrier estimate, e.g. on lines in vertical sync.
+pub const MIN_BURST_FRACTION: f32 = 0.5;
+
+/// How far the distance between neighbouring lines may stray from the
+/// median before the subcarrier is taken to be discontinuous, e.g. across a
+/// DMA gap, as a share of the median.
+pub const LINE_SPACING_TOLERANCE: f64 = 0.02;
+
+/// Largest phase step between neighbouring bursts, once the frequency is
+/// roughly known, before the subcarrier is taken to be discontinuous, in
+/// radians. PAL's swinging burst steps by a quarter cycle.
+pub const MAX_PHASE_STEP: f64 = PI * 0.75;
+
+/// Narrowest image with room for the MHz scale, in pixels.
+pub const MIN_WIDTH: usize = 64;
+
+const MARGIN_BOTTOM: usize = 16;
+const LABEL_COLOR: [f32; 3] = [0.8, 0.65, 0.2];
+const MARKER_COLOR: [f32; 3] = [0.9, 0.2, 0.2];
+
+/// Part of a line to take a spectrum of.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Region {
+    Burst,
+    Active,
+}
+
+impl Region {
+    pub const ALL: [Region; 2] = [Region::Burst, Region::Active];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Region::Burst => "burst",
+            Region::Active => "active",
+        }
+    }
+
+    /// Samples of the region, from the end of sync.
+    pub fn window(&self) -> std::ops::Range<usize> {
+        match self {
+            Region::Burst => BURST_WINDOW,
+            Region::Active => (ACTIVE_US.start * SAMPLE_RATE_MHZ) as usize..(ACTIVE_US.end * SAMPLE_RATE_MHZ) as usize,
+        }
+    }
+
+    /// FFT length: the region zero-padded past the next power of two, for
+    /// finer bins.
+    pub fn fft_size(&self) -> usize {
+        self.window().len().next_power_of_two() * 2
+    }
+}
+
+/// Frequency of FFT bin `bin`, in MHz.
+pub fn bin_mhz(bin: usize, fft_size: usize) -> f32 {
+    bin as f32 * SAMPLE_RATE_MHZ / fft_size as f32
+}
+
+/**
+ * Takes the Hann-windowed spectrum of `samples`, zero-padded to
+ * `fft_size`, up to the Nyquist frequency. Levels are in dB relative to a
+ * 100 IRE sine, so a sine of amplitude 20 IRE peaks near -14dB.
+ */
+pub fn spectrum(samples: &[f32], fft_size: usize) -> Vec<f32> {
+    let len = samples.len().min(fft_size);
+    let window = (0..len)
+        .map(|n| 0.5 - 0.5 * (2. * PI * n as f64 / len as f64).cos())
+        .collect::<Vec<_>>();
+    let gain = window.iter().sum::<f64>();
+
+    let mut input = vec![Complex::zero(); fft_size];
+    for (n, (x, w)) in samples.iter().zip(&window).enumerate() {
+        input[n] = Complex::new(to_ire(*x) as f64 * w, 0.);
+    }
+    let mut output = vec![Complex::zero(); fft_size];
+    let mut planner = FFTplanner::new(false);
+    planner.plan_fft(fft_size).process(&mut input, &mut output);
+
+    output[..fft_size / 2 + 1].iter()
+        .map(|x| {
+            let amplitude = 2. * x.norm() / gain;
+            (20. * (amplitude / 100.).max(1e-9).log10()) as f32
+        })
+        .collect()
+}
+
+/// Spectra of a region of each line starting at `starts`.
+pub fn line_spectra(chroma: &[f32], starts: &[usize], region: Region) -> Vec<Vec<f32>> {
+    let window = region.window();
+    starts.iter()
+        .map(|start| {
+            let from = (start + window.start).min(chroma.len());
+            let to = (start + window.end).min(chroma.len());
+            spectrum(&chroma[from..to], region.fft_size())
+        })
+        .collect()
+}
+
+/// Renders spectra as an image, one row per line and frequency increasing
+/// to the right up to Nyquist, with a MHz scale and the subcarrier at
+/// `subcarrier_mhz` marked. `width` is at least `MIN_WIDTH`.
+pub fn render(spectra: &[Vec<f32>], width: usize, subcarrier_mhz: f32) -> Frame {
+    assert!(width >= MIN_WIDTH, "spectrum width {} below {}", width, MIN_WIDTH);
+    let rows = spectra.len().max(1);
+    let mut frame = Frame::new(width, rows + MARGIN_BOTTOM);
+    for (y, levels) in spectra.iter().enumerate() {
+        for x in 0..width {
+            let bin = x * (levels.len() - 1) / (width - 1).max(1);
+            let v = num::clamp((levels[bin] - DB_MIN) / (DB_MAX - DB_MIN), 0., 1.);
+            // Dark blue through green to yellow as the level rises.
+            frame.set(x, y, [v * v, v.sqrt() * 0.9, (1. - v) * v * 2. + 0.1 * v]);
+        }
+    }
+
+    let nyquist = SAMPLE_RATE_MHZ / 2.;
+    let column = |mhz: f32| ((mhz / nyquist) * (width - 1) as f32).round() as usize;
+    let marker = column(subcarrier_mhz);
+    for y in (0..rows).filter(|y| y % 4 < 2) {
+        frame.set(marker, y, MARKER_COLOR);
+    }
+    for mhz in 0..=nyquist as usize {
+        let x = column(mhz as f32);
+        let tick = if mhz % 5 == 0 { 4 } else { 2 };
+        for y in rows..rows + tick {
+            frame.set(x, y, LABEL_COLOR);
+        }
+        if mhz % 5 == 0 {
+            let label = mhz.to_string();
+            let left = (x as isize - font::text_width(&label, 1) as isize / 2).max(0);
+            font::draw_text(&mut frame, left, rows as isize + 6, &label, 1, LABEL_COLOR);
+        }
+    }
+    let units = "MHz";
+    font::draw_text(&mut frame, (width - font::text_width(units, 1)) as isize, rows as isize + 6, units, 1, LABEL_COLOR);
+    frame
+}
+
+/// The subcarrier's frequency measured against the sample clock.
+#[derive(Clone, Copy, Debug)]
+pub struct Estimate {
+    /// Subcarrier cycles per sample.
+    pub cycles_per_sample: f64,
+    /// Standard error of `cycles_per_sample`.
+    pub error: f64,
+    /// Bursts and runs of continuous lines the estimate was fitted to.
+    pub lines: usize,
+    pub runs: usize,
+}
+
+impl Estimate {
+    /// Subcarrier frequency in Hz, taking the sample clock as nominal.
+    pub fn hz(&self) -> f64 {
+        self.cycles_per_sample * SAMPLE_RATE_MHZ as f64 * 1e6
+    }
+
+    /// Standard error of `hz`.
+    pub fn error_hz(&self) -> f64 {
+        self.error * SAMPLE_RATE_MHZ as f64 * 1e6
+    }
+
+    /// Offset from a subcarrier of `subcarrier_hz` sampled at exactly
+    /// `SAMPLE_RATE_MHZ`, in ppm.
+    pub fn offset_ppm(&self, subcarrier_hz: f64) -> f64 {
+        let nominal = subcarrier_hz / (SAMPLE_RATE_MHZ as f64 * 1e6);
+        (self.cycles_per_sample / nominal - 1.) * 1e6
+    }
+
+    /// The actual sample rate in Hz, if the source's subcarrier is exactly
+    /// `subcarrier_hz`.
+    pub fn sample_rate_hz(&self, subcarrier_hz: f64) -> f64 {
+        subcarrier_hz / self.cycles_per_sample
+    }
+}
+
+/// A line's burst phase against a free-running carrier of `cycles` per
+/// sample, where the burst starts at absolute sample `from`.
+fn burst_phasor(chroma: &[f32], from: usize, len: usize, cycles: f64) -> Complex<f64> {
+    let to = (from + len).min(chroma.len());
+    (from..to)
+        .map(|k| Complex::from_polar(chroma[k] as f64, -2. * PI * cycles * k as f64))
+        .fold(Complex::zero(), |a, b| a + b)
+}
+
+fn wrap(phase: f64) -> f64 {
+    let phase = phase % (2. * PI);
+    if phase > PI {
+        phase - 2. * PI
+    } else if phase < -PI {
+        phase + 2. * PI
+    } else {
+        phase
+    }
+}
+
+/**
+ * Estimates the subcarrier frequency from the burst of each line starting
+ * at `starts`, relying on the subcarrier running continuously from line to
+ * line. Phase steps from one burst to the next give the frequency, and a
+ * least-squares fit of burst phase against time over runs of continuous
+ * lines refines it to a fraction of a Hz. Runs are broken where line
+ * spacing jumps, e.g. at gaps between capture blocks.
+ *
+ * Steps between lines can't tell apart frequencies a whole number of
+ * cycles per line apart, and bursts are too short to settle it, so the
+ * answer nearest `nominal_hz` is taken, which holds while the sample clock
+ * is within about 0.2% of `SAMPLE_RATE_MHZ`. Returns None without enough
+ * bursts.
+ */
+pub fn estimate_subcarrier(chroma: &[f32], starts: &[usize], nominal_hz: f64) -> Option<Estimate> {
+    let len = BURST_WINDOW.len();
+    let mut cycles = nominal_hz / (SAMPLE_RATE_MHZ as f64 * 1e6);
+
+    // Keep lines with a proper burst.
+    let amplitudes = starts.iter()
+        .map(|start| burst_phasor(chroma, start + BURST_WINDOW.start, len, cycles).norm() as f32)
+        .collect::<Vec<_>>();
+    let mut sorted = amplitudes.clone();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let median = *sorted.get(sorted.len() / 2)?;
+    let bursts = starts.iter()
+        .zip(&amplitudes)
+        .filter(|(_, amplitude)| **amplitude >= median * MIN_BURST_FRACTION && **amplitude > 0.)
+        .map(|(start, _)| start + BURST_WINDOW.start)
+        .collect::<Vec<_>>();
+    if bursts.len() < 4 {
+        return None;
+    }
+
+    // Split into runs of lines where the spacing stays put.
+    let mut spacings = starts.windows(2).map(|w| (w[1] - w[0]) as f64).collect::<Vec<_>>();
+    spacings.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let spacing = *spacings.get(spacings.len() / 2)?;
+    let mut runs: Vec<Vec<usize>> = vec![];
+    for (n, start) in starts.iter().enumerate() {
+        let continuous = n > 0 && ((start - starts[n - 1]) as f64 - spacing).abs() <= spacing * LINE_SPACING_TOLERANCE;
+        if !continuous || runs.is_empty() {
+            runs.push(vec![]);
+        }
+        let from = start + BURST_WINDOW.start;
+        if bursts.binary_search(&from).is_ok() {
+            runs.last_mut().unwrap().push(from);
+        }
+    }
+    runs.retain(|x| x.len() >= 2);
+    if runs.is_empty() {
+        return None;
+    }
+
+    // Rough: the average phase step from one burst to the next.
+    let (mut sum, mut weight) = (0., 0.);
+    for run in &runs {
+        for pair in run.windows(2) {
+            let step = burst_phasor(chroma, pair[1], len, cycles) * burst_phasor(chroma, pair[0], len, cycles).conj();
+            sum += step.arg() / (2. * PI * (pair[1] - pair[0]) as f64) * step.norm();
+            weight += step.norm();
+        }
+    }
+    cycles += sum / weight;
+
+    // A step far from the rest means the subcarrier jumped where the line
+    // spacing didn't show it, so split the run there.
+    let runs = runs.into_iter()
+        .flat_map(|run| {
+            let mut split: Vec<Vec<usize>> = vec![vec![run[0]]];
+            for pair in run.windows(2) {
+                let step = burst_phasor(chroma, pair[1], len, cycles) * burst_phasor(chroma, pair[0], len, cycles).conj();
+                if step.arg().abs() > MAX_PHASE_STEP {
+                    split.push(vec![]);
+                }
+                split.last_mut().unwrap().push(pair[1]);
+            }
+            split
+        })
+        .filter(|x| x.len() >= 2)
+        .collect::<Vec<_>>();
+    if runs.is_empty() {
+        return None;
+    }
+
+    // Fine: fit phase against time, with a common slope and each run its
+    // own offset.
+    let mut error = 0.;
+    let mut lines = 0;
+    for _ in 0..2 {
+        let (mut sxy, mut sxx, mut residuals, mut count) = (0., 0., vec![], 0);
+        let mut fitted = vec![];
+        for run in &runs {
+            let mut unwrapped = vec![];
+            let mut previous: Option<f64> = None;
+            for from in run {
+                let phase = burst_phasor(chroma, *from, len, cycles).arg();
+                let phase = match previous {
+                    Some(p) => p + wrap(phase - p),
+                    None => phase,
+                };
+                unwrapped.push((*from as f64, phase));
+                previous = Some(phase);
+            }
+            let n = unwrapped.len() as f64;
+            let mean_x = unwrapped.iter().map(|x| x.0).sum::<f64>() / n;
+            let mean_y = unwrapped.iter().map(|x| x.1).sum::<f64>() / n;
+            for (x, y) in &unwrapped {
+                sxy += (x - mean_x) * (y - mean_y);
+                sxx += (x - mean_x) * (x - mean_x);
+            }
+            count += unwrapped.len();
+            fitted.push((unwrapped, mean_x, mean_y));
+        }
+        let slope = sxy / sxx;
+        for (unwrapped, mean_x, mean_y) in &fitted {
+            for (x, y) in unwrapped {
+                residuals.push(y - mean_y - slope * (x - mean_x));
+            }
+        }
+        let dof = (count as f64 - fitted.len() as f64 - 1.).max(1.);
+        let variance = residuals.iter().map(|x| x * x).sum::<f64>() / dof;
+        cycles += slope / (2. * PI);
+        error = (variance / sxx).sqrt() / (2. * PI);
+        lines = count;
+    }
+
+    Some(Estimate {
+        cycles_per_sample: cycles,
+        error,
+        lines,
+        runs: runs.len(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::SUBCARRIER_MHZ;
+
+    /// A 20 IRE subcarrier at `cycles` per sample.
+    fn subcarrier(len: usize, cycles: f64) -> Vec<f32> {
+        (0..len).map(|k| 28. * (2. * PI * cycles * k as f64).sin() as f32).collect()
+    }
+
+    #[test]
+    fn peaks_at_subcarrier() {
+        let cycles = SUBCARRIER_MHZ as f64 / SAMPLE_RATE_MHZ as f64;
+        let levels = spectrum(&subcarrier(1000, cycles), 2048);
+        assert_eq!(levels.len(), 1025);
+        let (peak, level) = levels.iter()
+            .enumerate()
+            .fold((0, DB_MIN), |a, (bin, x)| if *x > a.1 { (bin, *x) } else { a });
+        assert!((bin_mhz(peak, 2048) - SUBCARRIER_MHZ).abs() <= bin_mhz(1, 2048), "{}", peak);
+        assert!((level - -14.).abs() < 0.5, "{}", level);
+    }
+
+    #[test]
+    fn estimates_subcarrier() {
+        let nominal = 315e6 / 88.;
+        let actual = nominal * (1. + 20e-6);
+        let chroma = subcarrier(200_000, actual / (SAMPLE_RATE_MHZ as f64 * 1e6));
+        // Two runs of lines, with a gap between them.
+        let starts = (0..30).map(|n| n * 2648).chain((40..70).map(|n| n * 2648 + 1000)).collect::<Vec<_>>();
+
+        let estimate = estimate_subcarrier(&chroma, &starts, nominal).unwrap();
+        assert!((estimate.hz() - actual).abs() < 1., "{}", estimate.hz());
+        assert!((estimate.offset_ppm(nominal) - 20.).abs() < 0.3, "{}", estimate.offset_ppm(nominal));
+        assert_eq!(estimate.runs, 2);
+        assert_eq!(estimate.lines, 60);
+
+        assert!(estimate_subcarrier(&chroma, &starts[..3], nominal).is_none());
+    }
+}
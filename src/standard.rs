@@ -0,0 +1,126 @@
+/// Width over height of the picture as displayed, unless signalled
+/// otherwise.
+pub const DISPLAY_ASPECT: (u32, u32) = (4, 3);
+
+/// Broadcast standard of the source, which sets its timing.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Standard {
+    Ntsc,
+    Pal,
+}
+
+impl Standard {
+    /// Reads the `STANDARD` environment variable, defaulting to NTSC.
+    pub fn from_env() -> Standard {
+        match std::env::var("STANDARD").as_ref().map(|x| x.as_str()) {
+            Ok("pal") => Standard::Pal,
+            Ok("ntsc") | Err(_) => Standard::Ntsc,
+            Ok(other) => panic!("unknown STANDARD {:?}", other),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Standard::Ntsc => "ntsc",
+            Standard::Pal => "pal",
+        }
+    }
+
+    /// Fields per second as a fraction, i.e. 59.94 or 50.
+    pub fn field_rate(&self) -> (u32, u32) {
+        match self {
+            Standard::Ntsc => (60000, 1001),
+            Standard::Pal => (50, 1),
+        }
+    }
+
+    /// Frames per second as a fraction, i.e. 29.97 or 25.
+    pub fn frame_rate(&self) -> (u32, u32) {
+        let (num, den) = self.field_rate();
+        (num, den * 2)
+    }
+
+    /// Color subcarrier frequency in Hz.
+    pub fn subcarrier_hz(&self) -> f64 {
+        match self {
+            Standard::Ntsc => 315e6 / 88.,
+            Standard::Pal => 4_433_618.75,
+        }
+    }
+
+    /// Samples per scanline at the SMI sample rate.
+    pub fn line_samples(&self) -> usize {
+        match self {
+            Standard::Ntsc => crate::decode::LINE_SAMPLES,
+            Standard::Pal => 2666,
+        }
+    }
+
+    /// Line on which the first vertical sync broad pulse begins.
+    pub fn vsync_line(&self) -> usize {
+        match self {
+            Standard::Ntsc => 4,
+            Standard::Pal => 1,
+        }
+    }
+
+    /// First line of a field carrying picture.
+    pub fn first_active_line(&self) -> usize {
+        match self {
+            Standard::Ntsc => 22,
+            Standard::Pal => 23,
+        }
+    }
+
+    pub fn lines_per_frame(&self) -> usize {
+        match self {
+            Standard::Ntsc => 525,
+            Standard::Pal => 625,
+        }
+    }
+
+    /// Lines per field carrying picture.
+    pub fn active_lines(&self) -> usize {
+        match self {
+            Standard::Ntsc => 240,
+            Standard::Pal => 288,
+        }
+    }
+}
+
+/// Reduces a fraction, e.g. a pixel aspect ratio.
+pub fn reduce(num: u32, den: u32) -> (u32, u32) {
+    fn gcd(a: u32, b: u32) -> u32 {
+        if b == 0 { a } else { gcd(b, a % b) }
+    }
+    let d = gcd(num, den).max(1);
+    (num / d, den / d)
+}
+
+/// Pixel aspect ratio for an image of `width` by `height` that is displayed
+/// at `display` (width, height), e.g. `DISPLAY_ASPECT`. `height` counts the
+/// lines as displayed, so a field is twice its own line count.
+pub fn pixel_aspect(width: usize, height: usize, display: (u32, u32)) -> (u32, u32) {
+    reduce(display.0 * height as u32, display.1 * width as u32)
+}
+
+/// An aspect ratio as a single number, width over height.
+pub fn ratio(aspect: (u32, u32)) -> f32 {
+    aspect.0 as f32 / aspect.1 as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pixel_aspects() {
+        assert_eq!(reduce(0, 0), (0, 0));
+        // 640 square pixels across 480 lines.
+        assert_eq!(pixel_aspect(640, 480, DISPLAY_ASPECT), (1, 1));
+        // A 720 wide field of 240 lines, shown at twice its height.
+        assert_eq!(pixel_aspect(720, 480, DISPLAY_ASPECT), (8, 9));
+        // The same field signalled as 16:9.
+        assert_eq!(pixel_aspect(720, 480, (16, 9)), (32, 27));
+    }
+}
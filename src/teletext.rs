@@ -0,0 +1,413 @@
+use std::ops::{Range, RangeInclusive};
+use crate::caption::parity_ok;
+use crate::decode::SAMPLE_RATE_MHZ;
+use crate::vbi::{self, Slicer};
+
+/// Lines of each 625-line field that may carry teletext.
+pub const LINES: RangeInclusive<usize> = 6..=22;
+
+/// Bits per line period: 444 times the line rate, 6.9375MHz.
+pub const BITS_PER_LINE: f32 = 444.;
+
+/// Framing code, following 16 bits of clock run-in, `1010...`.
+pub const FRAMING_CODE: u8 = 0x27;
+
+/// Bytes after the framing code: the magazine and row address, then 40
+/// bytes of data.
+pub const PACKET_BYTES: usize = 42;
+
+/// Run-in bits checked before the framing code.
+pub const RUN_IN_BITS: usize = 8;
+
+/// How many bits late the run-in's leading edge may be found, as the first
+/// few bits may be lost to a slow rising edge.
+pub const LATE_BITS: usize = 8;
+
+/// Where the run-in may start, in µs from the sync leading edge. It's
+/// nominally at 12µs.
+pub const START_US: Range<f32> = 9.5..14.;
+
+/// Smallest swing, in IRE, taken to be teletext rather than a blank line.
+/// It's nominally 66.
+pub const MIN_SWING_IRE: f32 = 30.;
+
+/// Rows of a page, including the header.
+pub const ROWS: usize = 25;
+pub const COLUMNS: usize = 40;
+
+/// Bytes at the start of the header carrying the page number and control
+/// bits rather than characters.
+pub const HEADER_CONTROL_BYTES: usize = 8;
+
+/// Hamming 8/4 codeword for `nibble`: `P1 D1 P2 D2 P3 D3 P4 D4` from bit 0,
+/// each parity bit making its checks odd.
+pub fn hamming_encode(nibble: u8) -> u8 {
+    let d = |k: u32| (nibble >> k) & 1;
+    let p1 = 1 ^ d(0) ^ d(2) ^ d(3);
+    let p2 = 1 ^ d(0) ^ d(1) ^ d(3);
+    let p3 = 1 ^ d(0) ^ d(1) ^ d(2);
+    let p4 = 1 ^ p1 ^ d(0) ^ p2 ^ d(1) ^ p3 ^ d(2) ^ d(3);
+    p1 | d(0) << 1 | p2 << 2 | d(1) << 3 | p3 << 4 | d(2) << 5 | p4 << 6 | d(3) << 7
+}
+
+/// Decodes a Hamming 8/4 byte, correcting a single bit error. Returns None
+/// for two, which the code can only detect.
+pub fn hamming_decode(byte: u8) -> Option<u8> {
+    (0..16).find(|nibble| (hamming_encode(*nibble) ^ byte).count_ones() <= 1)
+}
+
+/**
+ * Slices the teletext packet on the line starting at `start` (the end of
+ * its sync) into the 42 bytes after its framing code. Bits are clocked
+ * from the run-in's leading edge and the framing code is found after what
+ * remains of the run-in. Returns None if there's no run-in and framing
+ * code.
+ */
+pub fn slice(signal: &[f32], start: usize, line_samples: usize) -> Option<[u8; PACKET_BYTES]> {
+    // The packet runs to the very end of the line, so take a little of the
+    // next to slice the last bits.
+    let (_, line) = vbi::raw_line(signal, start, line_samples + line_samples / 8);
+    let bit = line_samples as f32 / BITS_PER_LINE;
+    let at = |us: f32| (us * SAMPLE_RATE_MHZ) as usize;
+    let count = 16 + 8 + PACKET_BYTES * 8;
+
+    // Level from the run-in and framing code, which end well before the
+    // line does.
+    let slicer = Slicer::new(&line, at(START_US.start)..at(START_US.end) + (24. * bit) as usize, bit, MIN_SWING_IRE)?;
+    let edge = slicer.rising_edge(&line, at(START_US.start)..at(START_US.end))?;
+    let bits = slicer.bits(&line, edge, count)?;
+
+    let byte = |from: usize| (0..8).fold(0u8, |byte, k| byte | (bits[from + k] as u8) << k);
+    // The edge is always at a `1` of the run-in, so the framing code is an
+    // even number of bits in.
+    let framing = (16 - LATE_BITS..=16).step_by(2).find(|p| {
+        bits[p - RUN_IN_BITS..*p].iter().enumerate().all(|(n, x)| *x == (n % 2 == 0)) && byte(*p) == FRAMING_CODE
+    })?;
+    let mut bytes = [0; PACKET_BYTES];
+    for (n, byte_value) in bytes.iter_mut().enumerate() {
+        *byte_value = byte(framing + 8 + n * 8);
+    }
+    Some(bytes)
+}
+
+/// A packet addressed to a row of a page in a magazine.
+#[derive(Clone, Copy, Debug)]
+pub struct Packet {
+    pub line: vbi::Line,
+    /// Magazine, 1 to 8.
+    pub magazine: u8,
+    /// Packet number: 0 for a page header, 1 to 24 for rows of the page,
+    /// and more for data that isn't displayed.
+    pub row: u8,
+    /// Characters, with parity, or for headers the page address first.
+    pub data: [u8; COLUMNS],
+}
+
+/// Slices teletext from `lines`, leaving out those without it or whose
+/// magazine and row address can't be corrected. `signal` is the sync
+/// channel's levels.
+pub fn packets(signal: &[f32], lines: &[vbi::Line], line_samples: usize) -> Vec<Packet> {
+    let mut packets = vec![];
+    for line in lines {
+        let bytes = match slice(signal, line.start, line_samples) {
+            Some(bytes) => bytes,
+            None => continue,
+        };
+        let (a, b) = match (hamming_decode(bytes[0]), hamming_decode(bytes[1])) {
+            (Some(a), Some(b)) => (a, b),
+            _ => continue,
+        };
+        let mut data = [0; COLUMNS];
+        data.copy_from_slice(&bytes[2..]);
+        packets.push(Packet {
+            line: *line,
+            magazine: if a & 7 == 0 { 8 } else { a & 7 },
+            row: a >> 3 | b << 1,
+            data,
+        });
+    }
+    packets
+}
+
+/// A page as received, possibly over several transmissions.
+#[derive(Clone, Debug)]
+pub struct Page {
+    /// Magazine and page number, as shown, from `0x100` to `0x8ff`.
+    pub number: u16,
+    /// Subpage, for pages that rotate.
+    pub subcode: u16,
+    /// Rows with parity, or None if they haven't been received. The
+    /// header's page address is blanked.
+    pub rows: Vec<Option<[u8; COLUMNS]>>,
+    /// Seconds from the start of the capture to its first header.
+    pub time: f64,
+    /// The page is sent whole and replaces what was held.
+    erase: bool,
+}
+
+impl Page {
+    /// Rows as text, 25 lines with the header's page address shown as
+    /// `P100`. Rows not received are blank.
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for (n, row) in self.rows.iter().enumerate() {
+            let mut line = match row {
+                Some(row) => row_text(row),
+                None => String::new(),
+            };
+            if n == 0 {
+                let page = format!("P{:03X}", self.number);
+                line = format!("{:8}{}", page, line.chars().skip(HEADER_CONTROL_BYTES).collect::<String>());
+            }
+            text.push_str(line.trim_end());
+            text.push('\n');
+        }
+        text
+    }
+}
+
+/// A character of the Latin G0 set with the English national option,
+/// which is ASCII but for a few symbols.
+pub fn g0(byte: u8) -> char {
+    match byte {
+        0x23 => '£',
+        0x5b => '←',
+        0x5c => '½',
+        0x5d => '→',
+        0x5e => '↑',
+        0x5f => '#',
+        0x60 => '―',
+        0x7b => '¼',
+        0x7c => '‖',
+        0x7d => '¾',
+        0x7e => '÷',
+        0x7f => '■',
+        _ => byte as char,
+    }
+}
+
+/// A contiguous block mosaic, `0x20` to `0x3f` and `0x60` to `0x7f`, as a
+/// Unicode sextant. Bits 0 to 4 and 6 are its six cells, left to right and
+/// top to bottom.
+pub fn mosaic(byte: u8) -> char {
+    let cells = (byte & 0x1f) | (byte & 0x40) >> 1;
+    match cells {
+        0 => ' ',
+        0b010101 => '▌',
+        0b101010 => '▐',
+        0b111111 => '█',
+        // The sextant block leaves out the four above.
+        _ => std::char::from_u32(0x1fb00 + cells as u32 - 1 - (cells > 0b010101) as u32 - (cells > 0b101010) as u32).unwrap(),
+    }
+}
+
+/// A row's characters as text. Spacing attributes show as spaces, and
+/// characters after a graphics colour code as mosaics. Characters failing
+/// odd parity show as blocks.
+pub fn row_text(row: &[u8]) -> String {
+    let mut graphics = false;
+    row.iter()
+        .map(|raw| {
+            let byte = raw & 0x7f;
+            if !parity_ok(*raw) {
+                return '█';
+            }
+            match byte {
+                0x00..=0x07 => {
+                    graphics = false;
+                    ' '
+                }
+                0x10..=0x17 => {
+                    graphics = true;
+                    ' '
+                }
+                0x08..=0x0f | 0x18..=0x1f => ' ',
+                _ if graphics && byte & 0x20 != 0 => mosaic(byte),
+                _ => g0(byte),
+            }
+        })
+        .collect()
+}
+
+/**
+ * Assembles pages from packets. Each magazine receives one page at a time,
+ * from its header to the next header in the magazine, or in serial mode the
+ * next header in any magazine. Pages sent more than once are merged, later
+ * rows replacing earlier ones, unless the header says to erase the page.
+ */
+#[derive(Default)]
+pub struct Assembler {
+    current: [Option<Page>; 8],
+    pages: Vec<Page>,
+}
+
+impl Assembler {
+    pub fn new() -> Assembler {
+        Assembler::default()
+    }
+
+    pub fn feed(&mut self, packet: &Packet) {
+        let magazine = packet.magazine as usize - 1;
+        match packet.row {
+            0 => {
+                let address = packet.data[..HEADER_CONTROL_BYTES].iter().map(|x| hamming_decode(*x)).collect::<Vec<_>>();
+                let nibble = |n: usize| address[n].unwrap_or(0) as u16;
+                // C11, magazine serial.
+                if nibble(7) & 1 == 1 {
+                    (0..8).for_each(|n| self.close(n));
+                } else {
+                    self.close(magazine);
+                }
+                let (units, tens) = match (address[0], address[1]) {
+                    (Some(units), Some(tens)) => (units as u16, tens as u16),
+                    _ => return,
+                };
+                // Page FF only marks the end of the previous page.
+                if units == 0xf && tens == 0xf {
+                    return;
+                }
+                let mut rows = vec![None; ROWS];
+                let mut header = packet.data;
+                header[..HEADER_CONTROL_BYTES].iter_mut().for_each(|x| *x = b' ');
+                rows[0] = Some(header);
+                self.current[magazine] = Some(Page {
+                    number: (packet.magazine as u16) << 8 | tens << 4 | units,
+                    subcode: (nibble(5) & 0x3) << 12 | nibble(4) << 8 | (nibble(3) & 0x7) << 4 | nibble(2),
+                    rows,
+                    time: packet.line.time(),
+                    erase: nibble(3) & 0x8 != 0,
+                });
+            }
+            row if (row as usize) < ROWS => {
+                if let Some(page) = &mut self.current[magazine] {
+                    page.rows[row as usize] = Some(packet.data);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Files the page being received in `magazine`, merging it with any
+    /// earlier copy.
+    fn close(&mut self, magazine: usize) {
+        let page = match self.current[magazine].take() {
+            Some(page) => page,
+            None => return,
+        };
+        match self.pages.iter_mut().find(|x| x.number == page.number && x.subcode == page.subcode) {
+            Some(held) if !page.erase => {
+                for (row, new) in held.rows.iter_mut().zip(page.rows) {
+                    if new.is_some() {
+                        *row = new;
+                    }
+                }
+            }
+            Some(held) => held.rows = page.rows,
+            None => self.pages.push(page),
+        }
+    }
+
+    /// Files the pages still being received, and returns every page in
+    /// page and subpage order.
+    pub fn finish(mut self) -> Vec<Page> {
+        (0..8).for_each(|n| self.close(n));
+        self.pages.sort_by_key(|x| (x.number, x.subcode));
+        self.pages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hamming() {
+        assert_eq!(hamming_encode(0x0), 0x15);
+        assert_eq!(hamming_encode(0x1), 0x02);
+        assert_eq!(hamming_encode(0xf), 0xea);
+        for nibble in 0..16 {
+            let byte = hamming_encode(nibble);
+            assert_eq!(hamming_decode(byte), Some(nibble));
+            for k in 0..8 {
+                assert_eq!(hamming_decode(byte ^ 1 << k), Some(nibble), "{:x} with bit {} flipped", nibble, k);
+                for j in 0..k {
+                    assert_eq!(hamming_decode(byte ^ 1 << k ^ 1 << j), None);
+                }
+            }
+        }
+    }
+
+    /// Text as a row of characters with odd parity, padded with spaces.
+    fn row(text: &str) -> [u8; COLUMNS] {
+        let mut row = [b' '; COLUMNS];
+        for (byte, c) in row.iter_mut().zip(text.bytes()) {
+            *byte = if parity_ok(c) { c } else { c | 0x80 };
+        }
+        row
+    }
+
+    fn packet(magazine: u8, number: u8, data: [u8; COLUMNS]) -> Packet {
+        let line = vbi::Line {
+            field: 0,
+            number: 7,
+            second: None,
+            start: 0,
+        };
+        Packet { line, magazine, row: number, data }
+    }
+
+    /// A header for page `page` of `magazine`, erasing it with C4 and in
+    /// serial mode with C11.
+    fn header(magazine: u8, page: u8, erase: bool, serial: bool) -> Packet {
+        let mut data = row("        HEADER");
+        let address = [page & 0xf, page >> 4, 0, if erase { 0x8 } else { 0 }, 0, 0, 0, if serial { 1 } else { 0 }];
+        for (byte, nibble) in data.iter_mut().zip(address.iter()) {
+            *byte = hamming_encode(*nibble);
+        }
+        packet(magazine, 0, data)
+    }
+
+    fn rows(page: &Page) -> Vec<String> {
+        page.text().lines().skip(1).take(3).map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn assembles_pages() {
+        let mut assembler = Assembler::new();
+        assembler.feed(&header(1, 0x00, false, false));
+        assembler.feed(&packet(1, 1, row("ONE")));
+        assembler.feed(&packet(1, 2, row("TWO")));
+        // Magazines are sent in parallel, so this leaves page 100 open.
+        assembler.feed(&header(2, 0x34, false, false));
+        assembler.feed(&packet(2, 1, row("OTHER")));
+        assembler.feed(&packet(1, 3, row("THREE")));
+        // Sent again, replacing row 1 and keeping the others.
+        assembler.feed(&header(1, 0x00, false, false));
+        assembler.feed(&packet(1, 1, row("UNO")));
+        assembler.feed(&header(1, 0xff, false, false));
+
+        let pages = assembler.finish();
+        assert_eq!(pages.iter().map(|x| x.number).collect::<Vec<_>>(), vec![0x100, 0x234]);
+        assert_eq!(pages[0].text().lines().next().unwrap(), "P100    HEADER");
+        assert_eq!(rows(&pages[0]), vec!["UNO", "TWO", "THREE"]);
+        assert_eq!(rows(&pages[1]), vec!["OTHER", "", ""]);
+    }
+
+    #[test]
+    fn erase_and_serial() {
+        let mut assembler = Assembler::new();
+        assembler.feed(&header(1, 0x00, false, true));
+        assembler.feed(&packet(1, 1, row("ONE")));
+        assembler.feed(&packet(1, 2, row("TWO")));
+        // In serial mode any header ends the page, so this row is dropped.
+        assembler.feed(&header(2, 0x00, false, true));
+        assembler.feed(&packet(1, 3, row("LOST")));
+        // C4 replaces what was held.
+        assembler.feed(&header(1, 0x00, true, true));
+        assembler.feed(&packet(1, 2, row("NEW")));
+
+        let pages = assembler.finish();
+        assert_eq!(pages.iter().map(|x| x.number).collect::<Vec<_>>(), vec![0x100, 0x200]);
+        assert_eq!(rows(&pages[0]), vec!["", "NEW", ""]);
+    }
+}
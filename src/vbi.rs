@@ -0,0 +1,279 @@
+use std::io::{self, Write};
+use std::ops::{Range, RangeInclusive};
+use byteorder::{LittleEndian, WriteBytesExt};
+use crate::capture::to_ire;
+use crate::decode::{self, Field, SAMPLE_RATE_MHZ};
+use crate::standard::Standard;
+
+/// Identifies the raw line format, followed by its version.
+pub const MAGIC: [u8; 8] = *b"AVVBILIN";
+pub const VERSION: u16 = 1;
+
+/// Part of the back porch after the burst that raw lines are clamped to,
+/// in µs from the sync leading edge.
+pub const BACK_PORCH_US: Range<f32> = 8.2..9.2;
+
+/// Lines of each field in the vertical blanking interval after vertical
+/// sync, for the standard: 10 to 21 for 525 lines, and 6 to 23 for 625,
+/// whose line 23 carries WSS in its first half.
+pub fn default_lines(standard: Standard) -> Range<usize> {
+    match standard {
+        Standard::Ntsc => 10..22,
+        Standard::Pal => 6..24,
+    }
+}
+
+/// A line in the vertical blanking interval of a field.
+#[derive(Clone, Copy, Debug)]
+pub struct Line {
+    /// Index of the field in the capture.
+    pub field: usize,
+    /// Line number counted from the field's vertical sync, as for
+    /// `Field::first_line`.
+    pub number: usize,
+    /// Whether the field is the second of a frame, see `Field::second`.
+    pub second: Option<bool>,
+    /// Where the line starts in the capture, at the end of its sync.
+    pub start: usize,
+}
+
+impl Line {
+    /// Seconds from the start of the capture to the end of the line's sync.
+    pub fn time(&self) -> f64 {
+        self.start as f64 / (SAMPLE_RATE_MHZ as f64 * 1e6)
+    }
+}
+
+/**
+ * Finds lines `numbers` of every field found by its vertical sync, field by
+ * field. `starts` are all of the capture's lines, as from `assemble_lines`.
+ * Fields without vertical sync can't be numbered and have none, and lines
+ * before the start of the capture are left out.
+ */
+pub fn lines(fields: &[Field], starts: &[usize], numbers: RangeInclusive<usize>) -> Vec<Line> {
+    let mut lines = vec![];
+    for (n, field) in fields.iter().enumerate() {
+        let (first_line, first) = match (field.first_line, starts.iter().position(|x| *x == field.start)) {
+            (Some(first_line), Some(first)) => (first_line, first),
+            _ => continue,
+        };
+        for number in numbers.clone() {
+            let index = first as isize + number as isize - first_line as isize;
+            if index < 0 || index as usize >= starts.len() {
+                continue;
+            }
+            lines.push(Line {
+                field: n,
+                number,
+                second: field.second,
+                start: starts[index as usize],
+            });
+        }
+    }
+    lines
+}
+
+/**
+ * Copies `len` samples of the line starting at `start` (the end of its
+ * sync) from its sync leading edge, in IRE with its back porch clamped to
+ * blanking. Returns the leading edge and the samples.
+ */
+pub fn raw_line(signal: &[f32], start: usize, len: usize) -> (usize, Vec<f32>) {
+    let fall = decode::find_sync_fall(signal, start);
+    let line = decode::line_at(signal, fall, len);
+    let porch = &line[(BACK_PORCH_US.start * SAMPLE_RATE_MHZ) as usize..(BACK_PORCH_US.end * SAMPLE_RATE_MHZ) as usize];
+    let level = porch.iter().sum::<f32>() / porch.len() as f32;
+    let line = line.iter().map(|x| to_ire(x - level)).collect();
+    (fall, line)
+}
+
+/**
+ * Writes `lines` as raw sample lines for external VBI decoders, all
+ * little-endian:
+ *
+ * - `MAGIC`, then `VERSION` as u16
+ * - sample rate in MHz as f64
+ * - lines per frame as u16 (525 or 625)
+ * - samples per line as u32, line count as u32
+ * - for each line: its field's index as u32, its line number as u16, its
+ *   field's parity as u8 (0 first, 1 second, 255 unknown), its sync
+ *   leading edge as a u64 sample index into the capture, then its samples
+ *   as f32 IRE, starting at that edge
+ *
+ * Every record is the same length, so a line can be found by seeking.
+ * `signal` is the sync channel's levels.
+ */
+pub fn write_raw<W: Write>(out: &mut W, signal: &[f32], lines: &[Line], line_samples: usize, standard: Standard) -> io::Result<()> {
+    out.write_all(&MAGIC)?;
+    out.write_u16::<LittleEndian>(VERSION)?;
+    out.write_f64::<LittleEndian>(SAMPLE_RATE_MHZ as f64)?;
+    out.write_u16::<LittleEndian>(standard.lines_per_frame() as u16)?;
+    out.write_u32::<LittleEndian>(line_samples as u32)?;
+    out.write_u32::<LittleEndian>(lines.len() as u32)?;
+    for line in lines {
+        let (fall, samples) = raw_line(signal, line.start, line_samples);
+        out.write_u32::<LittleEndian>(line.field as u32)?;
+        out.write_u16::<LittleEndian>(line.number as u16)?;
+        out.write_u8(match line.second {
+            Some(second) => second as u8,
+            None => 255,
+        })?;
+        out.write_u64::<LittleEndian>(fall as u64)?;
+        for sample in samples {
+            out.write_f32::<LittleEndian>(sample)?;
+        }
+    }
+    Ok(())
+}
+
+/**
+ * Slices NRZ data out of a raw line, as from `raw_line`, at a fixed rate.
+ * Used for data whose bits are short enough that the luma filter would
+ * smear them, such as teletext, or whose elements are, such as WSS.
+ */
+#[derive(Clone, Copy, Debug)]
+pub struct Slicer {
+    /// Samples per bit.
+    pub bit: f32,
+    /// Level between `0` and `1`, in IRE.
+    pub level: f32,
+}
+
+impl Slicer {
+    /// A slicer for `line` at `bit` samples per bit, its level halfway
+    /// between the lowest and highest samples in `range`. Returns None if
+    /// they're less than `min_swing` IRE apart, as on a line without data.
+    pub fn new(line: &[f32], range: Range<usize>, bit: f32, min_swing: f32) -> Option<Slicer> {
+        let samples = &line[range.start.min(line.len())..range.end.min(line.len())];
+        let low = samples.iter().cloned().fold(f32::INFINITY, f32::min);
+        let high = samples.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+        if high - low < min_swing {
+            return None;
+        }
+        Some(Slicer { bit, level: (low + high) / 2. })
+    }
+
+    /// The crossing of the level nearest to `near`, within `reach`
+    /// samples either side, to a fraction of a sample.
+    fn crossing(&self, line: &[f32], near: f32, reach: f32) -> Option<f32> {
+        let from = ((near - reach).max(1.)) as usize;
+        let to = ((near + reach) as usize).min(line.len().saturating_sub(1));
+        (from..=to)
+            .filter(|i| (line[i - 1] >= self.level) != (line[*i] >= self.level))
+            .map(|i| (i - 1) as f32 + (self.level - line[i - 1]) / (line[i] - line[i - 1]))
+            .min_by(|a, b| (a - near).abs().partial_cmp(&(b - near).abs()).unwrap())
+    }
+
+    /// The first crossing from `0` to `1` in `range`, to a fraction of a
+    /// sample.
+    pub fn rising_edge(&self, line: &[f32], range: Range<usize>) -> Option<f32> {
+        let from = range.start.max(1);
+        let to = range.end.min(line.len());
+        let i = (from..to).find(|i| line[*i] >= self.level && line[i - 1] < self.level)?;
+        Some((i - 1) as f32 + (self.level - line[i - 1]) / (line[i] - line[i - 1]))
+    }
+
+    /**
+     * Slices `count` bits, the first starting at `edge`. Each bit is the
+     * mean of the middle half of its period. Wherever a transition falls
+     * near the start of a bit the clock is pulled halfway to it, so it
+     * follows data sent a little off the nominal rate. Returns None if the
+     * line ends first.
+     */
+    pub fn bits(&self, line: &[f32], edge: f32, count: usize) -> Option<Vec<bool>> {
+        let mut start = edge;
+        let mut bits = Vec::with_capacity(count);
+        for _ in 0..count {
+            if let Some(crossing) = self.crossing(line, start, self.bit / 3.) {
+                start += (crossing - start) / 2.;
+            }
+            let from = (start + self.bit / 4.) as usize;
+            let to = ((start + self.bit * 3. / 4.).ceil() as usize).max(from + 1);
+            if to > line.len() {
+                return None;
+            }
+            bits.push(line[from..to].iter().sum::<f32>() / (to - from) as f32 >= self.level);
+            start += self.bit;
+        }
+        Some(bits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use byteorder::{ByteOrder, LittleEndian};
+    use crate::decode::FieldStats;
+    use crate::frame::Frame;
+
+    fn field(start: usize, first_line: Option<usize>, second: Option<bool>) -> Field {
+        Field {
+            frame: Frame::new(1, 1),
+            stats: FieldStats::default(),
+            start,
+            end: start + 10_000,
+            first_line,
+            second,
+        }
+    }
+
+    /// Lines of 1000 samples with sync from 100 to 300, blanking 3 levels up
+    /// and a 50 IRE sample at 600.
+    fn signal(lines: usize) -> (Vec<f32>, Vec<usize>) {
+        let mut signal = vec![3.; lines * 1000];
+        for n in 0..lines {
+            signal[n * 1000 + 100..n * 1000 + 300].iter_mut().for_each(|x| *x = -56.);
+            signal[n * 1000 + 600] = 73.;
+        }
+        let starts = (0..lines).map(|n| n * 1000 + 300).collect();
+        (signal, starts)
+    }
+
+    #[test]
+    fn numbers_lines() {
+        let (_, starts) = signal(30);
+        let fields = [
+            field(starts[2], Some(4), Some(false)),
+            field(starts[10], None, None),
+            field(starts[20], Some(1), Some(true)),
+        ];
+        let lines = lines(&fields, &starts, 2..=5);
+        let found = lines.iter().map(|x| (x.field, x.number, x.start)).collect::<Vec<_>>();
+        assert_eq!(found, vec![
+            (0, 2, starts[0]), (0, 3, starts[1]), (0, 4, starts[2]), (0, 5, starts[3]),
+            (2, 2, starts[21]), (2, 3, starts[22]), (2, 4, starts[23]), (2, 5, starts[24]),
+        ]);
+        assert_eq!(lines[4].second, Some(true));
+    }
+
+    #[test]
+    fn record_layout() {
+        let (signal, starts) = signal(4);
+        let lines = [
+            Line { field: 3, number: 10, second: Some(true), start: starts[1] },
+            Line { field: 7, number: 21, second: None, start: starts[2] },
+        ];
+        let mut out = vec![];
+        write_raw(&mut out, &signal, &lines, 1000, Standard::Ntsc).unwrap();
+
+        assert_eq!(out[..8], MAGIC);
+        assert_eq!(LittleEndian::read_u16(&out[8..]), VERSION);
+        assert_eq!(LittleEndian::read_f64(&out[10..]), SAMPLE_RATE_MHZ as f64);
+        assert_eq!(LittleEndian::read_u16(&out[18..]), 525);
+        assert_eq!(LittleEndian::read_u32(&out[20..]), 1000);
+        assert_eq!(LittleEndian::read_u32(&out[24..]), 2);
+
+        let record = 4 + 2 + 1 + 8 + 4 * 1000;
+        assert_eq!(out.len(), 28 + 2 * record);
+        let second = &out[28 + record..];
+        assert_eq!(LittleEndian::read_u32(second), 7);
+        assert_eq!(LittleEndian::read_u16(&second[4..]), 21);
+        assert_eq!(second[6], 255);
+        assert_eq!(LittleEndian::read_u64(&second[7..]), 2100);
+        let sample = |n: usize| LittleEndian::read_f32(&second[15 + n * 4..]);
+        assert!((sample(0) - to_ire(-59.)).abs() < 1e-4);
+        assert_eq!(sample(499), 0.);
+        assert!((sample(500) - 50.).abs() < 1e-4);
+        assert_eq!(out[28 + 6], 1);
+    }
+}
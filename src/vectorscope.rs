@@ -0,0 +1,262 @@
+use crate::capture::to_ire;
+use crate::decode::{Decoder, InputMode, SAMPLE_RATE_MHZ};
+use crate::font;
+use crate::frame::Frame;
+use crate::standard::Standard;
+
+/// Chroma amplitude at the edge of the graticule, in IRE.
+pub const RADIUS_IRE: f32 = 60.;
+
+/// Amplitude and angle of the NTSC color burst, in IRE and degrees from +U.
+pub const BURST_IRE: f32 = 20.;
+pub const BURST_DEGREES: f32 = 180.;
+
+/// Angles of the NTSC I and Q axes, in degrees from +U.
+pub const I_DEGREES: f32 = 123.;
+pub const Q_DEGREES: f32 = 33.;
+
+/// Part of a line holding picture, in µs from the end of sync.
+pub const ACTIVE_US: std::ops::Range<f32> = 5.0..57.0;
+
+const GRATICULE_COLOR: [f32; 3] = [0.45, 0.35, 0.1];
+const TARGET_COLOR: [f32; 3] = [0.8, 0.65, 0.2];
+const TRACE_COLOR: [f32; 3] = [0.3, 1., 0.4];
+
+/// The color difference axes plotted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Space {
+    /// U (B-Y) across and V (R-Y) up, for composite and S-Video.
+    Uv,
+    /// Pb across and Pr up, for component.
+    PbPr,
+}
+
+impl Space {
+    pub fn for_input(mode: InputMode) -> Space {
+        match mode {
+            InputMode::Component => Space::PbPr,
+            _ => Space::Uv,
+        }
+    }
+}
+
+/// Where 75% color bars land, as `(label, x, y)` in IRE. NTSC chroma is
+/// scaled down with the picture to make room for setup.
+pub fn bar_targets(space: Space, standard: Standard) -> Vec<(&'static str, f32, f32)> {
+    let bars = [
+        ("R", [0.75, 0., 0.]),
+        ("MG", [0.75, 0., 0.75]),
+        ("B", [0., 0., 0.75]),
+        ("CY", [0., 0.75, 0.75]),
+        ("G", [0., 0.75, 0.]),
+        ("YL", [0.75, 0.75, 0.]),
+    ];
+    let setup = if space == Space::Uv && standard == Standard::Ntsc { 0.925 } else { 1. };
+    let (u_scale, v_scale) = match space {
+        Space::Uv => (0.492, 0.877),
+        Space::PbPr => (0.564, 0.713),
+    };
+    bars.iter()
+        .map(|(label, rgb)| {
+            let y = 0.299 * rgb[0] + 0.587 * rgb[1] + 0.114 * rgb[2];
+            let u = u_scale * (rgb[2] - y) * 100. * setup;
+            let v = v_scale * (rgb[0] - y) * 100. * setup;
+            (*label, u, v)
+        })
+        .collect()
+}
+
+/**
+ * Demodulates the chroma of lines starting at the end of sync into points
+ * on the vectorscope, in IRE. With `locked`, each line is rotated so its
+ * burst sits at `BURST_DEGREES`, like a scope genlocked to the source;
+ * otherwise hue is taken against the decoder's own carrier and `SHIFT`,
+ * showing what the decoder makes of it. Component lines give Pb and Pr.
+ */
+pub fn chroma_points(decoder: &Decoder, channels: &[Vec<f32>], starts: &[usize], line_samples: usize, locked: bool) -> Vec<(f32, f32)> {
+    let from = (ACTIVE_US.start * SAMPLE_RATE_MHZ) as usize;
+    let to = ((ACTIVE_US.end * SAMPLE_RATE_MHZ) as usize).min(line_samples);
+    let width = decoder.chunk_width;
+
+    let mut points = vec![];
+    for start in starts {
+        let lines = decoder.prepare_line(channels, *start, line_samples);
+        if decoder.mode == InputMode::Component {
+            let avg = |samples: &[f32]| samples.iter().sum::<f32>() / samples.len() as f32;
+            for x in (from..to.saturating_sub(width)).step_by(width) {
+                points.push((to_ire(avg(&lines[1][x..x + width])), to_ire(avg(&lines[2][x..x + width]))));
+            }
+            continue;
+        }
+
+        let chroma = match decoder.mode {
+            InputMode::SVideo => &lines[1],
+            _ => &lines[0],
+        };
+        let (_, burst_phase) = decoder.measure_burst(chroma);
+        for x in (from..to.saturating_sub(width)).step_by(width) {
+            let (i, q, _, _) = decoder.demodulate(&chroma[x..x + width], x);
+            let amplitude = to_ire((i * i + q * q).sqrt() / 2.);
+            // The demodulator's sine and cosine products follow U and V, but
+            // the decoder takes them as I and Q, which mirrors hue.
+            let angle = if locked {
+                q.atan2(i) - burst_phase + BURST_DEGREES.to_radians()
+            } else {
+                I_DEGREES.to_radians() - q.atan2(i)
+            };
+            points.push((amplitude * angle.cos(), amplitude * angle.sin()));
+        }
+    }
+    points
+}
+
+/// Renders chroma points onto a vectorscope graticule.
+pub struct Vectorscope {
+    /// Width and height of the square image.
+    pub size: usize,
+    pub space: Space,
+    pub standard: Standard,
+}
+
+impl Vectorscope {
+    pub fn new(size: usize, space: Space, standard: Standard) -> Vectorscope {
+        Vectorscope {
+            size,
+            space,
+            standard,
+        }
+    }
+
+    /// Pixel of a point in IRE, which may fall outside the image.
+    fn pixel(&self, x: f32, y: f32) -> (isize, isize) {
+        let center = self.size as f32 / 2.;
+        let scale = (center - 4.) / RADIUS_IRE;
+        ((center + x * scale).round() as isize, (center - y * scale).round() as isize)
+    }
+
+    fn plot(&self, frame: &mut Frame, x: f32, y: f32, color: [f32; 3]) {
+        let (px, py) = self.pixel(x, y);
+        if px >= 0 && py >= 0 && (px as usize) < frame.width && (py as usize) < frame.height {
+            frame.set(px as usize, py as usize, color);
+        }
+    }
+
+    fn line(&self, frame: &mut Frame, from: (f32, f32), to: (f32, f32), dashed: bool, color: [f32; 3]) {
+        let steps = (self.size * 2).max(1);
+        for n in 0..=steps {
+            if dashed && (n * 40 / steps) % 2 == 1 {
+                continue;
+            }
+            let t = n as f32 / steps as f32;
+            self.plot(frame, from.0 + (to.0 - from.0) * t, from.1 + (to.1 - from.1) * t, color);
+        }
+    }
+
+    /// Outline of a box around a target, `size` IRE across.
+    fn target(&self, frame: &mut Frame, x: f32, y: f32, size: f32) {
+        let h = size / 2.;
+        let corners = [(x - h, y - h), (x + h, y - h), (x + h, y + h), (x - h, y + h)];
+        for n in 0..4 {
+            self.line(frame, corners[n], corners[(n + 1) % 4], false, TARGET_COLOR);
+        }
+    }
+
+    fn draw_graticule(&self, frame: &mut Frame) {
+        // Outer circle, with a tick every 10 degrees.
+        let steps = self.size * 4;
+        for n in 0..steps {
+            let angle = n as f32 / steps as f32 * std::f32::consts::PI * 2.;
+            self.plot(frame, RADIUS_IRE * angle.cos(), RADIUS_IRE * angle.sin(), GRATICULE_COLOR);
+        }
+        for degrees in (0..360).step_by(10) {
+            let angle = (degrees as f32).to_radians();
+            let (c, s) = (angle.cos(), angle.sin());
+            self.line(frame, (c * (RADIUS_IRE - 3.), s * (RADIUS_IRE - 3.)), (c * RADIUS_IRE, s * RADIUS_IRE), false, GRATICULE_COLOR);
+        }
+
+        self.line(frame, (-RADIUS_IRE, 0.), (RADIUS_IRE, 0.), false, GRATICULE_COLOR);
+        self.line(frame, (0., -RADIUS_IRE), (0., RADIUS_IRE), false, GRATICULE_COLOR);
+        if self.space == Space::Uv {
+            for degrees in [I_DEGREES, Q_DEGREES].iter() {
+                let angle = degrees.to_radians();
+                let (c, s) = (angle.cos() * RADIUS_IRE, angle.sin() * RADIUS_IRE);
+                self.line(frame, (-c, -s), (c, s), true, GRATICULE_COLOR);
+            }
+        }
+
+        for (label, x, y) in bar_targets(self.space, self.standard) {
+            self.target(frame, x, y, 5.);
+            // 100% bars land a third further out.
+            self.target(frame, x * 4. / 3., y * 4. / 3., 2.);
+            let (px, py) = self.pixel(x * 1.25, y * 1.25);
+            let scale = if self.size >= 384 { 2 } else { 1 };
+            let width = font::text_width(label, scale) as isize;
+            let height = (font::GLYPH_HEIGHT * scale) as isize;
+            font::draw_text(frame, px - width / 2, py - height / 2, label, scale, TARGET_COLOR);
+        }
+
+        if self.space == Space::Uv {
+            let angle = BURST_DEGREES.to_radians();
+            let (x, y) = (BURST_IRE * angle.cos(), BURST_IRE * angle.sin());
+            self.line(frame, (0., 0.), (x, y), false, TARGET_COLOR);
+            self.target(frame, x, y, 3.);
+        }
+    }
+
+    /// Draws the graticule with the points over it, brighter where more
+    /// points land.
+    pub fn render(&self, points: &[(f32, f32)]) -> Frame {
+        let mut frame = Frame::new(self.size, self.size);
+        self.draw_graticule(&mut frame);
+
+        let mut hits = vec![0u32; self.size * self.size];
+        for (x, y) in points {
+            let (px, py) = self.pixel(*x, *y);
+            if px >= 0 && py >= 0 && (px as usize) < self.size && (py as usize) < self.size {
+                hits[py as usize * self.size + px as usize] += 1;
+            }
+        }
+        let most = hits.iter().cloned().max().unwrap_or(0).max(1) as f32;
+        for (pixel, count) in frame.pixels.iter_mut().zip(&hits) {
+            if *count == 0 {
+                continue;
+            }
+            let v = 0.25 + 0.75 * (1. + *count as f32).ln() / (1. + most).ln();
+            for c in 0..3 {
+                pixel[c] = pixel[c].max(TRACE_COLOR[c] * v);
+            }
+        }
+        frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bar_targets() {
+        let targets = super::bar_targets(Space::PbPr, Standard::Ntsc);
+        let (label, pb, pr) = targets[0];
+        assert_eq!(label, "R");
+        assert!((pb - -12.65).abs() < 0.1, "{}", pb);
+        assert!((pr - 37.5).abs() < 0.1, "{}", pr);
+
+        // NTSC composite chroma shrinks with the picture for setup.
+        let (_, u, v) = super::bar_targets(Space::Uv, Standard::Ntsc)[0];
+        let (_, pal_u, pal_v) = super::bar_targets(Space::Uv, Standard::Pal)[0];
+        assert!((u / pal_u - 0.925).abs() < 1e-4);
+        assert!((v / pal_v - 0.925).abs() < 1e-4);
+    }
+
+    #[test]
+    fn plots_points() {
+        let scope = Vectorscope::new(128, Space::Uv, Standard::Ntsc);
+        let frame = scope.render(&[(30., 30.)]);
+        assert_eq!(frame.width, 128);
+        assert_eq!(frame.get(94, 34)[1], TRACE_COLOR[1]);
+        assert_eq!(frame.get(0, 0), [0.; 3]);
+        // The axes are drawn through the middle.
+        assert_eq!(frame.get(64, 10), GRATICULE_COLOR);
+    }
+}
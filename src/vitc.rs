@@ -0,0 +1,343 @@
+use std::fmt;
+use std::ops::{Range, RangeInclusive};
+use crate::capture::to_ire;
+use crate::decode::{self, SAMPLE_RATE_MHZ};
+use crate::json::Json;
+use crate::standard::Standard;
+use crate::vbi;
+use crate::waveform;
+
+/// Lines of each field VITC may be recorded on. It's usually on two of
+/// them, not next to each other, so a dropout only takes out one.
+pub const SEARCH_LINES: RangeInclusive<usize> = 10..=20;
+
+/// Bits per line: nine groups of two sync bits, `1` then `0`, and eight
+/// data bits, the last eight being the CRC.
+pub const BITS: usize = 90;
+
+/// Bits per group, sync bits included.
+pub const GROUP_BITS: usize = 10;
+
+/// Data bits, least significant bit of each field first.
+pub const DATA_BITS: usize = 64;
+
+/// Where the first sync bit may start, in µs from the sync leading edge.
+/// It's no earlier than 10µs, and the code takes about 50µs.
+pub const START_US: Range<f32> = 8.5..14.;
+
+/// Smallest swing between `0` and `1` bits, in IRE, taken to be VITC
+/// rather than a blank line. It's nominally 80.
+pub const MIN_SWING_IRE: f32 = 40.;
+
+/// Bits per line period, 115 times the line rate for 525 lines and 116
+/// for 625.
+pub fn bits_per_line(standard: Standard) -> f32 {
+    match standard {
+        Standard::Ntsc => 115.,
+        Standard::Pal => 116.,
+    }
+}
+
+/// Whether the bits' CRC checks out. The generator is x^8 + 1 over every
+/// bit, sync bits and CRC included, which leaves bits eight apart adding
+/// up to zero.
+pub fn crc_ok(bits: &[bool]) -> bool {
+    let mut sums = [false; 8];
+    for (n, bit) in bits.iter().enumerate() {
+        sums[n % 8] ^= *bit;
+    }
+    sums.iter().all(|x| !x)
+}
+
+/**
+ * Slices the line starting at `start` (the end of its sync) into its 90
+ * bits. The slicing level is halfway between the lowest and highest levels
+ * where the code may be, and each group is timed
+ * from its own `1` to `0` sync transition so the bits stay locked over the
+ * line. Returns None if the line has no VITC or a group's sync bits are
+ * missing.
+ */
+pub fn slice(signal: &[f32], start: usize, line_samples: usize, standard: Standard) -> Option<Vec<bool>> {
+    let fall = decode::find_sync_fall(signal, start);
+    let line = waveform::luma(&decode::line_at(signal, fall, line_samples));
+    let bit = line_samples as f32 / bits_per_line(standard);
+    let at = |us: f32| (us * SAMPLE_RATE_MHZ) as usize;
+
+    let code = at(START_US.start)..(at(START_US.end) + (BITS as f32 * bit) as usize).min(line.len());
+    let low = line[code.clone()].iter().cloned().fold(f32::INFINITY, f32::min);
+    let high = line[code.clone()].iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+    if to_ire(high - low) < MIN_SWING_IRE {
+        return None;
+    }
+    let level = (low + high) / 2.;
+    let crossing = |i: usize| (i - 1) as f32 + (level - line[i - 1]) / (line[i] - line[i - 1]);
+    let mean = |center: f32| {
+        let from = (center - bit / 4.) as usize;
+        let to = ((center + bit / 4.) as usize).min(line.len());
+        if from < to { Some(line[from..to].iter().sum::<f32>() / (to - from) as f32) } else { None }
+    };
+
+    // The first sync bit follows blanking, so it starts with a rising edge.
+    let rise = (at(START_US.start)..at(START_US.end)).find(|i| line[*i] >= level && line[i - 1] < level)?;
+    let mut expected = crossing(rise) + bit;
+
+    let mut bits = Vec::with_capacity(BITS);
+    for _ in 0..BITS / GROUP_BITS {
+        // Every group has a falling edge between its sync bits.
+        let from = (expected - bit / 2.) as usize;
+        let to = ((expected + bit / 2.) as usize).min(line.len());
+        let edge = (from.max(1)..to).find(|i| line[*i] < level && line[i - 1] >= level)?;
+        let edge = crossing(edge);
+        if mean(edge - bit / 2.)? < level || mean(edge + bit / 2.)? >= level {
+            return None;
+        }
+        bits.push(true);
+        bits.push(false);
+        for k in 0..GROUP_BITS - 2 {
+            bits.push(mean(edge + (1.5 + k as f32) * bit)? >= level);
+        }
+        expected = edge + GROUP_BITS as f32 * bit;
+    }
+    Some(bits)
+}
+
+/// A timecode and the flags and user bits sent with it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Timecode {
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+    pub frames: u8,
+    /// Frame numbers 0 and 1 are skipped at the start of each minute but
+    /// every tenth, to keep up with 29.97Hz.
+    pub drop_frame: bool,
+    /// The color framing sequence is locked to the timecode.
+    pub color_frame: bool,
+    /// Set in the second field of a frame.
+    pub field_mark: bool,
+    /// The eight groups of user bits, first group in the highest nibble.
+    pub user_bits: u32,
+}
+
+impl Timecode {
+    /**
+     * Decodes the 64 data bits of a line for `standard`, which sets the frame
+     * rate and where the field mark is. Returns None if any of the digits is out of range,
+     * which the CRC can miss.
+     */
+    pub fn from_data(data: &[bool], standard: Standard) -> Option<Timecode> {
+        let value = |from: usize, len: usize| (0..len).fold(0u8, |value, k| value | (data[from + k] as u8) << k);
+        let digits = |units: usize, tens: usize, tens_len: usize, limit: u8| {
+            let (units, tens) = (value(units, 4), value(tens, tens_len));
+            if units > 9 || tens * 10 + units >= limit { None } else { Some(tens * 10 + units) }
+        };
+        let (field_mark, frame_rate) = match standard {
+            Standard::Ntsc => (27, 30),
+            Standard::Pal => (59, 25),
+        };
+        let user_bits = (0..8).fold(0u32, |bits, group| bits << 4 | value(group * 8 + 4, 4) as u32);
+        Some(Timecode {
+            hours: digits(48, 56, 2, 24)?,
+            minutes: digits(32, 40, 3, 60)?,
+            seconds: digits(16, 24, 3, 60)?,
+            frames: digits(0, 8, 2, frame_rate)?,
+            drop_frame: standard == Standard::Ntsc && data[10],
+            color_frame: data[11],
+            field_mark: data[field_mark],
+            user_bits,
+        })
+    }
+
+    pub fn to_json(self) -> Json {
+        Json::object(vec![
+            ("timecode", self.to_string().into()),
+            ("drop_frame", self.drop_frame.into()),
+            ("color_frame", self.color_frame.into()),
+            ("field_mark", self.field_mark.into()),
+            ("user_bits", format!("{:08x}", self.user_bits).into()),
+        ])
+    }
+}
+
+/// `HH:MM:SS:FF`, or `HH:MM:SS;FF` for drop frame.
+impl fmt::Display for Timecode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let separator = if self.drop_frame { ';' } else { ':' };
+        write!(f, "{:02}:{:02}:{:02}{}{:02}", self.hours, self.minutes, self.seconds, separator, self.frames)
+    }
+}
+
+/// The timecode read from one field.
+#[derive(Clone, Copy, Debug)]
+pub struct Reading {
+    pub line: vbi::Line,
+    pub timecode: Timecode,
+}
+
+/// Reads each field's timecode from the first of `lines` whose CRC checks
+/// out. Fields without one are left out. `signal` is the sync channel's
+/// levels.
+pub fn readings(signal: &[f32], lines: &[vbi::Line], line_samples: usize, standard: Standard) -> Vec<Reading> {
+    let mut readings: Vec<Reading> = vec![];
+    for line in lines {
+        if readings.last().map_or(false, |x| x.line.field == line.field) {
+            continue;
+        }
+        let bits = match slice(signal, line.start, line_samples, standard) {
+            Some(bits) if crc_ok(&bits) => bits,
+            _ => continue,
+        };
+        let data = bits.chunks(GROUP_BITS)
+            .take(DATA_BITS / 8)
+            .flat_map(|group| group[2..].iter().cloned())
+            .collect::<Vec<_>>();
+        if let Some(timecode) = Timecode::from_data(&data, standard) {
+            readings.push(Reading { line: *line, timecode });
+        }
+    }
+    readings
+}
+
+/// Each of `field_count` fields' timecode, if it had one.
+pub fn per_field(readings: &[Reading], field_count: usize) -> Vec<Option<Timecode>> {
+    let mut timecodes = vec![None; field_count];
+    for reading in readings {
+        if let Some(timecode) = timecodes.get_mut(reading.line.field) {
+            *timecode = Some(reading.timecode);
+        }
+    }
+    timecodes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::WHITE_LEVEL;
+
+    /// Writes the `len` low bits of `value` from `from`, least significant
+    /// first.
+    fn put(data: &mut [bool], from: usize, len: usize, value: u32) {
+        for k in 0..len {
+            data[from + k] = value >> k & 1 != 0;
+        }
+    }
+
+    /// The 90 bits of a line carrying `timecode`, sync bits and CRC
+    /// included.
+    fn encode(timecode: &Timecode, standard: Standard) -> Vec<bool> {
+        let mut data = vec![false; DATA_BITS];
+        let digits = [
+            (0, 8, timecode.frames),
+            (16, 24, timecode.seconds),
+            (32, 40, timecode.minutes),
+            (48, 56, timecode.hours),
+        ];
+        for &(units, tens, value) in digits.iter() {
+            put(&mut data, units, 4, value as u32 % 10);
+            put(&mut data, tens, 3, value as u32 / 10);
+        }
+        for group in 0..8 {
+            put(&mut data, group * 8 + 4, 4, timecode.user_bits >> (28 - group * 4) & 0xf);
+        }
+        data[10] = timecode.drop_frame;
+        data[11] = timecode.color_frame;
+        data[if standard == Standard::Ntsc { 27 } else { 59 }] = timecode.field_mark;
+
+        let mut bits = vec![];
+        for group in data.chunks(8) {
+            bits.extend_from_slice(&[true, false]);
+            bits.extend_from_slice(group);
+        }
+        bits.extend_from_slice(&[true, false]);
+        let mut crc = [false; 8];
+        for (n, bit) in bits.iter().enumerate() {
+            crc[n % 8] ^= *bit;
+        }
+        for n in bits.len()..BITS {
+            bits.push(crc[n % 8]);
+        }
+        bits
+    }
+
+    /// The sync channel's levels for a line carrying `bits` at the
+    /// standard's bit rate from 11µs, and where the line starts.
+    fn render(bits: &[bool], standard: Standard) -> (Vec<f32>, usize) {
+        let line_samples = standard.line_samples();
+        let bit = line_samples as f32 / bits_per_line(standard);
+        let (fall, sync) = (100, (4.7 * SAMPLE_RATE_MHZ) as usize);
+        let code = 11. * SAMPLE_RATE_MHZ;
+
+        let mut signal = vec![0.; fall + line_samples];
+        for (i, x) in signal[fall..].iter_mut().enumerate() {
+            let n = ((i as f32 - code) / bit).floor();
+            *x = if i < sync {
+                -56.
+            } else if n >= 0. && bits.get(n as usize) == Some(&true) {
+                WHITE_LEVEL * 0.8
+            } else {
+                0.
+            };
+        }
+        (signal, fall + sync)
+    }
+
+    /// The data bits of a line, without the sync bits and CRC.
+    fn data(bits: &[bool]) -> Vec<bool> {
+        bits.chunks(GROUP_BITS).take(DATA_BITS / 8).flat_map(|x| x[2..].to_vec()).collect()
+    }
+
+    fn timecode(standard: Standard) -> Timecode {
+        Timecode {
+            hours: 12,
+            minutes: 34,
+            seconds: 56,
+            frames: if standard == Standard::Ntsc { 29 } else { 24 },
+            drop_frame: standard == Standard::Ntsc,
+            color_frame: true,
+            field_mark: true,
+            user_bits: 0x1234_5678,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        for standard in [Standard::Ntsc, Standard::Pal].iter().cloned() {
+            let timecode = timecode(standard);
+            let (signal, start) = render(&encode(&timecode, standard), standard);
+            let bits = slice(&signal, start, standard.line_samples(), standard).unwrap();
+            assert_eq!(bits.len(), BITS);
+            assert!(crc_ok(&bits));
+            assert_eq!(Timecode::from_data(&data(&bits), standard), Some(timecode));
+        }
+    }
+
+    #[test]
+    fn crc_placement() {
+        let bits = encode(&timecode(Standard::Ntsc), Standard::Ntsc);
+        assert!(crc_ok(&bits));
+        // The last group's sync bits, then the CRC in bits 82 to 89.
+        assert_eq!(&bits[80..82], &[true, false]);
+        for n in (0..80).chain(82..BITS) {
+            let mut broken = bits.clone();
+            broken[n] = !broken[n];
+            assert!(!crc_ok(&broken), "bit {} flipped", n);
+        }
+    }
+
+    #[test]
+    fn flag_positions() {
+        // Line bit 14 is drop frame, only for 525 lines. The field mark is
+        // line bit 35 for 525 lines and 75 for 625.
+        for &(standard, drop_frame, field_mark) in [(Standard::Ntsc, true, 35), (Standard::Pal, false, 75)].iter() {
+            let mut bits = encode(&Timecode::from_data(&[false; DATA_BITS], standard).unwrap(), standard);
+            bits[14] = true;
+            let decoded = Timecode::from_data(&data(&bits), standard).unwrap();
+            assert_eq!((decoded.drop_frame, decoded.field_mark), (drop_frame, false));
+
+            let mut bits = encode(&Timecode::from_data(&[false; DATA_BITS], standard).unwrap(), standard);
+            bits[field_mark] = true;
+            let decoded = Timecode::from_data(&data(&bits), standard).unwrap();
+            assert_eq!((decoded.drop_frame, decoded.field_mark), (false, true));
+        }
+    }
+}
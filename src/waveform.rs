@@ -0,0 +1,233 @@
+use crate::capture::to_ire;
+use crate::decode::{SAMPLE_RATE_MHZ, SUBCARRIER_MHZ};
+use crate::font;
+use crate::frame::Frame;
+
+/// IRE range shown, from below sync tip to above peak white.
+pub const IRE_MIN: f32 = -50.;
+pub const IRE_MAX: f32 = 120.;
+
+/// Levels marked on the graticule, in IRE.
+pub const GRATICULE_IRE: [f32; 9] = [-40., -20., 0., 20., 40., 60., 80., 100., 120.];
+
+/// NTSC setup (black) level, drawn dashed.
+pub const SETUP_IRE: f32 = 7.5;
+
+/// Spacing of time ticks, in µs.
+pub const TICK_US: f32 = 10.;
+
+/// Time shown before each line's sync leading edge, in µs, so sync is whole.
+pub const LEAD_US: f32 = 2.;
+
+const MARGIN_LEFT: usize = 36;
+const MARGIN_RIGHT: usize = 8;
+const MARGIN_TOP: usize = 8;
+const MARGIN_BOTTOM: usize = 20;
+
+/// Smallest plot area worth drawing, in pixels each way.
+const MIN_PLOT: usize = 16;
+
+/// Smallest picture with room for the labels around the plot.
+pub const MIN_WIDTH: usize = MARGIN_LEFT + MARGIN_RIGHT + MIN_PLOT;
+pub const MIN_HEIGHT: usize = MARGIN_TOP + MARGIN_BOTTOM + MIN_PLOT;
+
+const GRATICULE_COLOR: [f32; 3] = [0.45, 0.35, 0.1];
+const LABEL_COLOR: [f32; 3] = [0.8, 0.65, 0.2];
+const TRACE_COLOR: [f32; 3] = [0.3, 1., 0.4];
+
+/// What the monitor shows of the signal.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    /// Low-passed to remove the color subcarrier.
+    Luma,
+    /// The signal as captured, chroma included.
+    Composite,
+}
+
+/// Low-passes a line with a moving average one subcarrier cycle long, which
+/// cancels the subcarrier while keeping sync and luma edges.
+pub fn luma(line: &[f32]) -> Vec<f32> {
+    let width = (SAMPLE_RATE_MHZ / SUBCARRIER_MHZ).round() as usize;
+    let half = width / 2;
+    (0..line.len()).map(|i| {
+        let from = i.saturating_sub(half);
+        let to = (from + width).min(line.len());
+        line[from..to].iter().sum::<f32>() / (to - from) as f32
+    }).collect()
+}
+
+/**
+ * Renders lines of signal onto an IRE graticule, like a broadcast waveform
+ * monitor. Each line starts `LEAD_US` before its sync leading edge and is
+ * drawn across the full width; overlaid lines add up, so the trace is
+ * brightest where most lines pass.
+ */
+pub struct Waveform {
+    pub width: usize,
+    pub height: usize,
+    pub mode: Mode,
+    /// Samples shown per line.
+    pub line_samples: usize,
+}
+
+impl Waveform {
+    pub fn new(width: usize, height: usize, mode: Mode, line_samples: usize) -> Waveform {
+        Waveform {
+            width,
+            height,
+            mode,
+            line_samples,
+        }
+    }
+
+    /// Samples before the sync leading edge each line should start at.
+    pub fn lead_samples() -> usize {
+        (LEAD_US * SAMPLE_RATE_MHZ) as usize
+    }
+
+    fn plot_width(&self) -> usize {
+        self.width.saturating_sub(MARGIN_LEFT + MARGIN_RIGHT).max(1)
+    }
+
+    fn plot_height(&self) -> usize {
+        self.height.saturating_sub(MARGIN_TOP + MARGIN_BOTTOM).max(1)
+    }
+
+    /// Row of an IRE level, which may fall outside the plot.
+    fn row(&self, ire: f32) -> isize {
+        let t = (IRE_MAX - ire) / (IRE_MAX - IRE_MIN);
+        MARGIN_TOP as isize + (t * (self.plot_height() - 1) as f32).round() as isize
+    }
+
+    fn column(&self, sample: usize) -> usize {
+        MARGIN_LEFT + sample * self.plot_width() / self.line_samples.max(1)
+    }
+
+    pub fn render(&self, lines: &[Vec<f32>]) -> Frame {
+        let mut frame = Frame::new(self.width, self.height);
+        self.draw_graticule(&mut frame);
+
+        // Count how many line segments cross each pixel.
+        let mut hits = vec![0u32; self.width * self.height];
+        let bottom = (MARGIN_TOP + self.plot_height() - 1) as isize;
+        for line in lines {
+            let filtered;
+            let line = match self.mode {
+                Mode::Luma => {
+                    filtered = luma(line);
+                    &filtered
+                }
+                Mode::Composite => line,
+            };
+            let mut previous: Option<isize> = None;
+            for (i, level) in line.iter().take(self.line_samples).enumerate() {
+                let x = self.column(i);
+                let y = num::clamp(self.row(to_ire(*level)), MARGIN_TOP as isize, bottom);
+                // Join to the previous sample so steep edges stay solid.
+                let (from, to) = match previous {
+                    Some(p) => (p.min(y), p.max(y)),
+                    None => (y, y),
+                };
+                for row in from..=to {
+                    hits[row as usize * self.width + x] += 1;
+                }
+                previous = Some(y);
+            }
+        }
+
+        let most = hits.iter().cloned().max().unwrap_or(0).max(1) as f32;
+        for (pixel, count) in frame.pixels.iter_mut().zip(&hits) {
+            if *count == 0 {
+                continue;
+            }
+            let v = (1. + *count as f32).ln() / (1. + most).ln();
+            let v = 0.25 + 0.75 * v;
+            for c in 0..3 {
+                pixel[c] = pixel[c].max(TRACE_COLOR[c] * v);
+            }
+        }
+        frame
+    }
+
+    fn draw_graticule(&self, frame: &mut Frame) {
+        let right = MARGIN_LEFT + self.plot_width();
+        let lead = Self::lead_samples();
+
+        for ire in GRATICULE_IRE.iter() {
+            let y = self.row(*ire) as usize;
+            for x in MARGIN_LEFT..right {
+                frame.set(x, y, GRATICULE_COLOR);
+            }
+            let label = format!("{}", *ire as i32);
+            let label_x = MARGIN_LEFT as isize - 4 - font::text_width(&label, 1) as isize;
+            font::draw_text(frame, label_x, y as isize - 2, &label, 1, LABEL_COLOR);
+        }
+        let setup = self.row(SETUP_IRE) as usize;
+        for x in (MARGIN_LEFT..right).filter(|x| (x / 4) % 2 == 0) {
+            frame.set(x, setup, GRATICULE_COLOR);
+        }
+
+        // Time ticks every `TICK_US` from the sync leading edge.
+        let top = MARGIN_TOP;
+        let bottom = MARGIN_TOP + self.plot_height() - 1;
+        let tick_samples = TICK_US * SAMPLE_RATE_MHZ;
+        let mut n = 0;
+        loop {
+            let sample = lead + (n as f32 * tick_samples) as usize;
+            if sample >= self.line_samples {
+                break;
+            }
+            let x = self.column(sample);
+            for y in top..=bottom {
+                if y % 3 == 0 || y < top + 4 || y > bottom - 4 {
+                    frame.set(x, y, GRATICULE_COLOR);
+                }
+            }
+            let label = format!("{}", (n as f32 * TICK_US) as i32);
+            let label_x = x as isize - font::text_width(&label, 1) as isize / 2;
+            font::draw_text(frame, label_x, bottom as isize + 5, &label, 1, LABEL_COLOR);
+            n += 1;
+        }
+        let units = "us";
+        font::draw_text(frame, right as isize - font::text_width(units, 1) as isize, bottom as isize + 12, units, 1, LABEL_COLOR);
+        font::draw_text(frame, 2, bottom as isize + 12, "IRE", 1, LABEL_COLOR);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn luma_cancels_subcarrier() {
+        let step = 2. * std::f32::consts::PI * SUBCARRIER_MHZ / SAMPLE_RATE_MHZ;
+        let line = (0..600)
+            .map(|i| if i < 300 { 0. } else { 50. + 20. * (i as f32 * step).sin() })
+            .collect::<Vec<_>>();
+        let filtered = luma(&line);
+        assert_eq!(filtered.len(), line.len());
+        // What's left of the subcarrier is a small fraction of it.
+        assert!(filtered[320..580].iter().all(|x| (x - 50.).abs() < 1.), "{:?}", &filtered[320..580]);
+        // The step stays within a subcarrier cycle of where it was.
+        assert!(filtered[..290].iter().all(|x| *x == 0.));
+        assert!(filtered[306..].iter().all(|x| *x > 40.));
+    }
+
+    #[test]
+    fn traces_levels() {
+        let waveform = Waveform::new(200, 128, Mode::Composite, 100);
+        // Rows run from `IRE_MAX` at the top of the plot to `IRE_MIN`.
+        assert_eq!(waveform.row(IRE_MAX), MARGIN_TOP as isize);
+        assert_eq!(waveform.row(IRE_MIN), (waveform.height - MARGIN_BOTTOM - 1) as isize);
+
+        // A flat line at blanking, then one at 100 IRE.
+        let blank = vec![0.; 100];
+        let white = vec![crate::capture::WHITE_LEVEL; 100];
+        let frame = waveform.render(&[blank, white]);
+        let x = waveform.column(50);
+        for &ire in &[0., 100.] {
+            assert_eq!(frame.get(x, waveform.row(ire) as usize)[1], 1., "{}", ire);
+        }
+        assert!(frame.get(x, waveform.row(50.) as usize)[1] < 0.5);
+    }
+}
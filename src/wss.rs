@@ -0,0 +1,307 @@
+use std::ops::{Range, RangeInclusive};
+use crate::decode::SAMPLE_RATE_MHZ;
+use crate::vbi::{self, Slicer};
+
+/// Line of 625-line fields carrying widescreen signalling.
+pub const LINE: usize = 23;
+
+/// Lines searched for it, as for captions.
+pub const SEARCH_LINES: RangeInclusive<usize> = LINE - 1..=LINE + 1;
+
+/// Element rate in MHz. Elements are 200ns, independent of the line rate.
+pub const ELEMENT_MHZ: f32 = 5.;
+
+/// Run-in, sent first, most significant element first.
+pub const RUN_IN: u32 = 0x1f1c71c7;
+pub const RUN_IN_ELEMENTS: usize = 29;
+
+/// Start code, following the run-in.
+pub const START_CODE: u32 = 0x1e3c1f;
+pub const START_CODE_ELEMENTS: usize = 24;
+
+/// Run-in elements that may be wrong, as the first few often are after a
+/// slow rising edge.
+pub const RUN_IN_ERRORS: usize = 3;
+
+/// Data bits, each biphase coded as six elements: `111000` for `1` and
+/// `000111` for `0`.
+pub const DATA_BITS: usize = 14;
+pub const ELEMENTS_PER_BIT: usize = 6;
+
+/// Where the run-in may start, in µs from the sync leading edge. It's
+/// nominally at 11µs.
+pub const START_US: Range<f32> = 10.0..12.5;
+
+/// Smallest swing, in IRE, taken to be WSS rather than a blank line. It's
+/// nominally 500mV, about 70.
+pub const MIN_SWING_IRE: f32 = 35.;
+
+/// Aspect ratio and framing of the picture, from bits 0 to 3.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    FullFormat4x3,
+    Letterbox14x9Centre,
+    Letterbox14x9Top,
+    Letterbox16x9Centre,
+    Letterbox16x9Top,
+    /// Letterboxed wider than 16:9, centred.
+    LetterboxWiderCentre,
+    /// 14:9 shot to be cropped to 4:3.
+    FullFormat14x9,
+    /// 16:9 squeezed horizontally into the full 4:3 picture.
+    Anamorphic16x9,
+}
+
+impl Format {
+    /// The format coded by bits 0 to 3, bit 0 lowest. Codes without odd
+    /// parity are errors.
+    pub fn from_bits(bits: u8) -> Option<Format> {
+        Some(match bits {
+            0b1000 => Format::FullFormat4x3,
+            0b0001 => Format::Letterbox14x9Centre,
+            0b0010 => Format::Letterbox14x9Top,
+            0b1011 => Format::Letterbox16x9Centre,
+            0b0100 => Format::Letterbox16x9Top,
+            0b1101 => Format::LetterboxWiderCentre,
+            0b1110 => Format::FullFormat14x9,
+            0b0111 => Format::Anamorphic16x9,
+            _ => return None,
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Format::FullFormat4x3 => "4:3",
+            Format::Letterbox14x9Centre => "14:9 letterbox centre",
+            Format::Letterbox14x9Top => "14:9 letterbox top",
+            Format::Letterbox16x9Centre => "16:9 letterbox centre",
+            Format::Letterbox16x9Top => "16:9 letterbox top",
+            Format::LetterboxWiderCentre => ">16:9 letterbox centre",
+            Format::FullFormat14x9 => "14:9 full format",
+            Format::Anamorphic16x9 => "16:9 anamorphic",
+        }
+    }
+
+    /// Width over height of the whole picture as it should be displayed.
+    /// Only anamorphic pictures are wider than 4:3; letterboxes are 4:3
+    /// pictures with bars.
+    pub fn display_aspect(&self) -> (u32, u32) {
+        match self {
+            Format::Anamorphic16x9 => (16, 9),
+            _ => (4, 3),
+        }
+    }
+}
+
+/// Open subtitles, from bits 9 and 10.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Subtitles {
+    None,
+    InsideActivePicture,
+    OutsideActivePicture,
+    Reserved,
+}
+
+/// Widescreen signalling from one field.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Wss {
+    pub format: Format,
+    /// Film mode rather than camera mode.
+    pub film: bool,
+    /// Motion Adaptive Colour Plus rather than standard coding.
+    pub colour_plus: bool,
+    /// A vertical helper signal is modulated in the bars.
+    pub helper: bool,
+    /// Subtitles are carried in teletext.
+    pub teletext_subtitles: bool,
+    pub open_subtitles: Subtitles,
+    pub surround: bool,
+    pub copyright: bool,
+    /// Copying is restricted.
+    pub copy_restricted: bool,
+}
+
+impl Wss {
+    /// Decodes the 14 data bits, bit 0 first.
+    pub fn from_bits(bits: &[bool]) -> Option<Wss> {
+        let format = (0..4).fold(0u8, |value, k| value | (bits[k] as u8) << k);
+        Some(Wss {
+            format: Format::from_bits(format)?,
+            film: bits[4],
+            colour_plus: bits[5],
+            helper: bits[6],
+            teletext_subtitles: bits[8],
+            open_subtitles: match (bits[9], bits[10]) {
+                (false, false) => Subtitles::None,
+                (true, false) => Subtitles::InsideActivePicture,
+                (false, true) => Subtitles::OutsideActivePicture,
+                (true, true) => Subtitles::Reserved,
+            },
+            surround: bits[11],
+            copyright: bits[12],
+            copy_restricted: bits[13],
+        })
+    }
+}
+
+/// Whether `elements` spell out `code`, sent most significant element
+/// first, but for up to `errors` of them.
+fn matches(elements: &[bool], code: u32, errors: usize) -> bool {
+    let len = elements.len();
+    elements.iter().enumerate().filter(|(n, x)| **x != ((code >> (len - 1 - n)) & 1 == 1)).count() <= errors
+}
+
+/**
+ * Reads WSS from the line starting at `start` (the end of its sync). The
+ * line is sliced into elements from the run-in's leading edge, and the
+ * run-in and start code must be there. Returns None if they aren't, or if
+ * a data bit isn't valid biphase or the format fails its parity.
+ */
+pub fn read(signal: &[f32], start: usize, line_samples: usize) -> Option<Wss> {
+    let (_, line) = vbi::raw_line(signal, start, line_samples);
+    let element = SAMPLE_RATE_MHZ / ELEMENT_MHZ;
+    let at = |us: f32| (us * SAMPLE_RATE_MHZ) as usize;
+    let count = RUN_IN_ELEMENTS + START_CODE_ELEMENTS + DATA_BITS * ELEMENTS_PER_BIT;
+
+    let slicer = Slicer::new(&line, at(START_US.start)..at(START_US.end) + (count as f32 * element) as usize, element, MIN_SWING_IRE)?;
+    let edge = slicer.rising_edge(&line, at(START_US.start)..at(START_US.end))?;
+    let elements = slicer.bits(&line, edge, count)?;
+
+    let (run_in, rest) = elements.split_at(RUN_IN_ELEMENTS);
+    let (start_code, data) = rest.split_at(START_CODE_ELEMENTS);
+    if !matches(run_in, RUN_IN, RUN_IN_ERRORS) || !matches(start_code, START_CODE, 0) {
+        return None;
+    }
+    let bits = data.chunks(ELEMENTS_PER_BIT)
+        .map(|bit| {
+            let first = bit[..3].iter().filter(|x| **x).count() >= 2;
+            let second = bit[3..].iter().filter(|x| **x).count() >= 2;
+            if first == second { None } else { Some(first) }
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Wss::from_bits(&bits)
+}
+
+/// The WSS read from one field.
+#[derive(Clone, Copy, Debug)]
+pub struct Reading {
+    pub line: vbi::Line,
+    pub wss: Wss,
+}
+
+/// Reads each field's line 23, or failing that the nearest of `lines`
+/// with WSS, see `SEARCH_LINES`. Fields without it are left out. `signal`
+/// is the sync channel's levels.
+pub fn readings(signal: &[f32], lines: &[vbi::Line], line_samples: usize) -> Vec<Reading> {
+    let mut readings: Vec<Reading> = vec![];
+    let mut lines = lines.to_vec();
+    lines.sort_by_key(|x| (x.field, (x.number as isize - LINE as isize).abs()));
+    for line in lines {
+        if readings.last().map_or(false, |x| x.line.field == line.field) {
+            continue;
+        }
+        if let Some(wss) = read(signal, line.start, line_samples) {
+            readings.push(Reading { line, wss });
+        }
+    }
+    readings
+}
+
+/// Each of `field_count` fields' WSS, if it had it.
+pub fn per_field(readings: &[Reading], field_count: usize) -> Vec<Option<Wss>> {
+    let mut wss = vec![None; field_count];
+    for reading in readings {
+        if let Some(field) = wss.get_mut(reading.line.field) {
+            *field = Some(reading.wss);
+        }
+    }
+    wss
+}
+
+/// The format signalled on the most fields, if any.
+pub fn common_format(wss: &[Option<Wss>]) -> Option<Format> {
+    let mut counts: Vec<(Format, usize)> = vec![];
+    for format in wss.iter().flatten().map(|x| x.format) {
+        match counts.iter_mut().find(|x| x.0 == format) {
+            Some(count) => count.1 += 1,
+            None => counts.push((format, 1)),
+        }
+    }
+    counts.iter().max_by_key(|x| x.1).map(|x| x.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::WHITE_LEVEL;
+    use crate::standard::Standard;
+
+    /// Elements of `code`, most significant first.
+    fn code(code: u32, len: usize) -> Vec<bool> {
+        (0..len).map(|n| (code >> (len - 1 - n)) & 1 == 1).collect()
+    }
+
+    /// The sync channel's levels for line 23 carrying `bits` from 11µs, and
+    /// where the line starts.
+    fn render(bits: &[bool]) -> (Vec<f32>, usize) {
+        let mut elements = code(RUN_IN, RUN_IN_ELEMENTS);
+        elements.extend(code(START_CODE, START_CODE_ELEMENTS));
+        for bit in bits {
+            elements.extend(if *bit { [true, true, true, false, false, false] } else { [false, false, false, true, true, true] }.iter());
+        }
+
+        let line_samples = Standard::Pal.line_samples();
+        let element = SAMPLE_RATE_MHZ / ELEMENT_MHZ;
+        let (fall, sync) = (100, (4.7 * SAMPLE_RATE_MHZ) as usize);
+        let run_in = 11. * SAMPLE_RATE_MHZ;
+        let mut signal = vec![0.; fall + line_samples];
+        for (i, x) in signal[fall..].iter_mut().enumerate() {
+            let n = ((i as f32 - run_in) / element).floor();
+            *x = if i < sync {
+                -56.
+            } else if n >= 0. && elements.get(n as usize) == Some(&true) {
+                // 500mV.
+                WHITE_LEVEL * 0.7
+            } else {
+                0.
+            };
+        }
+        (signal, fall + sync)
+    }
+
+    /// Data bits for `format`, subtitles in teletext and copyright.
+    fn bits(format: u8) -> Vec<bool> {
+        let mut bits = (0..4).map(|k| format >> k & 1 == 1).collect::<Vec<_>>();
+        bits.extend_from_slice(&[false; DATA_BITS - 4]);
+        bits[8] = true;
+        bits[12] = true;
+        bits
+    }
+
+    #[test]
+    fn reads_line() {
+        let (signal, start) = render(&bits(0b0111));
+        let wss = read(&signal, start, Standard::Pal.line_samples()).unwrap();
+        assert_eq!(wss.format, Format::Anamorphic16x9);
+        assert_eq!(wss.format.display_aspect(), (16, 9));
+        assert!(wss.teletext_subtitles && wss.copyright);
+        assert!(!wss.film && !wss.surround && !wss.copy_restricted);
+        assert_eq!(wss.open_subtitles, Subtitles::None);
+
+        let (signal, start) = render(&bits(0b1000));
+        assert_eq!(read(&signal, start, Standard::Pal.line_samples()).unwrap().format, Format::FullFormat4x3);
+    }
+
+    #[test]
+    fn rejects_bad_lines() {
+        let line_samples = Standard::Pal.line_samples();
+        // Even parity in the format.
+        let (signal, start) = render(&bits(0b0011));
+        assert_eq!(read(&signal, start, line_samples), None);
+
+        // A blank line, with only its sync left.
+        let (signal, start) = render(&[]);
+        let blank = signal.iter().map(|x| x.min(0.)).collect::<Vec<_>>();
+        assert_eq!(read(&blank, start, line_samples), None);
+    }
+}
@@ -0,0 +1,319 @@
+use crate::caption::{self, parity_ok};
+use crate::json::Json;
+
+/// Code ending a packet, followed by its checksum.
+pub const END: u8 = 0x0f;
+
+/// Class of an XDS packet, from its start code.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Class {
+    /// About the program being shown.
+    Current,
+    /// About a program to come.
+    Future,
+    /// About the channel.
+    Channel,
+    Miscellaneous,
+    PublicService,
+    Reserved,
+    Private,
+}
+
+impl Class {
+    /// The class a start or continue code, `0x01` to `0x0e`, belongs to.
+    fn from_code(code: u8) -> Class {
+        match (code - 1) / 2 {
+            0 => Class::Current,
+            1 => Class::Future,
+            2 => Class::Channel,
+            3 => Class::Miscellaneous,
+            4 => Class::PublicService,
+            5 => Class::Reserved,
+            _ => Class::Private,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Class::Current => "current",
+            Class::Future => "future",
+            Class::Channel => "channel",
+            Class::Miscellaneous => "miscellaneous",
+            Class::PublicService => "public_service",
+            Class::Reserved => "reserved",
+            Class::Private => "private",
+        }
+    }
+}
+
+/// A complete XDS packet.
+#[derive(Clone, Debug)]
+pub struct Packet {
+    pub class: Class,
+    /// Type within the class.
+    pub kind: u8,
+    /// Informational characters, without parity.
+    pub data: Vec<u8>,
+    /// Whether the checksum matched and every byte had odd parity.
+    pub valid: bool,
+    /// Seconds from the start of the capture to the line the packet ended
+    /// on.
+    pub time: f64,
+}
+
+/// A packet still being received.
+struct Partial {
+    class: Class,
+    kind: u8,
+    /// Every byte counted in the checksum so far.
+    bytes: Vec<u8>,
+    parity_ok: bool,
+}
+
+/**
+ * Assembles XDS packets from line 21 of the second field of each frame. A
+ * packet runs from its start code and type to its end code and checksum,
+ * which makes the sum of those bytes and the informational characters
+ * between them a multiple of 128. Captions may interrupt a packet, as may
+ * packets of other classes, and it picks up again at its continue code,
+ * which isn't counted in the checksum. Packets that never end are dropped.
+ */
+pub fn packets(captions: &[caption::Packet]) -> Vec<Packet> {
+    let mut partials: Vec<Partial> = vec![];
+    let mut current: Option<Class> = None;
+    let mut packets = vec![];
+    for packet in captions.iter().filter(|x| x.line.second == Some(true)) {
+        let (a, b) = (packet.bytes[0] & 0x7f, packet.bytes[1] & 0x7f);
+        let parity = parity_ok(packet.bytes[0]) && parity_ok(packet.bytes[1]);
+        match a {
+            0x01..=0x0e if a % 2 == 1 => {
+                let class = Class::from_code(a);
+                partials.retain(|x| x.class != class);
+                partials.push(Partial { class, kind: b, bytes: vec![a, b], parity_ok: parity });
+                current = Some(class);
+            }
+            0x01..=0x0e => {
+                let class = Class::from_code(a);
+                current = partials.iter().find(|x| x.class == class && x.kind == b).map(|x| x.class);
+            }
+            END => {
+                let index = current.take().and_then(|class| partials.iter().position(|x| x.class == class));
+                if let Some(index) = index {
+                    let partial = partials.remove(index);
+                    let sum = partial.bytes.iter().map(|x| *x as u32).sum::<u32>() + a as u32 + b as u32;
+                    packets.push(Packet {
+                        class: partial.class,
+                        kind: partial.kind,
+                        data: partial.bytes[2..].to_vec(),
+                        valid: partial.parity_ok && parity && sum % 128 == 0,
+                        time: packet.line.time(),
+                    });
+                }
+            }
+            0x10..=0x1f => current = None,
+            _ => {
+                if let Some(partial) = partials.iter_mut().find(|x| Some(x.class) == current) {
+                    // An odd number of characters is padded with a null.
+                    partial.bytes.extend([a, b].iter().filter(|x| **x != 0));
+                    partial.parity_ok &= parity;
+                }
+            }
+        }
+    }
+    packets
+}
+
+/// Basic-set text, as in program and network names.
+fn text(data: &[u8]) -> Json {
+    data.iter().map(|x| caption::basic(*x)).collect::<String>().trim().into()
+}
+
+/// Low `bits` bits of `data[index]`, or None if the packet is too short.
+fn field(data: &[u8], index: usize, bits: u32) -> Option<u8> {
+    data.get(index).map(|x| x & ((1 << bits) - 1))
+}
+
+/// Whether bit `bit` of `data[index]` is set.
+fn flag(data: &[u8], index: usize, bit: u32) -> bool {
+    data.get(index).map_or(false, |x| x & (1 << bit) != 0)
+}
+
+/// A content advisory rating as `TV-PG-DV`, `PG-13`, `C8+` or `13 ans+`.
+fn rating(data: &[u8]) -> Option<String> {
+    let system = (field(data, 0, 5)? >> 3) & 0b11;
+    let level = field(data, 1, 3)? as usize;
+    let rating = match system {
+        0b01 => {
+            let name = ["None", "TV-Y", "TV-Y7", "TV-G", "TV-PG", "TV-14", "TV-MA", "None"][level];
+            let fantasy = level == 2;
+            let flags = [
+                (flag(data, 0, 5), "D"),
+                (flag(data, 1, 3), "L"),
+                (flag(data, 1, 4), "S"),
+                (flag(data, 1, 5), if fantasy { "FV" } else { "V" }),
+            ];
+            let flags = flags.iter().filter(|x| x.0).map(|x| x.1).collect::<String>();
+            if flags.is_empty() { name.to_string() } else { format!("{}-{}", name, flags) }
+        }
+        0b11 if flag(data, 1, 3) => ["E", "G", "8 ans+", "13 ans+", "16 ans+", "18 ans+", "None", "None"][level].to_string(),
+        0b11 => ["E", "C", "C8+", "G", "PG", "14+", "18+", "None"][level].to_string(),
+        _ => ["N/A", "G", "PG", "PG-13", "R", "NC-17", "X", "Not Rated"][field(data, 0, 3)? as usize].to_string(),
+    };
+    Some(rating)
+}
+
+impl Packet {
+    /// Name of the packet's type, for those that are decoded.
+    pub fn type_name(&self) -> Option<&'static str> {
+        Some(match (self.class, self.kind) {
+            (Class::Current, 0x01) | (Class::Future, 0x01) => "program_start",
+            (Class::Current, 0x02) | (Class::Future, 0x02) => "length",
+            (Class::Current, 0x03) | (Class::Future, 0x03) => "program_name",
+            (Class::Current, 0x04) | (Class::Future, 0x04) => "program_type",
+            (Class::Current, 0x05) | (Class::Future, 0x05) => "content_advisory",
+            (Class::Channel, 0x01) => "network_name",
+            (Class::Channel, 0x02) => "call_letters",
+            (Class::Channel, 0x04) => "tsid",
+            (Class::Miscellaneous, 0x01) => "time_of_day",
+            (Class::Miscellaneous, 0x04) => "time_zone",
+            _ => return None,
+        })
+    }
+
+    /// The packet's content, where its type is one that's decoded and it's
+    /// long enough. Times are UTC.
+    fn decode(&self) -> Option<Json> {
+        let data = &self.data;
+        Some(match self.type_name()? {
+            "program_start" => format!(
+                "{:02}-{:02}T{:02}:{:02}Z",
+                field(data, 3, 4)?, field(data, 2, 5)?, field(data, 1, 5)?, field(data, 0, 6)?,
+            ).into(),
+            "length" => {
+                let minutes = field(data, 1, 6)? as usize * 60 + field(data, 0, 6)? as usize;
+                let mut value = Json::object(vec![("minutes", minutes.into())]);
+                if let (Some(minute), Some(hour)) = (field(data, 2, 6), field(data, 3, 6)) {
+                    let seconds = field(data, 4, 6).unwrap_or(0) as usize;
+                    value.insert("elapsed_seconds", ((hour as usize * 60 + minute as usize) * 60 + seconds).into());
+                }
+                value
+            }
+            "program_name" | "network_name" | "call_letters" => text(data),
+            "program_type" => data.iter().map(|x| *x as usize).collect::<Vec<_>>().into(),
+            "content_advisory" => rating(data)?.into(),
+            "tsid" => data.get(..4)?.iter().fold(0usize, |tsid, x| tsid << 4 | (x & 0x0f) as usize).into(),
+            "time_of_day" => format!(
+                "{}-{:02}-{:02}T{:02}:{:02}Z",
+                1990 + field(data, 5, 6)? as usize, field(data, 3, 4)?, field(data, 2, 5)?, field(data, 1, 5)?, field(data, 0, 6)?,
+            ).into(),
+            "time_zone" => Json::object(vec![
+                ("hours_behind_utc", (field(data, 0, 5)? as usize).into()),
+                ("daylight_saving", flag(data, 0, 5).into()),
+            ]),
+            _ => return None,
+        })
+    }
+
+    /// The packet's content, decoded where possible and as hex otherwise.
+    pub fn value(&self) -> Json {
+        self.decode().unwrap_or_else(|| self.data.iter().map(|x| format!("{:02x}", x)).collect::<String>().into())
+    }
+
+    pub fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("time", self.time.into()),
+            ("class", self.class.name().into()),
+            ("type", self.type_name().map_or_else(|| format!("0x{:02x}", self.kind), |x| x.to_string()).into()),
+            ("value", self.value()),
+        ])
+    }
+}
+
+/// Summarizes packets for reports: how many there were, how many failed
+/// their checksum, and each distinct valid packet in the order first seen.
+pub fn report(packets: &[Packet]) -> Json {
+    let mut seen: Vec<&Packet> = vec![];
+    for packet in packets.iter().filter(|x| x.valid) {
+        if !seen.iter().any(|x| x.class == packet.class && x.kind == packet.kind && x.data == packet.data) {
+            seen.push(packet);
+        }
+    }
+    Json::object(vec![
+        ("packets", packets.len().into()),
+        ("checksum_errors", packets.iter().filter(|x| !x.valid).count().into()),
+        ("metadata", Json::Array(seen.iter().map(|x| x.to_json()).collect())),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vbi;
+
+    fn odd(byte: u8) -> u8 {
+        if parity_ok(byte) { byte } else { byte | 0x80 }
+    }
+
+    /// Line 21 packets of the second field of successive frames.
+    fn captions(pairs: &[[u8; 2]]) -> Vec<caption::Packet> {
+        pairs.iter()
+            .enumerate()
+            .map(|(n, pair)| caption::Packet {
+                line: vbi::Line {
+                    field: n * 2 + 1,
+                    number: caption::LINE,
+                    second: Some(true),
+                    start: n * 1000,
+                },
+                bytes: [odd(pair[0]), odd(pair[1])],
+            })
+            .collect()
+    }
+
+    /// The checksum byte ending a packet of `bytes`.
+    fn checksum(bytes: &[u8]) -> u8 {
+        let sum = bytes.iter().map(|x| *x as u32).sum::<u32>() + END as u32;
+        ((128 - sum % 128) % 128) as u8
+    }
+
+    #[test]
+    fn assembles_packets() {
+        let name = checksum(&[0x01, 0x03, b'A', b'B', b'C', b'D', b'E']);
+        let letters = checksum(&[0x05, 0x02, b'K', b'Q', b'E', b'D']);
+        let captions = captions(&[
+            [0x01, 0x03],
+            [b'A', b'B'],
+            // A caption control code, then the packet continues.
+            [0x14, 0x2c],
+            [0x02, 0x03],
+            [b'C', b'D'],
+            [b'E', 0],
+            [END, name],
+            [0x05, 0x02],
+            [b'K', b'Q'],
+            [b'E', b'D'],
+            [END, letters ^ 1],
+        ]);
+        let packets = packets(&captions);
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0].class, Class::Current);
+        assert_eq!(packets[0].data, b"ABCDE");
+        assert!(packets[0].valid);
+        assert_eq!(packets[0].value(), Json::from("ABCDE"));
+        assert_eq!(packets[1].type_name(), Some("call_letters"));
+        assert!(!packets[1].valid);
+
+        let report = report(&packets).to_string();
+        assert!(report.contains("\"packets\": 2,\n  \"checksum_errors\": 1,"), "{}", report);
+        assert!(report.contains("\"type\": \"program_name\",\n      \"value\": \"ABCDE\""), "{}", report);
+    }
+
+    #[test]
+    fn decodes_ratings() {
+        assert_eq!(rating(&[0x48 | 0x20, 0x40 | 0x20 | 4]), Some("TV-PG-DV".to_string()));
+        assert_eq!(rating(&[0x40 | 3, 0x40]), Some("PG-13".to_string()));
+        assert_eq!(rating(&[0x58, 0x40 | 2]), Some("C8+".to_string()));
+        assert_eq!(rating(&[0x58, 0x48 | 3]), Some("13 ans+".to_string()));
+        assert_eq!(rating(&[0x48]), None);
+    }
+}
@@ -0,0 +1,120 @@
+use std::io::{self, Write};
+use crate::frame::{Frame, rgb_to_ycbcr};
+
+/// How pictures in the stream relate to fields, for the `I` header tag.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interlace {
+    Progressive,
+    TopFieldFirst,
+    BottomFieldFirst,
+}
+
+impl Interlace {
+    fn tag(&self) -> char {
+        match self {
+            Interlace::Progressive => 'p',
+            Interlace::TopFieldFirst => 't',
+            Interlace::BottomFieldFirst => 'b',
+        }
+    }
+}
+
+/**
+ * Streams frames as YUV4MPEG2 with 4:4:4 studio-range Y'CbCr, which ffmpeg
+ * and most players read directly, e.g. `cargo run -- y4m - | ffplay -`.
+ */
+pub struct Y4mWriter<W: Write> {
+    out: W,
+    width: usize,
+    height: usize,
+}
+
+impl<W: Write> Y4mWriter<W> {
+    /// Writes the stream header. `rate` is in pictures per second and
+    /// `aspect` is the pixel aspect ratio.
+    pub fn new(
+        mut out: W,
+        width: usize,
+        height: usize,
+        rate: (u32, u32),
+        interlace: Interlace,
+        aspect: (u32, u32),
+    ) -> io::Result<Y4mWriter<W>> {
+        writeln!(
+            out,
+            "YUV4MPEG2 W{} H{} F{}:{} I{} A{}:{} C444",
+            width, height, rate.0, rate.1, interlace.tag(), aspect.0, aspect.1,
+        )?;
+        Ok(Y4mWriter { out, width, height })
+    }
+
+    pub fn write_frame(&mut self, frame: &Frame) -> io::Result<()> {
+        self.write_frame_with(frame, &[])
+    }
+
+    /// Writes a frame with parameters in its header, e.g. `XVITC=...`.
+    pub fn write_frame_with(&mut self, frame: &Frame, params: &[String]) -> io::Result<()> {
+        assert_eq!((frame.width, frame.height), (self.width, self.height),
+            "frame size changed mid-stream");
+
+        let ycbcr = frame.pixels.iter().map(|p| rgb_to_ycbcr(*p)).collect::<Vec<_>>();
+        let mut data = Vec::with_capacity(ycbcr.len() * 3);
+        for plane in 0..3 {
+            data.extend(ycbcr.iter().map(|p| p[plane]));
+        }
+
+        self.out.write_all(b"FRAME")?;
+        for param in params {
+            write!(self.out, " {}", param)?;
+        }
+        self.out.write_all(b"\n")?;
+        self.out.write_all(&data)
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_and_frames() {
+        let mut frame = Frame::new(2, 1);
+        frame.set(0, 0, [1., 1., 1.]);
+        frame.set(1, 0, [1., 0., 0.]);
+
+        let mut writer = Y4mWriter::new(vec![], 2, 1, (60000, 1001), Interlace::Progressive, (10, 11)).unwrap();
+        writer.write_frame(&frame).unwrap();
+        writer.write_frame_with(&Frame::new(2, 1), &["XVITC=01:00:00:00".to_string()]).unwrap();
+        let out = writer.finish().unwrap();
+
+        let mut expected = b"YUV4MPEG2 W2 H1 F60000:1001 Ip A10:11 C444\n".to_vec();
+        // Planes of Y', Cb then Cr, white then red.
+        expected.extend(b"FRAME\n");
+        expected.extend(&[235, 81, 128, 90, 128, 240]);
+        expected.extend(b"FRAME XVITC=01:00:00:00\n");
+        expected.extend(&[16, 16, 128, 128, 128, 128]);
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    #[should_panic(expected = "frame size changed")]
+    fn rejects_size_change() {
+        let mut writer = Y4mWriter::new(vec![], 2, 1, (50, 1), Interlace::BottomFieldFirst, (1, 1)).unwrap();
+        writer.write_frame(&Frame::new(1, 2)).unwrap();
+    }
+
+    #[test]
+    fn interlace_tags() {
+        let header = |interlace| {
+            let writer = Y4mWriter::new(vec![], 4, 2, (30000, 1001), interlace, (8, 9)).unwrap();
+            String::from_utf8(writer.finish().unwrap()).unwrap()
+        };
+        assert_eq!(header(Interlace::TopFieldFirst), "YUV4MPEG2 W4 H2 F30000:1001 It A8:9 C444\n");
+        assert_eq!(header(Interlace::BottomFieldFirst), "YUV4MPEG2 W4 H2 F30000:1001 Ib A8:9 C444\n");
+    }
+}